@@ -0,0 +1,187 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+use chrono::{DateTime, Utc};
+use quanta::Instant;
+use serde::{Deserialize, Serialize};
+
+use crate::config::AccuracyConfig;
+/// 每十倍划分的桶数；桶边界从 1µs 到 10s 按对数等分。
+const BINS_PER_DECADE: i32 = 10;
+const DECADES: i32 = 7;
+const MIN_EDGE_SECS: f64 = 1e-6;
+#[allow(clippy::cast_sign_loss)]
+const BIN_COUNT: usize = (BINS_PER_DECADE * DECADES) as usize + 2;
+/// 已达到精度的分位数（秒），取所在桶的上边界，即 “误差不超过该值” 的保守估计。
+#[derive(Copy, Clone, Debug)]
+pub struct AccuracySummary {
+    pub samples: u64,
+    pub p50_secs: f64,
+    pub p95_secs: f64,
+    pub p99_secs: f64,
+}
+#[derive(Serialize, Deserialize)]
+struct PersistedHistogram {
+    counts: Vec<u64>,
+}
+/// 某服务器最近一次测量所隐含的服务器时间，及测量时的单调时刻。
+struct Reference {
+    server_utc: DateTime<Utc>,
+    at: Instant,
+}
+/// 多个服务器在时间窗口内相互吻合时，以它们的中位数作为事后的 “真实” 时间，
+/// 记录此刻程序时钟与之的误差，累积为持久化的对数直方图，用于长期的精度自评。
+pub struct AccuracyTracker {
+    config: AccuracyConfig,
+    references: BTreeMap<String, Reference>,
+    counts: Vec<u64>,
+}
+impl AccuracyTracker {
+    /// 读取已保存的直方图继续累积；文件不存在时从空直方图开始，桶数不符时视为损坏并报错。
+    pub fn open(config: AccuracyConfig) -> io::Result<Self> {
+        let counts = match fs::read_to_string(&config.path) {
+            Ok(content) => {
+                let persisted: PersistedHistogram =
+                    serde_json::from_str(&content).map_err(|e| {
+                        io::Error::new(
+                            ErrorKind::InvalidData,
+                            format!("{} 解析失败: {e}", config.path),
+                        )
+                    })?;
+                if persisted.counts.len() != BIN_COUNT {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!(
+                            "{} 中的直方图有 {} 个桶，应为 {BIN_COUNT}",
+                            config.path,
+                            persisted.counts.len()
+                        ),
+                    ));
+                }
+                persisted.counts
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => vec![0; BIN_COUNT],
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            config,
+            references: BTreeMap::new(),
+            counts,
+        })
+    }
+
+    /// 记录 `server` 的测量：`clock_before` 为施加本次校正之前的时钟读数，
+    /// 加上测得的偏移即为测量时刻的服务器时间。
+    pub fn record_reference(
+        &mut self,
+        server: &str,
+        clock_before: DateTime<Utc>,
+        measured_offset_secs: f64,
+    ) {
+        self.references.insert(
+            server.to_owned(),
+            Reference {
+                server_utc: clock_before + secs_to_chrono(measured_offset_secs),
+                at: Instant::now(),
+            },
+        );
+    }
+
+    /// 窗口内至少 `min_servers` 个服务器相互吻合时，以校正后的时钟读数评估一次误差，
+    /// 计入直方图并保存，返回误差（秒，时钟减共识）。
+    pub fn assess(&mut self, clock_now: DateTime<Utc>) -> io::Result<Option<f64>> {
+        let now = Instant::now();
+        let window = self.config.window();
+        self.references
+            .retain(|_, reference| now.saturating_duration_since(reference.at) <= window);
+        if self.references.len() < self.config.min_servers {
+            return Ok(None);
+        }
+        // 单调时钟推进的时间同样推进了服务器时间，把各参考投影到当前时刻后与时钟比较。
+        let mut server_minus_clock: Vec<f64> = self
+            .references
+            .values()
+            .map(|reference| {
+                let elapsed = now.saturating_duration_since(reference.at);
+                (reference.server_utc + chrono::Duration::from_std(elapsed).unwrap() - clock_now)
+                    .as_seconds_f64()
+            })
+            .collect();
+        server_minus_clock.sort_by(f64::total_cmp);
+        let spread = server_minus_clock[server_minus_clock.len() - 1] - server_minus_clock[0];
+        if spread > self.config.agreement_millis / 1000.0 {
+            return Ok(None);
+        }
+        let middle =
+            &server_minus_clock[(server_minus_clock.len() - 1) / 2..=server_minus_clock.len() / 2];
+        #[allow(clippy::cast_precision_loss)]
+        let consensus = middle.iter().sum::<f64>() / middle.len() as f64;
+        let error = -consensus;
+        self.counts[bin_index(error.abs())] += 1;
+        self.save()?;
+        Ok(Some(error))
+    }
+
+    pub fn summary(&self) -> Option<AccuracySummary> {
+        let samples = self.counts.iter().sum::<u64>();
+        (samples > 0).then(|| AccuracySummary {
+            samples,
+            p50_secs: self.percentile(samples, 0.50),
+            p95_secs: self.percentile(samples, 0.95),
+            p99_secs: self.percentile(samples, 0.99),
+        })
+    }
+
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    fn percentile(&self, samples: u64, quantile: f64) -> f64 {
+        let rank = ((samples as f64) * quantile).ceil().max(1.0) as u64;
+        let mut cumulative = 0;
+        for (index, count) in self.counts.iter().enumerate() {
+            cumulative += count;
+            if cumulative >= rank {
+                return upper_edge(index);
+            }
+        }
+        upper_edge(BIN_COUNT - 1)
+    }
+
+    /// 先写临时文件再重命名，避免进程在写入中途退出留下不完整的直方图。
+    fn save(&self) -> io::Result<()> {
+        let bytes = serde_json::to_vec(&PersistedHistogram {
+            counts: self.counts.clone(),
+        })
+        .map_err(io::Error::other)?;
+        let path = Path::new(&self.config.path);
+        let temp_path = path.with_extension("tmp");
+        fs::write(&temp_path, bytes)?;
+        fs::rename(temp_path, path)
+    }
+}
+/// 桶 0 为小于 1µs 的误差，最后一个桶为不小于 10s 的误差，其余按对数等分。
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn bin_index(error_secs: f64) -> usize {
+    if error_secs < MIN_EDGE_SECS {
+        return 0;
+    }
+    let position = (error_secs / MIN_EDGE_SECS).log10() * f64::from(BINS_PER_DECADE);
+    (position.floor() as usize + 1).min(BIN_COUNT - 1)
+}
+/// 桶的上边界（秒）；最后一个桶没有上界，以其下边界代替。
+#[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+fn upper_edge(index: usize) -> f64 {
+    let exponent = f64::from(index.min(BIN_COUNT - 2) as i32) / f64::from(BINS_PER_DECADE);
+    MIN_EDGE_SECS * 10f64.powf(exponent)
+}
+fn secs_to_chrono(secs: f64) -> chrono::Duration {
+    let magnitude = chrono::Duration::from_std(std::time::Duration::from_secs_f64(secs.abs()))
+        .unwrap_or(chrono::Duration::MAX);
+    if secs < 0.0 { -magnitude } else { magnitude }
+}
@@ -1,25 +1,32 @@
 use std::{
+    fmt::Display,
     io::{self, Write},
     sync::{
         Arc, Mutex,
         atomic::{AtomicBool, Ordering},
-        mpsc,
     },
     thread,
     time::Duration,
 };
 
-use chrono::{DateTime, Local};
+use chrono::{DateTime, DurationRound, Utc};
 use crossterm::{cursor, execute, style::Print, terminal};
-use rand::Rng;
+use quanta::Instant;
+use serde::Serialize;
 
 use crate::{
-    config::{AppConfig, NtpConfig, UiConfig},
-    kalman_filter::KalmanFilter,
-    ntp,
+    cli::OnceFormat,
+    config::{AppConfig, HttpFallbackConfig, NtpConfig, UiConfig, UiMode},
+    dashboard::{Dashboard, DashboardAction},
+    demo,
+    display::{self, DriftSmoother, OffsetTrend},
+    ntp, ntp_control,
     program_clock::ProgramClock,
+    startup_state,
+    synchronizer::{SyncEvent, Synchronizer},
+    timescale::TimeDisplay,
 };
-pub fn run(config: &AppConfig) -> io::Result<()> {
+pub fn run(config: &AppConfig, force: bool) -> io::Result<()> {
     println!("按下 Ctrl+C 退出。");
     let running = Arc::new(AtomicBool::new(true));
     let running_flag = Arc::clone(&running);
@@ -27,68 +34,197 @@ pub fn run(config: &AppConfig) -> io::Result<()> {
         running_flag.store(false, Ordering::SeqCst);
     })
     .map_err(|e| io::Error::other(format!("无法设置 Ctrl+C 处理器: {e}")))?;
-    let initial_utc = config.clock.initial_utc()?;
-    let clock = Arc::new(Mutex::new(ProgramClock::new(initial_utc)));
-    if !initial_sync(&clock, &running, &config.ntp)? {
+    if let Some(provisional_utc) = provisional_start_utc(config) {
+        let mut clock = ProgramClock::new(provisional_utc);
+        clock.apply_offset_stepped(chrono::Duration::zero());
+        let mut synchronizer = Synchronizer::new(config, clock)?;
+        synchronizer.mark_provisional();
+        synchronizer.start()?;
+        let time_display = TimeDisplay::new(&config.clock)?;
+        let result = run_ui_loop(&mut synchronizer, &config.ui, &time_display, &running);
+        println!();
+        return result;
+    }
+    let (initial_utc, warning) = config.clock.reconcile_startup_utc(Utc::now(), force)?;
+    if let Some(warning) = warning {
+        println!("{warning}");
+    }
+    let mut synchronizer = Synchronizer::new(config, ProgramClock::new(initial_utc))?;
+    // 广播模式下不主动查询服务器，直接等待第一个广播包完成首次校正。
+    if config.broadcast.is_none()
+        && !initial_sync(
+            synchronizer.clock(),
+            &running,
+            &config.ntp,
+            config.http_fallback.as_ref(),
+        )?
+    {
         println!();
         return Ok(());
     }
-    let mut kalman_filter = KalmanFilter::new(
-        0.0,
-        config.kalman.initial_uncertainty,
-        config.kalman.initial_process_noise_q,
-        config.kalman.adaptation_rate_eta,
-        config.kalman.nis_ema_alpha,
-    );
-    let rx = ntp::start_sync_thread(Arc::clone(&clock), config.ntp.clone());
-    let result = run_ui_loop(
-        &clock,
-        &mut kalman_filter,
-        &rx,
-        &config.ui,
-        config.kalman.delay_to_r_factor,
-        &running,
+    synchronizer.start()?;
+    let time_display = TimeDisplay::new(&config.clock)?;
+    let result = run_ui_loop(&mut synchronizer, &config.ui, &time_display, &running);
+    println!();
+    result
+}
+/// 配置了 `startup_state` 且保存的状态可用时，返回临时启动的初始时间，跳过阻塞的初始同步。
+fn provisional_start_utc(config: &AppConfig) -> Option<DateTime<Utc>> {
+    let startup_state = config.startup_state.as_ref()?;
+    match startup_state::provisional_start(startup_state, Utc::now()) {
+        Ok(utc) => {
+            println!("以保存的时钟状态临时启动，将在后台完成网络同步。");
+            Some(utc)
+        }
+        Err(reason) => {
+            println!("未使用保存的时钟状态: {reason}");
+            None
+        }
+    }
+}
+/// 演示模式：不读取配置文件、不访问网络，以系统时钟为起点，
+/// 由模拟带微小漂移的测量驱动界面。
+pub fn run_demo(config: &AppConfig) -> io::Result<()> {
+    println!(
+        "演示模式：以系统时钟为参考并模拟 {} ppm 漂移，不访问网络。按下 Ctrl+C 退出。",
+        demo::DEMO_DRIFT_PPM
     );
+    let running = Arc::new(AtomicBool::new(true));
+    let running_flag = Arc::clone(&running);
+    ctrlc::set_handler(move || {
+        running_flag.store(false, Ordering::SeqCst);
+    })
+    .map_err(|e| io::Error::other(format!("无法设置 Ctrl+C 处理器: {e}")))?;
+    let mut clock = ProgramClock::new(Utc::now());
+    clock.apply_offset_stepped(chrono::Duration::zero());
+    let mut synchronizer = Synchronizer::new(config, clock)?;
+    synchronizer.start_demo();
+    println!();
+    let time_display = TimeDisplay::new(&config.clock)?;
+    let result = run_ui_loop(&mut synchronizer, &config.ui, &time_display, &running);
     println!();
     result
 }
+pub fn print_now(config: &AppConfig, format: Option<&str>, utc: bool) -> io::Result<()> {
+    let (initial_utc, warning) = config.clock.reconcile_startup_utc(Utc::now(), false)?;
+    if let Some(warning) = warning {
+        eprintln!("{warning}");
+    }
+    let clock = Arc::new(Mutex::new(ProgramClock::new(initial_utc)));
+    sync_once(&clock, &config.ntp)?;
+    let now = clock.lock().unwrap().now();
+    let time_display = TimeDisplay::new(&config.clock)?;
+    let text = format.map_or_else(
+        || time_display.rfc3339(now, !utc),
+        |format| time_display.format(now, format, !utc),
+    );
+    println!("{text}");
+    Ok(())
+}
+/// `--once` 输出的一行测量结果。
+#[derive(Serialize)]
+struct OnceReport<'a> {
+    server: &'a str,
+    offset_secs: f64,
+    delay_secs: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stratum: Option<u8>,
+}
+/// 单次测量：以系统时钟为起点按初始同步的方式查询一次，打印系统时钟相对服务器的偏移与往返延迟后退出。
+/// 配置了法定数量时同样要求足够多台服务器一致；没有可用结果时返回错误，进程以非零状态退出。
+pub fn run_once(config: &AppConfig, format: OnceFormat) -> io::Result<()> {
+    let ntp_config = &config.ntp;
+    let clock = Arc::new(Mutex::new(ProgramClock::new(Utc::now())));
+    let sample = sync_once(&clock, ntp_config)?;
+    let report = OnceReport {
+        server: &sample.server,
+        offset_secs: sample.offset.as_seconds_f64(),
+        delay_secs: sample.delay.as_seconds_f64(),
+        stratum: sample.stratum,
+    };
+    let line = match format {
+        OnceFormat::Text => format!(
+            "server={} offset={:+.6}s delay={:.6}s",
+            report.server, report.offset_secs, report.delay_secs
+        ),
+        OnceFormat::Json => serde_json::to_string(&report).map_err(io::Error::other)?,
+    };
+    println!("{line}");
+    Ok(())
+}
+/// 一次性同步并一步校正时钟，返回所用的样本。配置了法定数量时同时查询多台服务器
+/// （`concurrent_queries`，未配置时为全部服务器），要求足够多台一致；否则以第一台响应的服务器为准。
+fn sync_once(
+    clock: &Arc<Mutex<ProgramClock>>,
+    ntp_config: &NtpConfig,
+) -> io::Result<ntp::NtpSample> {
+    let Some(min_servers) = ntp_config.discipline_quorum() else {
+        return ntp::quick_sync(clock, ntp_config);
+    };
+    let count = ntp_config
+        .concurrent_queries
+        .unwrap_or(ntp_config.servers.len());
+    let servers = ntp::concurrent_servers(ntp_config, count);
+    let timeout = ntp_config.initial_sync_timeout();
+    let sample = ntp::query_best(&servers, min_servers, timeout, clock, ntp_config)?;
+    clock.lock().unwrap().apply_offset_stepped(sample.offset);
+    Ok(sample)
+}
+pub fn probe(config: &AppConfig, server: &str) -> io::Result<()> {
+    const SUMMARY_VARIABLES: [&str; 5] = ["stratum", "offset", "sys_jitter", "jitter", "refid"];
+    let variables = ntp_control::read_variables(
+        server,
+        config.ntp.port,
+        config.ntp.sync_timeout(),
+        config.ntp.source_port_range,
+    )?;
+    println!("{server} 的服务器变量:");
+    for name in SUMMARY_VARIABLES {
+        if let Some(value) = variables.get(name) {
+            println!("  {name:<12} {value}");
+        }
+    }
+    for (name, value) in &variables {
+        if !SUMMARY_VARIABLES.contains(&name.as_str()) {
+            println!("  {name:<12} {value}");
+        }
+    }
+    Ok(())
+}
 fn initial_sync(
     clock: &Arc<Mutex<ProgramClock>>,
     running: &AtomicBool,
     ntp_config: &NtpConfig,
+    http_fallback: Option<&HttpFallbackConfig>,
 ) -> io::Result<bool> {
-    let mut rng = rand::rng();
-    loop {
-        if !running.load(Ordering::SeqCst) {
-            return Ok(false);
-        }
-        let server_index = rng.random_range(0..ntp_config.servers.len());
-        let server = &ntp_config.servers[server_index];
+    let mut progress = |message: &str| {
         execute!(
             io::stdout(),
             cursor::MoveToColumn(0),
             terminal::Clear(terminal::ClearType::CurrentLine),
-            Print(format!("正在尝试从 {server} 进行初始同步..."))
+            Print(message)
         )?;
-        io::stdout().flush()?;
-        if let Ok((initial_offset, _)) =
-            ntp::query_ntp(server, ntp_config.initial_sync_timeout(), clock, ntp_config)
-        {
-            clock.lock().unwrap().apply_offset(initial_offset);
+        io::stdout().flush()
+    };
+    loop {
+        if !running.load(Ordering::SeqCst) {
+            return Ok(false);
+        }
+        if ntp::initial_sync_round(clock, ntp_config, http_fallback, &mut progress)? {
             println!();
             return Ok(true);
         }
         thread::sleep(ntp_config.initial_sync_retry_interval());
     }
 }
-fn handle_sync_message(
-    message: ntp::SyncMessage,
-    kalman_filter: &mut KalmanFilter,
-    clock: &Arc<Mutex<ProgramClock>>,
-    delay_to_r_factor: f64,
+fn print_sync_event(
+    event: &SyncEvent,
+    ui_config: &UiConfig,
+    drift: &mut DriftSmoother,
+    offset_trend: Option<&mut OffsetTrend>,
 ) -> io::Result<()> {
-    match message {
-        ntp::SyncMessage::Syncing(server) => {
+    match event {
+        SyncEvent::Syncing(server) => {
             execute!(
                 io::stdout(),
                 cursor::MoveToColumn(0),
@@ -96,109 +232,228 @@ fn handle_sync_message(
                 Print(format!("重新同步中 (来自: {server})..."))
             )?;
         }
-        ntp::SyncMessage::Success(measured_offset, measured_delay) => {
-            fn micros_to_secs(micros: i64, what: &'static str) -> io::Result<f64> {
-                const MAX_SAFE_INTEGER_IN_F64: u64 = 9_007_199_254_740_992; // 2^53
-                const TWO_POW_32: f64 = 4_294_967_296.0;
-                let micros_abs = micros.unsigned_abs();
-                if micros_abs > MAX_SAFE_INTEGER_IN_F64 {
-                    return Err(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        format!("{what} 超出 f64 可精确表示的整数范围"),
-                    ));
-                }
-                let high = u32::try_from(micros_abs >> 32).map_err(|_| {
-                    io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        format!("{what} 超出 u32 可表示范围"),
-                    )
-                })?;
-                let low = u32::try_from(micros_abs & 0xFFFF_FFFF).map_err(|_| {
-                    io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        format!("{what} 超出 u32 可表示范围"),
-                    )
-                })?;
-                let micros_f64 = f64::from(high) * TWO_POW_32 + f64::from(low);
-                let micros_secs = micros_f64 / 1_000_000.0;
-                Ok(if micros < 0 {
-                    -micros_secs
-                } else {
-                    micros_secs
-                })
-            }
-            let measured_offset_micros = measured_offset.num_microseconds().ok_or_else(|| {
-                io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    "NTP measured_offset 超出 microseconds 可表示范围",
-                )
-            })?;
-            let measured_offset_secs = micros_to_secs(measured_offset_micros, "measured_offset")?;
-            let measured_delay_micros = measured_delay.num_microseconds().ok_or_else(|| {
-                io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    "NTP measured_delay 超出 microseconds 可表示范围",
-                )
-            })?;
-            let measurement_noise_r =
-                micros_to_secs(measured_delay_micros, "measured_delay")? * delay_to_r_factor;
-            let smoothed_offset_secs =
-                kalman_filter.update(measured_offset_secs, measurement_noise_r);
-            let smoothed_offset = if smoothed_offset_secs < 0.0 {
-                chrono::Duration::from_std(Duration::from_secs_f64(-smoothed_offset_secs))
-                    .map(|d| -d)
-            } else {
-                chrono::Duration::from_std(Duration::from_secs_f64(smoothed_offset_secs))
-            }
-            .map_err(|e| {
-                io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    format!("滤波偏移无法转换为 chrono::Duration: {e}"),
-                )
-            })?;
-            clock.lock().unwrap().apply_offset(smoothed_offset);
+        SyncEvent::Failed(_) => {}
+        SyncEvent::Notice(notice) => {
+            execute!(
+                io::stdout(),
+                cursor::MoveToColumn(0),
+                terminal::Clear(terminal::ClearType::CurrentLine),
+                Print(format!("注意: {notice}"))
+            )?;
+        }
+        SyncEvent::Updated(report) => {
             execute!(
                 io::stdout(),
                 cursor::MoveToColumn(0),
                 terminal::Clear(terminal::ClearType::CurrentLine)
             )?;
+            let trend = offset_trend.map_or_else(String::new, |trend| {
+                format!(
+                    " {}",
+                    trend.update(report.measured_offset_secs, report.offset_sigma_secs)
+                )
+            });
             print!(
-                "结果：测量偏移: {:.2}ms, 延迟: {}ms | 滤波后偏移: {:.2}ms, 漂移率: {:.2} ppm, \
+                "结果：测量偏移: {}{trend}, 延迟: {} | 滤波后偏移: {} ±{}, 漂移率: {:.2} ppm, \
                  过程噪声: {:.1e}",
-                measured_offset_secs * 1000.0,
-                measured_delay.num_milliseconds(),
-                smoothed_offset_secs * 1000.0,
-                kalman_filter.get_drift_ppm(),
-                kalman_filter.get_process_noise_q()
+                display::format_offset(report.measured_offset_secs),
+                display::format_offset(report.measured_delay_secs),
+                display::format_offset(report.smoothed_offset_secs),
+                display::format_offset(report.offset_sigma_secs),
+                drift.update(report.drift_ppm),
+                report.process_noise_q
             );
+            if let Some(interval) = report.effective_interval_secs {
+                print!(", 实际采样间隔: {interval:.1}s");
+            }
+            if ui_config.show_stability() {
+                print!(" | {}", display::stability_bar(report.offset_sigma_secs));
+            }
         }
     }
     io::stdout().flush()
 }
 fn run_ui_loop(
-    clock: &Arc<Mutex<ProgramClock>>,
-    kalman_filter: &mut KalmanFilter,
-    rx: &mpsc::Receiver<ntp::SyncMessage>,
+    synchronizer: &mut Synchronizer,
     ui_config: &UiConfig,
-    delay_to_r_factor: f64,
+    time_display: &TimeDisplay,
     running: &AtomicBool,
 ) -> io::Result<()> {
+    match ui_config.mode {
+        UiMode::Line => run_line_loop(synchronizer, ui_config, time_display, running),
+        UiMode::Dashboard => run_dashboard_loop(synchronizer, ui_config, time_display, running),
+    }
+}
+/// 界面线程调度饥饿检测：单次循环的实际耗时超过刷新间隔的 `STALL_FACTOR` 倍（且不少于
+/// `MIN_STALL`）时视为卡顿。卡顿期间被跳过的时间记为滞后量，此后显示时间以两倍速平滑追上，
+/// 而不是一次跳变；滞后超过 `MAX_CATCH_UP` 时追赶过久，直接跳到当前时间。
+struct StallMonitor {
+    refresh_interval: Duration,
+    last_frame: Instant,
+    lag: Duration,
+}
+impl StallMonitor {
+    const STALL_FACTOR: u32 = 10;
+    const MIN_STALL: Duration = Duration::from_millis(50);
+    const MAX_CATCH_UP: Duration = Duration::from_secs(1);
+
+    fn new(refresh_interval: Duration) -> Self {
+        Self {
+            refresh_interval,
+            last_frame: Instant::now(),
+            lag: Duration::ZERO,
+        }
+    }
+
+    /// 开始新的一帧：返回本帧显示时间应回退的滞后量，以及检测到的卡顿时长。
+    fn frame(&mut self) -> (Duration, Option<Duration>) {
+        let now = Instant::now();
+        let elapsed = now.saturating_duration_since(self.last_frame);
+        self.last_frame = now;
+        let threshold = (self.refresh_interval * Self::STALL_FACTOR).max(Self::MIN_STALL);
+        if elapsed > threshold {
+            self.lag += elapsed.saturating_sub(self.refresh_interval);
+            if self.lag > Self::MAX_CATCH_UP {
+                self.lag = Duration::ZERO;
+            }
+            return (self.lag, Some(elapsed));
+        }
+        self.lag = self.lag.saturating_sub(elapsed);
+        (self.lag, None)
+    }
+}
+/// 卡顿时写入结构化日志并在界面上提示，返回本帧的显示时间。
+fn apply_stall_monitor(
+    monitor: &mut StallMonitor,
+    synchronizer: &mut Synchronizer,
+    time: DateTime<Utc>,
+) -> io::Result<DateTime<Utc>> {
+    let (lag, stall) = monitor.frame();
+    if let Some(stall) = stall {
+        synchronizer.notify(&format!(
+            "界面刷新停滞了 {}ms（刷新间隔 {}ms），系统负载过高或进程被暂停",
+            stall.as_millis(),
+            monitor.refresh_interval.as_millis()
+        ))?;
+    }
+    Ok(chrono::Duration::from_std(lag).map_or(time, |lag| time - lag))
+}
+/// 按 `display_resolution_millis` 向下截断后格式化界面上的时间，仅影响显示。
+/// 配置了 `subsecond_bar_width` 时在后面附上未截断时间的亚秒进度条。
+fn format_display_time(
+    time: DateTime<Utc>,
+    ui_config: &UiConfig,
+    time_display: &TimeDisplay,
+) -> String {
+    let truncated = ui_config
+        .display_resolution()
+        .and_then(|resolution| chrono::Duration::from_std(resolution).ok())
+        .and_then(|resolution| time.duration_trunc(resolution).ok())
+        .unwrap_or(time);
+    let text = time_display.format(truncated, &ui_config.time_format, true);
+    if ui_config.subsecond_bar_width == 0 {
+        return text;
+    }
+    format!(
+        "{text} {}",
+        display::subsecond_bar(time.timestamp_subsec_nanos(), ui_config.subsecond_bar_width)
+    )
+}
+/// 程序时钟无法读取时退回显示系统时间并附上提示，界面不因内部错误而崩溃或停滞。
+fn os_time_display(
+    error: &impl Display,
+    ui_config: &UiConfig,
+    time_display: &TimeDisplay,
+) -> String {
+    format!(
+        "{} [{}: {error}]",
+        format_display_time(Utc::now(), ui_config, time_display),
+        ui_config.os_time_fallback_message()
+    )
+}
+fn drift_smoother(ui_config: &UiConfig) -> DriftSmoother {
+    DriftSmoother::new(ui_config.drift_smoothing_alpha())
+        .with_display_limit(ui_config.max_displayed_drift_ppm)
+}
+fn run_line_loop(
+    synchronizer: &mut Synchronizer,
+    ui_config: &UiConfig,
+    time_display: &TimeDisplay,
+    running: &AtomicBool,
+) -> io::Result<()> {
+    let mut drift = drift_smoother(ui_config);
+    let mut offset_trend = ui_config.show_offset_trend.then(OffsetTrend::new);
+    let mut stall_monitor = StallMonitor::new(ui_config.refresh_interval());
     while running.load(Ordering::SeqCst) {
-        let corrected_utc = clock.lock().unwrap().now();
-        let corrected_local: DateTime<Local> = corrected_utc.with_timezone(&Local);
+        let display = match synchronizer.try_now() {
+            Ok(time) => {
+                let time = apply_stall_monitor(&mut stall_monitor, synchronizer, time)?;
+                if synchronizer.is_synchronized() {
+                    format_display_time(time, ui_config, time_display)
+                } else {
+                    ui_config.warmup_message().to_owned()
+                }
+            }
+            Err(e) => os_time_display(&e, ui_config, time_display),
+        };
         execute!(
             io::stdout(),
             cursor::MoveUp(1),
             cursor::MoveToColumn(0),
             terminal::Clear(terminal::ClearType::CurrentLine),
-            Print(corrected_local.format(&ui_config.time_format)),
+            Print(display),
             cursor::MoveDown(1),
             cursor::MoveToColumn(0),
         )?;
         io::stdout().flush()?;
-        if let Ok(message) = rx.try_recv() {
-            handle_sync_message(message, kalman_filter, clock, delay_to_r_factor)?;
+        if let Some(event) = synchronizer.poll()? {
+            print_sync_event(&event, ui_config, &mut drift, offset_trend.as_mut())?;
+        }
+        thread::sleep(ui_config.refresh_interval());
+    }
+    Ok(())
+}
+fn run_dashboard_loop(
+    synchronizer: &mut Synchronizer,
+    ui_config: &UiConfig,
+    time_display: &TimeDisplay,
+    running: &AtomicBool,
+) -> io::Result<()> {
+    let mut dashboard = Dashboard::enter(
+        ui_config.show_stability(),
+        ui_config.show_offset_trend,
+        drift_smoother(ui_config),
+    )?;
+    let mut stall_monitor = StallMonitor::new(ui_config.refresh_interval());
+    while running.load(Ordering::SeqCst) {
+        match Dashboard::poll_action()? {
+            Some(DashboardAction::Quit) => {
+                running.store(false, Ordering::SeqCst);
+                break;
+            }
+            Some(DashboardAction::TogglePause) => {
+                if synchronizer.is_paused() {
+                    synchronizer.resume();
+                } else {
+                    synchronizer.pause();
+                }
+            }
+            Some(DashboardAction::ResetSafety) => synchronizer.reset_drift_latch()?,
+            None => {}
+        }
+        if let Some(event) = synchronizer.poll()? {
+            dashboard.handle_event(event);
         }
+        let state = synchronizer.snapshot();
+        let display = if let Some(error) = &state.clock_error {
+            os_time_display(error, ui_config, time_display)
+        } else if state.synchronized {
+            let time = apply_stall_monitor(&mut stall_monitor, synchronizer, state.time)?;
+            format_display_time(time, ui_config, time_display)
+        } else {
+            ui_config.warmup_message().to_owned()
+        };
+        dashboard.render(&display, &state, synchronizer.stats())?;
         thread::sleep(ui_config.refresh_interval());
     }
     Ok(())
@@ -0,0 +1,106 @@
+use std::{
+    io::{self, ErrorKind},
+    net::{Ipv4Addr, UdpSocket},
+    sync::{Arc, Mutex, mpsc},
+    thread,
+};
+
+use crate::{
+    config::{BroadcastConfig, NtpConfig},
+    ntp::{self, NtpSample, SyncMessage},
+    program_clock::ProgramClock,
+};
+const MODE_BROADCAST: u8 = 5;
+const STRATUM_OFFSET: usize = 1;
+fn open_socket(config: &BroadcastConfig) -> io::Result<UdpSocket> {
+    let group = config.group_addr()?;
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, config.port))?;
+    if group.is_multicast() {
+        socket.join_multicast_v4(&group, &Ipv4Addr::UNSPECIFIED)?;
+    } else {
+        socket.set_broadcast(true)?;
+    }
+    Ok(socket)
+}
+/// 广播包只有服务器的发送时间戳，没有往返测量，
+/// 因此以配置的单向传播延迟补偿：offset = (T3 + delay) - 本地接收时刻。
+fn measure(
+    packet: &[u8],
+    received_at: i128,
+    ntp_config: &NtpConfig,
+    config: &BroadcastConfig,
+) -> io::Result<(chrono::Duration, chrono::Duration)> {
+    let mode = packet
+        .first()
+        .map(|byte| byte & 0b111)
+        .ok_or_else(|| io::Error::new(ErrorKind::UnexpectedEof, "广播包为空"))?;
+    if mode != MODE_BROADCAST {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("收到非广播模式（mode {mode}）的数据包"),
+        ));
+    }
+    if let Some(&stratum) = packet.get(STRATUM_OFFSET)
+        && stratum > ntp_config.max_acceptable_stratum()
+    {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "广播服务器 stratum {stratum} 超过 max_acceptable_stratum ({})，已拒绝",
+                ntp_config.max_acceptable_stratum()
+            ),
+        ));
+    }
+    let t3_picos =
+        ntp::read_timestamp_picos(packet, ntp_config.transmit_timestamp_offset, ntp_config)?;
+    let delay_picos = i128::try_from(config.broadcast_delay().as_nanos())
+        .map_err(|_| io::Error::other("broadcast delay overflow"))?
+        * 1000;
+    let offset = ntp::picos_to_duration(t3_picos + delay_picos - received_at);
+    Ok((offset, ntp::picos_to_duration(delay_picos * 2)))
+}
+fn run_listener(
+    socket: &UdpSocket,
+    clock: &Arc<Mutex<ProgramClock>>,
+    ntp_config: &NtpConfig,
+    config: &BroadcastConfig,
+    tx: &mpsc::Sender<SyncMessage>,
+) -> Result<(), mpsc::SendError<SyncMessage>> {
+    let mut buf = vec![0u8; ntp_config.packet_size.max(512)];
+    loop {
+        let received = socket.recv_from(&mut buf).and_then(|(n, source)| {
+            let received_at = ntp::utc_to_picos(clock.lock().unwrap().disciplined_now())?;
+            Ok((n, source, received_at))
+        });
+        let (n, source, received_at) = match received {
+            Ok(received) => received,
+            Err(e) => {
+                tx.send(SyncMessage::Failed(
+                    format!("广播 {}", config.group),
+                    e.to_string(),
+                ))?;
+                continue;
+            }
+        };
+        let name = format!("广播 {}", source.ip());
+        tx.send(SyncMessage::Syncing(name.clone()))?;
+        match measure(&buf[..n], received_at, ntp_config, config) {
+            Ok((offset, delay)) => {
+                tx.send(SyncMessage::Success(NtpSample::new(name, offset, delay)))?;
+            }
+            Err(e) => tx.send(SyncMessage::Failed(name, e.to_string()))?,
+        }
+    }
+}
+pub fn start_broadcast_thread(
+    clock: Arc<Mutex<ProgramClock>>,
+    ntp_config: NtpConfig,
+    config: BroadcastConfig,
+    tx: mpsc::Sender<SyncMessage>,
+) -> io::Result<()> {
+    let socket = open_socket(&config)?;
+    thread::spawn(move || {
+        let _ = run_listener(&socket, &clock, &ntp_config, &config, &tx);
+    });
+    Ok(())
+}
@@ -0,0 +1,116 @@
+use std::io;
+pub const USAGE: &str = "用法:
+  khronos [--force] [--demo]           启动交互式时钟（--demo 为离线演示模式）
+  khronos now [--format <FMT>] [--utc] 同步一次后打印当前时间并退出
+  khronos --once [--format text|json]  测量一次系统时钟偏移与延迟，打印一行结果后退出
+  khronos probe <SERVER>               通过 NTP 控制消息（mode 6）读取服务器变量
+  khronos evaluate <LOG>               回放结构化日志中的样本，对比自适应 Q 与固定 Q
+  khronos correlate <LOG> [--points]   计算日志中最近样本的延迟与偏移相关系数
+  khronos version                      打印版本与构建信息";
+/// `--once` 的输出格式。
+#[derive(Clone, Copy)]
+pub enum OnceFormat {
+    Text,
+    Json,
+}
+pub enum Command {
+    Run { force: bool, demo: bool },
+    Now { format: Option<String>, utc: bool },
+    Once { format: OnceFormat },
+    Probe { server: String },
+    Evaluate { log_path: String },
+    Correlate { log_path: String, points: bool },
+    Version,
+}
+impl Command {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> io::Result<Self> {
+        let mut args = args.into_iter();
+        match args.next().as_deref() {
+            None => Ok(Self::Run {
+                force: false,
+                demo: false,
+            }),
+            Some(first @ ("--force" | "--demo")) => {
+                parse_run_flags(std::iter::once(first.to_owned()).chain(args))
+            }
+            Some("--once") => {
+                let mut format = OnceFormat::Text;
+                while let Some(arg) = args.next() {
+                    match arg.as_str() {
+                        "--format" => {
+                            format = match required_value(&mut args, "--format")?.as_str() {
+                                "text" => OnceFormat::Text,
+                                "json" => OnceFormat::Json,
+                                other => {
+                                    return Err(usage_error(&format!(
+                                        "--once 不支持的输出格式: {other}"
+                                    )));
+                                }
+                            };
+                        }
+                        other => return Err(usage_error(&format!("未知参数: {other}"))),
+                    }
+                }
+                Ok(Self::Once { format })
+            }
+            Some("now") => {
+                let mut format = None;
+                let mut utc = false;
+                while let Some(arg) = args.next() {
+                    match arg.as_str() {
+                        "--format" => format = Some(required_value(&mut args, "--format")?),
+                        "--utc" => utc = true,
+                        other => return Err(usage_error(&format!("未知参数: {other}"))),
+                    }
+                }
+                Ok(Self::Now { format, utc })
+            }
+            Some("probe") => {
+                let server = required_value(&mut args, "probe")?;
+                if let Some(extra) = args.next() {
+                    return Err(usage_error(&format!("未知参数: {extra}")));
+                }
+                Ok(Self::Probe { server })
+            }
+            Some("evaluate") => {
+                let log_path = required_value(&mut args, "evaluate")?;
+                if let Some(extra) = args.next() {
+                    return Err(usage_error(&format!("未知参数: {extra}")));
+                }
+                Ok(Self::Evaluate { log_path })
+            }
+            Some("correlate") => {
+                let log_path = required_value(&mut args, "correlate")?;
+                let mut points = false;
+                for arg in args {
+                    match arg.as_str() {
+                        "--points" => points = true,
+                        other => return Err(usage_error(&format!("未知参数: {other}"))),
+                    }
+                }
+                Ok(Self::Correlate { log_path, points })
+            }
+            Some("version" | "--version" | "-V") => Ok(Self::Version),
+            Some(other) => Err(usage_error(&format!("未知命令: {other}"))),
+        }
+    }
+}
+fn parse_run_flags(args: impl Iterator<Item = String>) -> io::Result<Command> {
+    let mut force = false;
+    let mut demo = false;
+    for arg in args {
+        match arg.as_str() {
+            "--force" => force = true,
+            "--demo" => demo = true,
+            other => return Err(usage_error(&format!("未知参数: {other}"))),
+        }
+    }
+    Ok(Command::Run { force, demo })
+}
+fn required_value(args: &mut impl Iterator<Item = String>, flag: &str) -> io::Result<String> {
+    args.next()
+        .ok_or_else(|| usage_error(&format!("{flag} 需要一个参数")))
+}
+fn usage_error(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("{message}\n{USAGE}"))
+}
@@ -0,0 +1,28 @@
+use chrono::{DateTime, Utc};
+use quanta::Instant;
+/// 比较相邻两次样本之间系统时钟与单调时钟各自经过的时间。两者相差超过阈值时，
+/// 说明系统时钟被外部调整或系统曾挂起，基于单调时钟的滤波步长已不可信。
+pub struct ClockStepGuard {
+    max_discrepancy_secs: f64,
+    last: Option<(Instant, DateTime<Utc>)>,
+}
+impl ClockStepGuard {
+    pub const fn new(max_discrepancy_millis: f64) -> Self {
+        Self {
+            max_discrepancy_secs: max_discrepancy_millis / 1000.0,
+            last: None,
+        }
+    }
+
+    /// 记录本次的两个时钟读数，相差超过阈值时返回差值（系统时钟减单调时钟，秒）。
+    pub fn check(&mut self, monotonic_now: Instant, wall_now: DateTime<Utc>) -> Option<f64> {
+        let previous = self.last.replace((monotonic_now, wall_now));
+        let (last_monotonic, last_wall) = previous?;
+        let monotonic_elapsed = monotonic_now
+            .saturating_duration_since(last_monotonic)
+            .as_secs_f64();
+        let wall_elapsed = (wall_now - last_wall).as_seconds_f64();
+        let discrepancy = wall_elapsed - monotonic_elapsed;
+        (discrepancy.abs() > self.max_discrepancy_secs).then_some(discrepancy)
+    }
+}
@@ -1,52 +1,411 @@
-use std::{fs, io, path::Path, time::Duration};
+use std::{
+    fmt::Display,
+    fs, io,
+    net::Ipv4Addr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use chrono::{DateTime, Utc};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+
+use crate::{kalman_filter::MAX_PREDICT_DT, timescale::LeapSecondTable};
 pub const DEFAULT_CONFIG_PATH: &str = "config.yaml";
+pub const CONFIG_PATHS_ENV: &str = "KHRONOS_CONFIG";
+const BUILTIN_CONFIG: &str = include_str!("../config.yaml");
+pub const STANDARD_NTP_PACKET_SIZE: usize = 48;
+pub const NTP_HEADER_SIZE: usize = 16;
+pub const NTP_TIMESTAMP_SIZE: usize = 8;
+const MAX_SYNC_INTERVAL_SECS: u64 = 1 << 17;
 #[derive(Debug, Clone, Deserialize)]
 pub struct AppConfig {
     pub kalman: KalmanConfig,
     pub ntp: NtpConfig,
     pub ui: UiConfig,
     pub clock: ClockConfig,
+    pub local_source: Option<LocalSourceConfig>,
+    pub debug: Option<DebugConfig>,
+    pub log: Option<LogConfig>,
+    pub http_fallback: Option<HttpFallbackConfig>,
+    pub broadcast: Option<BroadcastConfig>,
+    pub stats_dir: Option<String>,
+    pub path_change: Option<PathChangeConfig>,
+    pub cross_check: Option<CrossCheckConfig>,
+    pub temperature_source: Option<TemperatureSourceConfig>,
+    pub startup_state: Option<StartupStateConfig>,
+    pub http_time_port: Option<u16>,
+    pub rfc868_port: Option<u16>,
+    pub clock_step_guard: Option<ClockStepGuardConfig>,
+    pub accuracy: Option<AccuracyConfig>,
+    pub exec: Option<ExecConfig>,
+    pub server_step: Option<ServerStepConfig>,
+    pub drift_latch: Option<DriftLatchConfig>,
+    pub serial_output: Option<SerialOutputConfig>,
+    pub mmap_path: Option<String>,
+}
+/// 要加载的配置文件：环境变量 `KHRONOS_CONFIG` 中按平台路径分隔符列出的文件（后者覆盖前者），
+/// 未设置时只有默认的 config.yaml。
+pub fn config_paths() -> Vec<PathBuf> {
+    std::env::var_os(CONFIG_PATHS_ENV).map_or_else(
+        || vec![PathBuf::from(DEFAULT_CONFIG_PATH)],
+        |paths| std::env::split_paths(&paths).collect(),
+    )
+}
+fn merge_layer(merged: &mut Value, mut layer: Value) -> io::Result<()> {
+    let servers_merge = layer
+        .as_mapping_mut()
+        .and_then(|mapping| mapping.remove("servers_merge"));
+    let append = match servers_merge.as_ref().map(Value::as_str) {
+        None | Some(Some("replace")) => false,
+        Some(Some("append")) => true,
+        Some(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "servers_merge 只能为 append 或 replace",
+            ));
+        }
+    };
+    if append
+        && let Some(existing) = merged
+            .get("ntp")
+            .and_then(|ntp| ntp.get("servers"))
+            .and_then(Value::as_sequence)
+        && let Some(added) = layer
+            .get_mut("ntp")
+            .and_then(|ntp| ntp.get_mut("servers"))
+            .and_then(Value::as_sequence_mut)
+    {
+        let mut servers = existing.clone();
+        servers.append(added);
+        *added = servers;
+    }
+    if !layer.is_null() {
+        deep_merge(merged, layer);
+    }
+    Ok(())
+}
+fn deep_merge(merged: &mut Value, layer: Value) {
+    match (merged, layer) {
+        (Value::Mapping(merged), Value::Mapping(layer)) => {
+            for (key, value) in layer {
+                match merged.get_mut(&key) {
+                    Some(existing) => deep_merge(existing, value),
+                    None => {
+                        merged.insert(key, value);
+                    }
+                }
+            }
+        }
+        (merged, layer) => *merged = layer,
+    }
+}
+/// 收敛配置档：一组相互协调的过程噪声、自适应速度、延迟加权、同步间隔与 NIS 门限预设，
+/// 供不想逐项调参的用户使用。`fast` 收敛快但估计更跳动，`stable` 平滑但对变化反应慢。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConvergenceProfile {
+    Fast,
+    #[default]
+    Balanced,
+    Stable,
+}
+impl ConvergenceProfile {
+    /// 该配置档的预设：(配置段, 键, 值)。`balanced` 与未引入配置档前的默认值一致；
+    /// 离群门限为 `None` 时填入空值，即不拒绝任何测量。
+    fn presets(self) -> [(&'static str, &'static str, Value); 8] {
+        let (process_noise_q, eta, alpha, r_factor, interval_min, interval_max) = match self {
+            Self::Fast => (5e-9, 0.1, 0.1, 0.5, 0u64, 2u64),
+            Self::Balanced => (5e-10, 0.05, 0.05, 1.0, 0, 3),
+            Self::Stable => (5e-11, 0.02, 0.02, 2.0, 4, 16),
+        };
+        let (max_nis_ema, outlier_nis_threshold) = match self {
+            Self::Fast => (200.0, None),
+            Self::Balanced => (100.0, None),
+            Self::Stable => (50.0, Some(10.83)),
+        };
+        [
+            ("kalman", "initial_process_noise_q", process_noise_q.into()),
+            ("kalman", "adaptation_rate_eta", eta.into()),
+            ("kalman", "nis_ema_alpha", alpha.into()),
+            ("kalman", "delay_to_r_factor", r_factor.into()),
+            ("kalman", "max_nis_ema", max_nis_ema.into()),
+            (
+                "kalman",
+                "outlier_nis_threshold",
+                outlier_nis_threshold.map_or(Value::Null, Value::from),
+            ),
+            ("ntp", "sync_interval_min_secs", interval_min.into()),
+            ("ntp", "sync_interval_max_secs", interval_max.into()),
+        ]
+    }
+}
+/// 把顶层 `convergence_profile`（省略时为 `balanced`）的预设填入配置中未显式给出的键，
+/// 显式给出的键始终优先。
+fn apply_convergence_profile(merged: &mut Value) -> io::Result<()> {
+    let profile: ConvergenceProfile = match merged.get("convergence_profile") {
+        Some(value) => serde_yaml::from_value(value.clone()).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("convergence_profile 只能为 fast、balanced 或 stable: {e}"),
+            )
+        })?,
+        None => ConvergenceProfile::default(),
+    };
+    for (section, key, value) in profile.presets() {
+        if let Some(Value::Mapping(section)) = merged.get_mut(section)
+            && !section.contains_key(key)
+        {
+            section.insert(key.into(), value);
+        }
+    }
+    Ok(())
 }
 impl AppConfig {
     pub fn load() -> io::Result<Self> {
-        Self::load_from_path(DEFAULT_CONFIG_PATH)
+        Self::load_from_paths(&config_paths())
     }
 
     pub fn load_from_path(path: impl AsRef<Path>) -> io::Result<Self> {
-        let path = path.as_ref();
-        let content = fs::read_to_string(path).map_err(|e| {
-            io::Error::new(
-                io::ErrorKind::NotFound,
-                format!("无法读取配置文件 {}: {e}", path.display()),
-            )
+        Self::load_from_paths(&[path])
+    }
+
+    /// 按顺序读取多个配置文件并逐层深度合并：映射按键递归合并，其余取值由后面的文件覆盖。
+    /// 某一层在顶层写 `servers_merge: append` 时，它的 `ntp.servers` 追加到前面各层的列表之后，
+    /// 否则整体替换。只校验合并后的结果，因此单个覆盖文件可以只包含部分字段。
+    pub fn load_from_paths(paths: &[impl AsRef<Path>]) -> io::Result<Self> {
+        let mut merged = Value::Null;
+        for path in paths {
+            let path = path.as_ref();
+            let content = fs::read_to_string(path).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("无法读取配置文件 {}: {e}", path.display()),
+                )
+            })?;
+            let layer: Value = serde_yaml::from_str(&content).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("配置文件 {} 解析失败: {e}", path.display()),
+                )
+            })?;
+            merge_layer(&mut merged, layer).map_err(|e| {
+                io::Error::new(e.kind(), format!("配置文件 {}: {e}", path.display()))
+            })?;
+        }
+        apply_convergence_profile(&mut merged)?;
+        let config: Self = serde_yaml::from_value(merged).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("配置文件解析失败: {e}"))
+        })?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// 编译时嵌入的默认配置（仓库中的 config.yaml），用于没有配置文件时的演示模式。
+    pub fn builtin() -> io::Result<Self> {
+        Self::parse(BUILTIN_CONFIG)
+    }
+
+    fn parse(content: &str) -> io::Result<Self> {
+        let mut value: Value = serde_yaml::from_str(content).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("配置文件解析失败: {e}"))
         })?;
-        let config: Self = serde_yaml::from_str(&content).map_err(|e| {
+        apply_convergence_profile(&mut value)?;
+        let config: Self = serde_yaml::from_value(value).map_err(|e| {
             io::Error::new(io::ErrorKind::InvalidData, format!("配置文件解析失败: {e}"))
         })?;
         config.validate()?;
         Ok(config)
     }
 
+    /// 合法但可疑的取值只产生警告，不阻止启动。
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = self.kalman.warnings();
+        warnings.extend(self.ntp.warnings());
+        warnings.extend(self.ui.warnings());
+        warnings
+    }
+
     fn validate(&self) -> io::Result<()> {
         self.kalman.validate()?;
         self.ntp.validate()?;
         self.ui.validate()?;
         self.clock.validate()?;
+        if let Some(local_source) = &self.local_source {
+            local_source.validate()?;
+        }
+        if let Some(debug) = &self.debug {
+            debug.validate()?;
+        }
+        if let Some(log) = &self.log {
+            log.validate()?;
+        }
+        if let Some(http_fallback) = &self.http_fallback {
+            http_fallback.validate()?;
+        }
+        if let Some(broadcast) = &self.broadcast {
+            broadcast.validate()?;
+        }
+        if let Some(path_change) = &self.path_change {
+            path_change.validate()?;
+        }
+        if let Some(cross_check) = &self.cross_check {
+            cross_check.validate()?;
+        }
+        if let Some(temperature_source) = &self.temperature_source {
+            temperature_source.validate()?;
+        }
+        if let Some(startup_state) = &self.startup_state {
+            startup_state.validate()?;
+        }
+        if let Some(clock_step_guard) = &self.clock_step_guard {
+            clock_step_guard.validate()?;
+        }
+        if let Some(accuracy) = &self.accuracy {
+            accuracy.validate()?;
+        }
+        if let Some(exec) = &self.exec {
+            exec.validate()?;
+        }
+        if let Some(server_step) = &self.server_step {
+            server_step.validate()?;
+        }
+        if let Some(drift_latch) = &self.drift_latch {
+            drift_latch.validate()?;
+        }
+        if let Some(serial_output) = &self.serial_output {
+            serial_output.validate()?;
+        }
+        if self.http_time_port == Some(0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "http_time_port 不能为 0",
+            ));
+        }
+        if self.rfc868_port == Some(0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "rfc868_port 不能为 0",
+            ));
+        }
+        if self
+            .mmap_path
+            .as_ref()
+            .is_some_and(|path| path.trim().is_empty())
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "mmap_path 不能为空",
+            ));
+        }
+        if self
+            .stats_dir
+            .as_ref()
+            .is_some_and(|dir| dir.trim().is_empty())
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "stats_dir 不能为空",
+            ));
+        }
         Ok(())
     }
 }
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KalmanModel {
+    OffsetOnly,
+    #[default]
+    OffsetDrift,
+}
+/// 滤波器预测步长的时间来源。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterDtSource {
+    #[default]
+    Monotonic,
+    ProgramClock,
+}
 #[derive(Debug, Clone, Deserialize)]
 pub struct KalmanConfig {
+    #[serde(default)]
+    pub model: KalmanModel,
+    #[serde(default)]
+    pub dt_source: FilterDtSource,
     pub initial_process_noise_q: f64,
+    #[serde(default)]
+    pub min_process_noise_q: Option<f64>,
+    #[serde(default)]
+    pub max_process_noise_q: Option<f64>,
     pub delay_to_r_factor: f64,
     pub initial_uncertainty: f64,
     pub adaptation_rate_eta: f64,
     pub nis_ema_alpha: f64,
+    #[serde(default)]
+    pub max_sample_age_millis: Option<u64>,
+    #[serde(default)]
+    pub hw_drift_warn_ppm: Option<f64>,
+    #[serde(default)]
+    pub hw_drift_warn_samples: Option<usize>,
+    #[serde(default)]
+    pub min_predict_dt_millis: Option<u64>,
+    #[serde(default)]
+    pub warmup_decay: Option<WarmupDecayConfig>,
+    #[serde(default)]
+    pub max_updates_per_minute: Option<usize>,
+    #[serde(default)]
+    pub adaptive_q: Option<bool>,
+    #[serde(default)]
+    pub initial_nis_ema: Option<f64>,
+    #[serde(default)]
+    pub max_nis_ema: Option<f64>,
+    #[serde(default)]
+    pub max_applied_drift_ppm: Option<f64>,
+    #[serde(default)]
+    pub outlier_nis_threshold: Option<f64>,
+}
+/// 预热期的协方差收缩计划：最初 `updates` 次更新后把协方差乘以从 `factor` 线性过渡到 1 的系数。
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct WarmupDecayConfig {
+    pub updates: usize,
+    pub factor: f64,
 }
 impl KalmanConfig {
+    /// 样本从测量完成到被应用之间允许的最大时长，未配置时为 1 秒。
+    pub fn max_sample_age(&self) -> Duration {
+        Duration::from_millis(self.max_sample_age_millis.unwrap_or(1000))
+    }
+
+    /// 预测步长的下限，未配置时为 10 毫秒。
+    pub fn min_predict_dt(&self) -> Duration {
+        Duration::from_millis(self.min_predict_dt_millis.unwrap_or(10))
+    }
+
+    /// 漂移率告警阈值（ppm），未配置时为 50。
+    pub fn hw_drift_warn_ppm(&self) -> f64 {
+        self.hw_drift_warn_ppm.unwrap_or(50.0)
+    }
+
+    /// 漂移率连续超过阈值多少次后告警，未配置时为 10。
+    pub fn hw_drift_warn_samples(&self) -> usize {
+        self.hw_drift_warn_samples.unwrap_or(10)
+    }
+
+    /// 是否按 NIS 自适应过程噪声，未配置时开启。
+    pub fn adaptive_q(&self) -> bool {
+        self.adaptive_q.unwrap_or(true)
+    }
+
+    /// NIS 指数平均的初值，未配置时为 1.0。
+    pub fn initial_nis_ema(&self) -> f64 {
+        self.initial_nis_ema.unwrap_or(1.0)
+    }
+
+    /// NIS 指数平均的上限，未配置时为 100。
+    pub fn max_nis_ema(&self) -> f64 {
+        self.max_nis_ema.unwrap_or(100.0)
+    }
+
     fn validate(&self) -> io::Result<()> {
         if self.initial_uncertainty <= 0.0 {
             return Err(io::Error::new(
@@ -54,12 +413,7 @@ impl KalmanConfig {
                 "initial_uncertainty 必须为正值",
             ));
         }
-        if self.initial_process_noise_q <= 0.0 {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "initial_process_noise_q 必须为正值",
-            ));
-        }
+        self.validate_process_noise()?;
         if self.delay_to_r_factor <= 0.0 {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidData,
@@ -78,8 +432,167 @@ impl KalmanConfig {
                 "nis_ema_alpha 需要在 0 到 1 之间",
             ));
         }
+        self.validate_drift()?;
+        check_range(
+            "max_sample_age_millis",
+            self.max_sample_age().as_millis(),
+            1,
+            60_000,
+            "样本在通道中排队超过一分钟已无校正价值",
+        )?;
+        check_range(
+            "min_predict_dt_millis",
+            self.min_predict_dt().as_millis(),
+            1,
+            MAX_PREDICT_DT.as_millis() - 1,
+            "下限须为正值且小于预测步长的上限一天",
+        )?;
+        if !self.initial_nis_ema().is_finite() || self.initial_nis_ema() <= 0.0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "initial_nis_ema 必须为正值",
+            ));
+        }
+        if !self.max_nis_ema().is_finite() || self.max_nis_ema() <= self.initial_nis_ema().max(1.0)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "max_nis_ema 必须为有限值，且大于 1 与 initial_nis_ema",
+            ));
+        }
+        if let Some(threshold) = self.outlier_nis_threshold
+            && !(threshold.is_finite() && threshold > 1.0)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "outlier_nis_threshold 必须为大于 1 的有限值",
+            ));
+        }
+        if let Some(max_updates_per_minute) = self.max_updates_per_minute {
+            check_range(
+                "max_updates_per_minute",
+                max_updates_per_minute,
+                1,
+                600,
+                "过高的上限无法阻止相关样本使滤波器过度自信",
+            )?;
+        }
+        if let Some(warmup_decay) = &self.warmup_decay {
+            check_range(
+                "warmup_decay.updates",
+                warmup_decay.updates,
+                1,
+                1000,
+                "预热期只应覆盖最初的收敛阶段",
+            )?;
+            if !(warmup_decay.factor > 0.0 && warmup_decay.factor < 1.0) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "warmup_decay.factor 需要在 0 到 1 之间（不含端点）",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_process_noise(&self) -> io::Result<()> {
+        if self.initial_process_noise_q <= 0.0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "initial_process_noise_q 必须为正值",
+            ));
+        }
+        for (name, bound) in [
+            ("min_process_noise_q", self.min_process_noise_q),
+            ("max_process_noise_q", self.max_process_noise_q),
+        ] {
+            if let Some(bound) = bound
+                && !(bound.is_finite() && bound > 0.0)
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{name} 必须为正的有限值"),
+                ));
+            }
+        }
+        let min = self.min_process_noise_q.unwrap_or(0.0);
+        let max = self.max_process_noise_q.unwrap_or(f64::INFINITY);
+        if !(min..=max).contains(&self.initial_process_noise_q) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "需要满足 min_process_noise_q ≤ initial_process_noise_q ≤ max_process_noise_q",
+            ));
+        }
+        Ok(())
+    }
+
+    fn validate_drift(&self) -> io::Result<()> {
+        if self.hw_drift_warn_ppm() <= 0.0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "hw_drift_warn_ppm 必须为正值",
+            ));
+        }
+        check_range(
+            "hw_drift_warn_ppm",
+            self.hw_drift_warn_ppm(),
+            0.0,
+            10_000.0,
+            "普通晶振的漂移率通常在 ±100 ppm 以内",
+        )?;
+        if self.hw_drift_warn_samples() == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "hw_drift_warn_samples 必须大于 0",
+            ));
+        }
+        if let Some(max_applied_drift_ppm) = self.max_applied_drift_ppm {
+            check_range(
+                "max_applied_drift_ppm",
+                max_applied_drift_ppm,
+                0.1,
+                10_000.0,
+                "普通晶振的漂移率通常在 ±100 ppm 以内",
+            )?;
+        }
         Ok(())
     }
+
+    fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.adaptation_rate_eta > 0.5 {
+            warnings.push(format!(
+                "adaptation_rate_eta 为 {}，过程噪声自适应过快，滤波结果可能随单个异常样本剧烈振荡",
+                self.adaptation_rate_eta
+            ));
+        }
+        warnings
+    }
+}
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RefidLoopPolicy {
+    #[default]
+    Reject,
+    Warn,
+    Ignore,
+}
+/// 一次轮询内对同一服务器连续查询多次时，合并多个样本的方式。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SampleCombine {
+    #[default]
+    MinDelay,
+    Median,
+    TrimmedMean,
+}
+/// 周期同步选择服务器的排名依据。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServerSelection {
+    MinDelay,
+    MinJitter,
+    Composite,
 }
 #[derive(Debug, Clone, Deserialize)]
 pub struct NtpConfig {
@@ -87,13 +600,59 @@ pub struct NtpConfig {
     pub port: u16,
     pub packet_size: usize,
     pub unix_epoch_diff_seconds: u64,
+    #[serde(default)]
+    pub origin_timestamp_offset: Option<usize>,
     pub recv_timestamp_offset: usize,
     pub transmit_timestamp_offset: usize,
     pub initial_sync_timeout_millis: u64,
     pub initial_sync_retry_interval_secs: u64,
     pub sync_timeout_millis: u64,
+    #[serde(default)]
+    pub read_timeout_millis: Option<u64>,
+    #[serde(default)]
+    pub write_timeout_millis: Option<u64>,
+    #[serde(default)]
+    pub discovery_timeout_millis: Option<u64>,
+    #[serde(default)]
+    pub samples_per_poll: Option<usize>,
+    #[serde(default)]
+    pub sample_combine: SampleCombine,
+    #[serde(default)]
+    pub max_queries_per_minute: Option<u32>,
+    #[serde(default)]
+    pub dscp: Option<u8>,
+    #[serde(default)]
+    pub source_port_range: Option<(u16, u16)>,
+    #[serde(default)]
+    pub server_selection: Option<ServerSelection>,
+    #[serde(default)]
+    pub selection_jitter_weight: Option<f64>,
+    #[serde(default)]
+    pub server_tiers: Option<ServerTiersConfig>,
+    #[serde(default)]
+    pub sanity_server: Option<SanityServerConfig>,
+    #[serde(default)]
+    pub heartbeat_timeout_secs: Option<u64>,
+    #[serde(default)]
+    pub min_servers_for_discipline: Option<usize>,
+    #[serde(default)]
+    pub network_change: Option<NetworkChangeConfig>,
+    #[serde(default)]
+    pub kod_cooloff_secs: Option<u64>,
+    #[serde(default)]
+    pub concurrent_queries: Option<usize>,
     pub sync_interval_min_secs: u64,
     pub sync_interval_max_secs: u64,
+    #[serde(default)]
+    pub poll_jitter_percent: Option<f64>,
+    #[serde(default)]
+    pub retry_servers_per_cycle: Option<usize>,
+    #[serde(default)]
+    pub max_acceptable_stratum: Option<u8>,
+    #[serde(default)]
+    pub refid_loop_policy: RefidLoopPolicy,
+    #[serde(default)]
+    pub own_refids: Vec<String>,
 }
 impl NtpConfig {
     pub const fn initial_sync_timeout(&self) -> Duration {
@@ -108,6 +667,97 @@ impl NtpConfig {
         Duration::from_millis(self.sync_timeout_millis)
     }
 
+    /// 周期同步等待响应的超时，未单独配置时沿用 `sync_timeout_millis`。
+    pub fn read_timeout(&self) -> Duration {
+        Duration::from_millis(self.read_timeout_millis.unwrap_or(self.sync_timeout_millis))
+    }
+
+    /// 每次周期同步对所选服务器连续查询的次数，未配置时为 1。
+    pub fn samples_per_poll(&self) -> usize {
+        self.samples_per_poll.unwrap_or(1)
+    }
+
+    /// 可接受的最大服务器层级，未配置时为 15，即接受所有已同步的服务器。
+    pub fn max_acceptable_stratum(&self) -> u8 {
+        self.max_acceptable_stratum.unwrap_or(15)
+    }
+
+    /// 同步间隔的随机抖动幅度（基准间隔的百分比），未配置时为 50。
+    pub fn poll_jitter_percent(&self) -> f64 {
+        self.poll_jitter_percent.unwrap_or(50.0)
+    }
+
+    /// 服务器回显请求 nonce 的发起时间戳字段偏移，未配置时为 24。
+    pub fn origin_timestamp_offset(&self) -> usize {
+        self.origin_timestamp_offset.unwrap_or(24)
+    }
+
+    /// 周期同步失败时同一周期内改用其他服务器重试的次数，未配置时为 2。
+    pub fn retry_servers_per_cycle(&self) -> usize {
+        self.retry_servers_per_cycle.unwrap_or(2)
+    }
+
+    /// `composite` 选择策略中抖动相对最小延迟的权重，未配置时为 1。
+    pub fn selection_jitter_weight(&self) -> f64 {
+        self.selection_jitter_weight.unwrap_or(1.0)
+    }
+
+    /// 服务器所在的优先级层级（0 为最高）。未出现在任何层级中的服务器归入最后一个隐含层级，
+    /// 未配置分级时所有服务器都在第 0 层。
+    pub fn server_tier(&self, server: &str) -> usize {
+        self.server_tiers.as_ref().map_or(0, |tiers| {
+            tiers
+                .tiers
+                .iter()
+                .position(|tier| tier.iter().any(|entry| entry == server))
+                .unwrap_or(tiers.tiers.len())
+        })
+    }
+
+    /// 启动发现阶段每台服务器的查询超时，未配置时不进行发现。
+    pub fn discovery_timeout(&self) -> Option<Duration> {
+        self.discovery_timeout_millis.map(Duration::from_millis)
+    }
+
+    /// 同步线程心跳停滞多久视为卡住并重建线程，未配置时不监视。
+    pub fn heartbeat_timeout(&self) -> Option<Duration> {
+        self.heartbeat_timeout_secs.map(Duration::from_secs)
+    }
+
+    /// 校正时钟所需的一致服务器数：显式配置的法定数量；只启用并发查询时取并发数的过半数。
+    pub fn discipline_quorum(&self) -> Option<usize> {
+        self.min_servers_for_discipline
+            .or_else(|| self.concurrent_queries.map(|count| count / 2 + 1))
+    }
+
+    /// 服务器以 DENY 或 RSTR 拒绝访问后暂停查询它的时长，默认 1 小时。
+    pub fn kod_cooloff(&self) -> Duration {
+        Duration::from_secs(self.kod_cooloff_secs.unwrap_or(3600))
+    }
+
+    /// 周期同步发送请求的超时，未单独配置时沿用 `sync_timeout_millis`。
+    pub fn write_timeout(&self) -> Duration {
+        Duration::from_millis(
+            self.write_timeout_millis
+                .unwrap_or(self.sync_timeout_millis),
+        )
+    }
+
+    pub const fn sync_interval_min(&self) -> Duration {
+        Duration::from_secs(self.sync_interval_min_secs)
+    }
+
+    pub const fn sync_interval_max(&self) -> Duration {
+        Duration::from_secs(self.sync_interval_max_secs)
+    }
+
+    pub fn own_refid_addrs(&self) -> Vec<Ipv4Addr> {
+        self.own_refids
+            .iter()
+            .filter_map(|refid| refid.parse().ok())
+            .collect()
+    }
+
     pub fn unix_epoch_diff_u32(&self) -> io::Result<u32> {
         u32::try_from(self.unix_epoch_diff_seconds).map_err(|_| {
             io::Error::new(
@@ -130,59 +780,462 @@ impl NtpConfig {
                 "packet_size 必须大于 0",
             ));
         }
-        if self.recv_timestamp_offset + 8 > self.packet_size {
+        let timestamp_fields = [
+            ("origin_timestamp_offset", self.origin_timestamp_offset()),
+            ("recv_timestamp_offset", self.recv_timestamp_offset),
+            ("transmit_timestamp_offset", self.transmit_timestamp_offset),
+        ];
+        for (index, (name, offset)) in timestamp_fields.iter().enumerate() {
+            if *offset < NTP_HEADER_SIZE {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{name} 不能落在前 {NTP_HEADER_SIZE} 字节的报文头内"),
+                ));
+            }
+            if offset + NTP_TIMESTAMP_SIZE > self.packet_size {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{name} 超出数据包长度"),
+                ));
+            }
+            for (other_name, other_offset) in &timestamp_fields[index + 1..] {
+                if offset.abs_diff(*other_offset) < NTP_TIMESTAMP_SIZE {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{name} 与 {other_name} 的时间戳区域重叠"),
+                    ));
+                }
+            }
+        }
+        self.validate_timing()?;
+        if !(0.0..=100.0).contains(&self.poll_jitter_percent()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "poll_jitter_percent 必须在 0 到 100 之间",
+            ));
+        }
+        if !(1..=15).contains(&self.max_acceptable_stratum()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "max_acceptable_stratum 必须在 1 到 15 之间",
+            ));
+        }
+        for refid in &self.own_refids {
+            if refid.parse::<Ipv4Addr>().is_err() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("own_refids 中的 {refid} 不是有效的 IPv4 地址"),
+                ));
+            }
+        }
+        self.unix_epoch_diff_u32()?;
+        if let Some(server_tiers) = &self.server_tiers {
+            server_tiers.validate(&self.servers)?;
+        }
+        if let Some(sanity_server) = &self.sanity_server {
+            sanity_server.validate()?;
+        }
+        if let Some(network_change) = &self.network_change {
+            network_change.validate()?;
+        }
+        self.validate_quorum()?;
+        check_range(
+            "kod_cooloff_secs",
+            self.kod_cooloff().as_secs(),
+            1,
+            604_800,
+            "拒绝访问后的冷却时间应在 1 秒到 7 天之间",
+        )?;
+        if let Some(timeout) = self.heartbeat_timeout_secs
+            && timeout <= self.sync_interval_max_secs
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "heartbeat_timeout_secs ({timeout}) 必须大于 sync_interval_max_secs ({})，\
+                     否则同步线程在两次同步之间的正常等待也会被判定为停滞",
+                    self.sync_interval_max_secs
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    fn validate_quorum(&self) -> io::Result<()> {
+        if let Some(count) = self.concurrent_queries {
+            check_range(
+                "concurrent_queries",
+                count,
+                2,
+                self.servers.len(),
+                "并发查询的服务器数不能超过配置的服务器数",
+            )?;
+        }
+        if let Some(min_servers) = self.min_servers_for_discipline {
+            check_range(
+                "min_servers_for_discipline",
+                min_servers,
+                1,
+                self.concurrent_queries.unwrap_or(self.servers.len()),
+                "法定数量不能超过配置的服务器数或每周期并发查询的服务器数",
+            )?;
+        }
+        Ok(())
+    }
+
+    fn validate_timing(&self) -> io::Result<()> {
+        check_range(
+            "initial_sync_timeout_millis",
+            self.initial_sync_timeout_millis,
+            1,
+            60_000,
+            "超过一分钟仍未收到响应的查询应视为失败",
+        )?;
+        check_range(
+            "sync_timeout_millis",
+            self.sync_timeout_millis,
+            1,
+            60_000,
+            "超过一分钟仍未收到响应的查询应视为失败",
+        )?;
+        for (name, value) in [
+            ("read_timeout_millis", self.read_timeout_millis),
+            ("write_timeout_millis", self.write_timeout_millis),
+            ("discovery_timeout_millis", self.discovery_timeout_millis),
+        ] {
+            if let Some(value) = value {
+                check_range(name, value, 1, 60_000, "超过一分钟仍未完成的收发应视为失败")?;
+            }
+        }
+        check_range(
+            "samples_per_poll",
+            self.samples_per_poll(),
+            1,
+            16,
+            "连续查询过多会被服务器视为滥用",
+        )?;
+        if !self.selection_jitter_weight().is_finite() || self.selection_jitter_weight() < 0.0 {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidData,
-                "recv_timestamp_offset 超出数据包长度",
+                "selection_jitter_weight 必须为非负有限值",
             ));
         }
-        if self.transmit_timestamp_offset + 8 > self.packet_size {
+        if let Some(max_queries_per_minute) = self.max_queries_per_minute {
+            check_range(
+                "max_queries_per_minute",
+                max_queries_per_minute,
+                1,
+                6_000,
+                "上限应能容纳至少每分钟一次的周期同步",
+            )?;
+        }
+        if let Some(dscp) = self.dscp {
+            check_range(
+                "dscp",
+                dscp,
+                0,
+                63,
+                "DSCP 为 6 位字段，如 CS6 为 48、EF 为 46",
+            )?;
+        }
+        if let Some((first, last)) = self.source_port_range
+            && (first == 0 || first > last)
+        {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidData,
-                "transmit_timestamp_offset 超出数据包长度",
+                format!(
+                    "source_port_range 为 [{first}, {last}]，起始端口必须大于 0 且不大于结束端口"
+                ),
             ));
         }
+        check_range(
+            "initial_sync_retry_interval_secs",
+            self.initial_sync_retry_interval_secs,
+            1,
+            3_600,
+            "初始同步重试间隔过长会让程序长时间停留在启动阶段",
+        )?;
+        check_range(
+            "sync_interval_min_secs",
+            self.sync_interval_min_secs,
+            0,
+            MAX_SYNC_INTERVAL_SECS,
+            "上限与 ntpd 的 maxpoll 17（约 36 小时）一致",
+        )?;
+        check_range(
+            "sync_interval_max_secs",
+            self.sync_interval_max_secs,
+            1,
+            MAX_SYNC_INTERVAL_SECS,
+            "上限与 ntpd 的 maxpoll 17（约 36 小时）一致",
+        )?;
         if self.sync_interval_min_secs > self.sync_interval_max_secs {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidData,
-                "sync_interval_min_secs 不能大于 sync_interval_max_secs",
+                format!(
+                    "sync_interval_min_secs ({}) 不能大于 sync_interval_max_secs ({})",
+                    self.sync_interval_min_secs, self.sync_interval_max_secs
+                ),
             ));
         }
-        self.unix_epoch_diff_u32()?;
         Ok(())
     }
+
+    fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.packet_size != STANDARD_NTP_PACKET_SIZE {
+            warnings.push(format!(
+                "packet_size 为 {}，而未配置认证或扩展字段时标准 NTP 报文应为 \
+                 {STANDARD_NTP_PACKET_SIZE} 字节，服务器可能拒绝该请求",
+                self.packet_size
+            ));
+        }
+        let read_timeout_millis = self.read_timeout().as_millis();
+        if read_timeout_millis >= u128::from(self.sync_interval_max_secs) * 1000 {
+            warnings.push(format!(
+                "读超时 ({read_timeout_millis}ms) 不小于同步间隔上限 ({} 秒)，超时的查询会拖慢同步节奏",
+                self.sync_interval_max_secs
+            ));
+        }
+        warnings
+    }
+}
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UiMode {
+    #[default]
+    Line,
+    Dashboard,
 }
 #[derive(Debug, Clone, Deserialize)]
 pub struct UiConfig {
+    #[serde(default)]
+    pub mode: UiMode,
     pub refresh_interval_millis: u64,
     pub time_format: String,
+    #[serde(default)]
+    pub history_size: Option<usize>,
+    #[serde(default)]
+    pub show_stability: Option<bool>,
+    #[serde(default)]
+    pub warmup_message: Option<String>,
+    #[serde(default)]
+    pub display_resolution_millis: u64,
+    #[serde(default)]
+    pub drift_smoothing_alpha: Option<f64>,
+    #[serde(default)]
+    pub show_offset_trend: bool,
+    #[serde(default)]
+    pub os_time_fallback_message: Option<String>,
+    #[serde(default)]
+    pub subsecond_bar_width: usize,
+    #[serde(default)]
+    pub max_displayed_drift_ppm: Option<f64>,
 }
 impl UiConfig {
     pub const fn refresh_interval(&self) -> Duration {
         Duration::from_millis(self.refresh_interval_millis)
     }
 
+    /// 保留的最近同步样本数量，未配置时为 120。
+    pub fn history_size(&self) -> usize {
+        self.history_size.unwrap_or(120)
+    }
+
+    /// 是否显示偏移标准差及稳定度条，未配置时显示。
+    pub fn show_stability(&self) -> bool {
+        self.show_stability.unwrap_or(true)
+    }
+
+    /// 显示漂移率的平滑系数，未配置时为 1（不平滑）。
+    pub fn drift_smoothing_alpha(&self) -> f64 {
+        self.drift_smoothing_alpha.unwrap_or(1.0)
+    }
+
+    /// 首次校正偏移之前代替时钟显示的提示文字。
+    pub fn warmup_message(&self) -> &str {
+        self.warmup_message.as_deref().unwrap_or("同步中...")
+    }
+
+    /// 程序时钟无法读取、退回显示系统时间时附加的提示文字。
+    pub fn os_time_fallback_message(&self) -> &str {
+        self.os_time_fallback_message
+            .as_deref()
+            .unwrap_or("程序时钟异常，显示系统时间")
+    }
+
+    /// 界面显示时间的量化粒度，0 表示不量化。
+    pub const fn display_resolution(&self) -> Option<Duration> {
+        if self.display_resolution_millis == 0 {
+            None
+        } else {
+            Some(Duration::from_millis(self.display_resolution_millis))
+        }
+    }
+
     fn validate(&self) -> io::Result<()> {
-        if self.refresh_interval_millis == 0 {
+        check_range(
+            "subsecond_bar_width",
+            self.subsecond_bar_width,
+            0,
+            100,
+            "0 表示不显示亚秒进度条，过宽时一行容纳不下",
+        )?;
+        check_range(
+            "refresh_interval_millis",
+            self.refresh_interval_millis,
+            1,
+            10_000,
+            "刷新间隔过长时界面上的时间会长时间停滞",
+        )?;
+        if self.time_format.trim().is_empty() {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidData,
-                "refresh_interval_millis 必须大于 0",
+                "time_format 不能为空",
             ));
         }
-        if self.time_format.trim().is_empty() {
+        check_range(
+            "history_size",
+            self.history_size(),
+            1,
+            100_000,
+            "历史样本仅用于界面展示与统计，过大只会占用内存",
+        )?;
+        check_range(
+            "display_resolution_millis",
+            self.display_resolution_millis,
+            0,
+            1_000,
+            "粒度超过 1 秒时时间显示会跳秒",
+        )?;
+        if let Some(max_displayed_drift_ppm) = self.max_displayed_drift_ppm {
+            check_range(
+                "max_displayed_drift_ppm",
+                max_displayed_drift_ppm,
+                0.1,
+                10_000.0,
+                "普通晶振的漂移率通常在 ±100 ppm 以内",
+            )?;
+        }
+        if let Some(alpha) = self.drift_smoothing_alpha
+            && !(alpha > 0.0 && alpha <= 1.0)
+        {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidData,
-                "time_format 不能为空",
+                format!("drift_smoothing_alpha 为 {alpha}，必须在 (0, 1] 范围内"),
             ));
         }
         Ok(())
     }
+
+    fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.refresh_interval_millis > 1_000 {
+            warnings.push(format!(
+                "refresh_interval_millis 为 {}，超过 1 秒，时间显示会跳秒",
+                self.refresh_interval_millis
+            ));
+        }
+        warnings
+    }
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StartupReconciliation {
+    PreferOsClock,
+    RequireForce,
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CorrectionReference {
+    Measurement,
+    Application,
+}
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Timescale {
+    #[default]
+    Utc,
+    Tai,
+}
+/// 闰秒表的一项：自 `effective_date`（UTC 零时）起 TAI 比 UTC 快 `tai_minus_utc` 秒。
+#[derive(Debug, Clone, Deserialize)]
+pub struct LeapSecondConfig {
+    pub effective_date: String,
+    pub tai_minus_utc: i64,
 }
 #[derive(Debug, Clone, Deserialize)]
 pub struct ClockConfig {
     pub initial_utc: String,
+    #[serde(default)]
+    pub max_startup_disagreement_secs: Option<u64>,
+    #[serde(default)]
+    pub startup_reconciliation: Option<StartupReconciliation>,
+    #[serde(default)]
+    pub correction_reference: Option<CorrectionReference>,
+    #[serde(default)]
+    pub max_slew_ppm: Option<f64>,
+    #[serde(default)]
+    pub max_slew_duration_secs: Option<u64>,
+    #[serde(default)]
+    pub monotonic: bool,
+    #[serde(default)]
+    pub timescale: Timescale,
+    #[serde(default)]
+    pub leap_seconds: Vec<LeapSecondConfig>,
 }
 impl ClockConfig {
+    pub fn reconcile_startup_utc(
+        &self,
+        os_utc: DateTime<Utc>,
+        force: bool,
+    ) -> io::Result<(DateTime<Utc>, Option<String>)> {
+        let initial_utc = self.initial_utc()?;
+        let disagreement_secs = (initial_utc - os_utc).num_seconds().unsigned_abs();
+        if disagreement_secs <= self.max_startup_disagreement_secs() {
+            return Ok((initial_utc, None));
+        }
+        match (self.startup_reconciliation(), force) {
+            (StartupReconciliation::PreferOsClock, _) => Ok((
+                os_utc,
+                Some(format!(
+                    "initial_utc 与系统时钟相差 {disagreement_secs} 秒，已改用系统时钟作为起点"
+                )),
+            )),
+            (StartupReconciliation::RequireForce, true) => Ok((
+                initial_utc,
+                Some(format!(
+                    "initial_utc 与系统时钟相差 {disagreement_secs} 秒，已按 --force 继续使用 initial_utc"
+                )),
+            )),
+            (StartupReconciliation::RequireForce, false) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "initial_utc 与系统时钟相差 {disagreement_secs} 秒，超过 \
+                     max_startup_disagreement_secs ({})，如确需使用请添加 --force",
+                    self.max_startup_disagreement_secs()
+                ),
+            )),
+        }
+    }
+
+    /// `initial_utc` 与系统时钟允许的最大差值（秒），未配置时为 3600。
+    pub fn max_startup_disagreement_secs(&self) -> u64 {
+        self.max_startup_disagreement_secs.unwrap_or(3600)
+    }
+
+    /// 启动差值过大时的处理方式，未配置时改用系统时钟。
+    pub fn startup_reconciliation(&self) -> StartupReconciliation {
+        self.startup_reconciliation
+            .unwrap_or(StartupReconciliation::PreferOsClock)
+    }
+
+    /// 校正量应用的时间基准，未配置时以样本采集时刻为基准。
+    pub fn correction_reference(&self) -> CorrectionReference {
+        self.correction_reference
+            .unwrap_or(CorrectionReference::Measurement)
+    }
+
     pub fn initial_utc(&self) -> io::Result<DateTime<Utc>> {
         let parsed = DateTime::parse_from_rfc3339(&self.initial_utc).map_err(|e| {
             io::Error::new(
@@ -193,8 +1246,749 @@ impl ClockConfig {
         Ok(parsed.with_timezone(&Utc))
     }
 
+    /// 渐进校正的最大速率（ppm），未配置时为 0，即始终直接跳变。
+    pub fn max_slew_ppm(&self) -> f64 {
+        self.max_slew_ppm.unwrap_or(0.0)
+    }
+
+    /// 按最大速率完成一次校正允许的最长时间，未配置时为 600 秒。
+    pub fn max_slew_duration(&self) -> Duration {
+        Duration::from_secs(self.max_slew_duration_secs.unwrap_or(600))
+    }
+
     fn validate(&self) -> io::Result<()> {
         self.initial_utc()?;
+        if !(0.0..1_000_000.0).contains(&self.max_slew_ppm()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "max_slew_ppm 必须在 0 到 1000000 之间",
+            ));
+        }
+        LeapSecondTable::from_config(&self.leap_seconds)?;
         Ok(())
     }
 }
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LocalSourceKind {
+    Chrony,
+    Timesyncd,
+}
+#[derive(Debug, Clone, Deserialize)]
+pub struct LocalSourceConfig {
+    pub kind: LocalSourceKind,
+    pub poll_interval_secs: u64,
+    pub noise_scale: f64,
+}
+impl LocalSourceConfig {
+    pub const fn poll_interval(&self) -> Duration {
+        Duration::from_secs(self.poll_interval_secs)
+    }
+
+    fn validate(&self) -> io::Result<()> {
+        if self.poll_interval_secs == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "local_source.poll_interval_secs 必须大于 0",
+            ));
+        }
+        if self.noise_scale <= 0.0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "local_source.noise_scale 必须为正值",
+            ));
+        }
+        Ok(())
+    }
+}
+#[derive(Debug, Clone, Deserialize)]
+pub struct DebugConfig {
+    pub inject_offset_millis: f64,
+    pub inject_drift_ppm: f64,
+}
+impl DebugConfig {
+    fn validate(&self) -> io::Result<()> {
+        if !cfg!(feature = "skew-injection") {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "debug 配置段仅在启用 skew-injection 特性编译时可用",
+            ));
+        }
+        if !self.inject_offset_millis.is_finite() || !self.inject_drift_ppm.is_finite() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "inject_offset_millis 与 inject_drift_ppm 必须为有限值",
+            ));
+        }
+        Ok(())
+    }
+}
+#[derive(Debug, Clone, Deserialize)]
+pub struct LogConfig {
+    pub path: String,
+    pub max_size_bytes: u64,
+    pub max_files: usize,
+    pub compress: bool,
+}
+impl LogConfig {
+    fn validate(&self) -> io::Result<()> {
+        if self.path.trim().is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "log.path 不能为空",
+            ));
+        }
+        if self.max_size_bytes == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "log.max_size_bytes 必须大于 0",
+            ));
+        }
+        Ok(())
+    }
+}
+#[derive(Debug, Clone, Deserialize)]
+pub struct HttpFallbackConfig {
+    pub url: String,
+    pub timeout_millis: u64,
+    pub extra_delay_secs: f64,
+}
+impl HttpFallbackConfig {
+    pub const fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_millis)
+    }
+
+    fn validate(&self) -> io::Result<()> {
+        if !self.url.starts_with("https://") && !self.url.starts_with("http://") {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "http_fallback.url 必须以 https:// 或 http:// 开头",
+            ));
+        }
+        if self.timeout_millis == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "http_fallback.timeout_millis 必须大于 0",
+            ));
+        }
+        if !self.extra_delay_secs.is_finite() || self.extra_delay_secs < 0.0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "http_fallback.extra_delay_secs 必须为非负有限值",
+            ));
+        }
+        Ok(())
+    }
+}
+#[derive(Debug, Clone, Deserialize)]
+pub struct BroadcastConfig {
+    pub group: String,
+    pub port: u16,
+    pub broadcast_delay_millis: f64,
+}
+impl BroadcastConfig {
+    pub fn group_addr(&self) -> io::Result<Ipv4Addr> {
+        self.group.parse().map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("broadcast.group {} 不是有效的 IPv4 地址", self.group),
+            )
+        })
+    }
+
+    pub fn broadcast_delay(&self) -> Duration {
+        Duration::from_secs_f64(self.broadcast_delay_millis / 1000.0)
+    }
+
+    fn validate(&self) -> io::Result<()> {
+        let group = self.group_addr()?;
+        if !group.is_multicast() && !group.is_broadcast() && !group.is_unspecified() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "broadcast.group 必须是组播地址、255.255.255.255 或 0.0.0.0",
+            ));
+        }
+        if !self.broadcast_delay_millis.is_finite() || self.broadcast_delay_millis < 0.0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "broadcast.broadcast_delay_millis 必须为非负有限值",
+            ));
+        }
+        Ok(())
+    }
+}
+#[derive(Debug, Clone, Deserialize)]
+pub struct PathChangeConfig {
+    pub baseline_samples: usize,
+    pub confirm_samples: usize,
+    pub min_delay_jump_millis: f64,
+    pub covariance_inflation: f64,
+}
+impl PathChangeConfig {
+    fn validate(&self) -> io::Result<()> {
+        if self.baseline_samples == 0 || self.confirm_samples == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "path_change.baseline_samples 与 confirm_samples 必须大于 0",
+            ));
+        }
+        if !self.min_delay_jump_millis.is_finite() || self.min_delay_jump_millis <= 0.0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "path_change.min_delay_jump_millis 必须为正值",
+            ));
+        }
+        if !self.covariance_inflation.is_finite() || self.covariance_inflation < 1.0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "path_change.covariance_inflation 不能小于 1",
+            ));
+        }
+        Ok(())
+    }
+}
+#[derive(Debug, Clone, Deserialize)]
+pub struct CrossCheckConfig {
+    pub reference_process_noise_q: f64,
+    pub divergence_threshold_millis: f64,
+    #[serde(default)]
+    pub resync_on_anomaly: bool,
+    pub covariance_inflation: f64,
+}
+impl CrossCheckConfig {
+    fn validate(&self) -> io::Result<()> {
+        if !self.reference_process_noise_q.is_finite() || self.reference_process_noise_q < 0.0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "cross_check.reference_process_noise_q 必须为非负有限值",
+            ));
+        }
+        if !self.divergence_threshold_millis.is_finite() || self.divergence_threshold_millis <= 0.0
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "cross_check.divergence_threshold_millis 必须为正值",
+            ));
+        }
+        if !self.covariance_inflation.is_finite() || self.covariance_inflation < 1.0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "cross_check.covariance_inflation 不能小于 1",
+            ));
+        }
+        Ok(())
+    }
+}
+/// 温度读数来源：读取文件或执行命令，取输出的第一个数值乘以 `scale` 得到摄氏度。
+#[derive(Debug, Clone, Deserialize)]
+pub struct TemperatureSourceConfig {
+    pub path: Option<String>,
+    pub command: Option<Vec<String>>,
+    pub scale: f64,
+}
+impl TemperatureSourceConfig {
+    fn validate(&self) -> io::Result<()> {
+        match (&self.path, &self.command) {
+            (Some(_), None) => {}
+            (None, Some(command)) if !command.is_empty() => {}
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "temperature_source 必须且只能配置 path 或非空的 command 之一",
+                ));
+            }
+        }
+        if !self.scale.is_finite() || self.scale == 0.0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "temperature_source.scale 必须为非零有限值",
+            ));
+        }
+        Ok(())
+    }
+}
+#[derive(Debug, Clone, Deserialize)]
+pub struct StartupStateConfig {
+    pub path: String,
+    pub freshness_secs: u64,
+    pub max_os_disagreement_millis: f64,
+}
+impl StartupStateConfig {
+    fn validate(&self) -> io::Result<()> {
+        if self.path.trim().is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "startup_state.path 不能为空",
+            ));
+        }
+        check_range(
+            "startup_state.freshness_secs",
+            self.freshness_secs,
+            1,
+            86_400,
+            "过旧的状态已无法反映时钟的当前偏差",
+        )?;
+        if !self.max_os_disagreement_millis.is_finite() || self.max_os_disagreement_millis < 0.0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "startup_state.max_os_disagreement_millis 必须为非负有限值",
+            ));
+        }
+        Ok(())
+    }
+}
+#[derive(Debug, Clone, Deserialize)]
+pub struct ClockStepGuardConfig {
+    pub max_discrepancy_millis: f64,
+}
+impl ClockStepGuardConfig {
+    fn validate(&self) -> io::Result<()> {
+        if !self.max_discrepancy_millis.is_finite() || self.max_discrepancy_millis <= 0.0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "clock_step_guard.max_discrepancy_millis 必须为正值",
+            ));
+        }
+        Ok(())
+    }
+}
+#[derive(Debug, Clone, Deserialize)]
+pub struct AccuracyConfig {
+    pub path: String,
+    pub min_servers: usize,
+    pub agreement_millis: f64,
+    pub window_secs: u64,
+}
+impl AccuracyConfig {
+    pub const fn window(&self) -> Duration {
+        Duration::from_secs(self.window_secs)
+    }
+
+    fn validate(&self) -> io::Result<()> {
+        if self.path.trim().is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "accuracy.path 不能为空",
+            ));
+        }
+        check_range(
+            "accuracy.min_servers",
+            self.min_servers,
+            2,
+            16,
+            "至少需要两个服务器相互印证才能作为共识",
+        )?;
+        check_range(
+            "accuracy.window_secs",
+            self.window_secs,
+            1,
+            3600,
+            "窗口过长时漂移会使较早的测量失去参考价值",
+        )?;
+        if !self.agreement_millis.is_finite() || self.agreement_millis <= 0.0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "accuracy.agreement_millis 必须为正值",
+            ));
+        }
+        Ok(())
+    }
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExecEvent {
+    Synchronized,
+    Holdover,
+    LargeStep,
+}
+impl ExecEvent {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Synchronized => "synchronized",
+            Self::Holdover => "holdover",
+            Self::LargeStep => "large_step",
+        }
+    }
+}
+/// 事件到命令的映射；`command` 的第一项为程序，其余为参数。
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExecHookConfig {
+    pub event: ExecEvent,
+    pub command: Vec<String>,
+}
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExecConfig {
+    pub timeout_millis: u64,
+    pub holdover_after_secs: u64,
+    pub large_step_millis: f64,
+    pub hooks: Vec<ExecHookConfig>,
+}
+impl ExecConfig {
+    pub const fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_millis)
+    }
+
+    pub const fn holdover_after(&self) -> Duration {
+        Duration::from_secs(self.holdover_after_secs)
+    }
+
+    fn validate(&self) -> io::Result<()> {
+        check_range(
+            "exec.timeout_millis",
+            self.timeout_millis,
+            1,
+            600_000,
+            "钩子命令应尽快结束，长时间运行的任务请自行转入后台",
+        )?;
+        check_range(
+            "exec.holdover_after_secs",
+            self.holdover_after_secs,
+            1,
+            MAX_SYNC_INTERVAL_SECS,
+            "应大于正常的同步间隔，否则每次同步之间都会误判为保持状态",
+        )?;
+        if !self.large_step_millis.is_finite() || self.large_step_millis <= 0.0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "exec.large_step_millis 必须为正值",
+            ));
+        }
+        if self.hooks.iter().any(|hook| hook.command.is_empty()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "exec.hooks 中的 command 不能为空",
+            ));
+        }
+        Ok(())
+    }
+}
+#[derive(Debug, Clone, Deserialize)]
+pub struct ServerStepConfig {
+    pub threshold_millis: f64,
+    pub min_stable_servers: usize,
+    pub window_secs: u64,
+}
+impl ServerStepConfig {
+    pub const fn window(&self) -> Duration {
+        Duration::from_secs(self.window_secs)
+    }
+
+    fn validate(&self) -> io::Result<()> {
+        if !self.threshold_millis.is_finite() || self.threshold_millis <= 0.0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "server_step.threshold_millis 必须为正值",
+            ));
+        }
+        check_range(
+            "server_step.min_stable_servers",
+            self.min_stable_servers,
+            1,
+            16,
+            "需要其他服务器保持稳定才能把跳变归因于单个服务器",
+        )?;
+        check_range(
+            "server_step.window_secs",
+            self.window_secs,
+            1,
+            3600,
+            "窗口过长时其他服务器的偏移已不能代表本地时钟的当前状态",
+        )?;
+        Ok(())
+    }
+}
+#[derive(Debug, Clone, Deserialize)]
+pub struct ServerTiersConfig {
+    pub tiers: Vec<Vec<String>>,
+    pub min_responsive: usize,
+}
+impl ServerTiersConfig {
+    fn validate(&self, servers: &[String]) -> io::Result<()> {
+        if self.tiers.iter().any(Vec::is_empty) || self.tiers.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "server_tiers.tiers 不能为空，且每个层级至少包含一个服务器",
+            ));
+        }
+        for (index, server) in self.tiers.iter().flatten().enumerate() {
+            if !servers.contains(server) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("server_tiers 中的 {server} 不在 ntp.servers 列表中"),
+                ));
+            }
+            if self
+                .tiers
+                .iter()
+                .flatten()
+                .skip(index + 1)
+                .any(|other| other == server)
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{server} 在 server_tiers 中出现了多次"),
+                ));
+            }
+        }
+        check_range(
+            "server_tiers.min_responsive",
+            self.min_responsive,
+            1,
+            servers.len(),
+            "有响应的服务器少于该数量时才加入下一层级，不能超过服务器总数",
+        )?;
+        Ok(())
+    }
+}
+#[derive(Debug, Clone, Deserialize)]
+pub struct SanityServerConfig {
+    pub server: String,
+    pub max_disagreement_millis: f64,
+    pub max_reference_age_secs: u64,
+    #[serde(default)]
+    pub require_reachable: bool,
+}
+impl SanityServerConfig {
+    fn validate(&self) -> io::Result<()> {
+        if self.server.trim().is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "sanity_server.server 不能为空",
+            ));
+        }
+        if !self.max_disagreement_millis.is_finite() || self.max_disagreement_millis <= 0.0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "sanity_server.max_disagreement_millis 必须为正值",
+            ));
+        }
+        check_range(
+            "sanity_server.max_reference_age_secs",
+            self.max_reference_age_secs,
+            1,
+            3600,
+            "参考按单调时钟外推，过旧时本地晶振的漂移会累积到比较结果中",
+        )?;
+        Ok(())
+    }
+}
+#[derive(Debug, Clone, Deserialize)]
+pub struct DriftLatchConfig {
+    pub max_drift_ppm: f64,
+    pub trip_samples: usize,
+    pub release_samples: usize,
+    pub sane_offset_millis: f64,
+}
+impl DriftLatchConfig {
+    fn validate(&self) -> io::Result<()> {
+        check_range(
+            "drift_latch.max_drift_ppm",
+            self.max_drift_ppm,
+            1.0,
+            100_000.0,
+            "应明显高于晶振的正常漂移，只在滤波器失稳时触发",
+        )?;
+        check_range(
+            "drift_latch.trip_samples",
+            self.trip_samples,
+            1,
+            1000,
+            "连续超限的次数，过小时单个异常样本即可触发",
+        )?;
+        check_range(
+            "drift_latch.release_samples",
+            self.release_samples,
+            1,
+            1000,
+            "连续正常测量的次数，达到后自动解除",
+        )?;
+        if !self.sane_offset_millis.is_finite() || self.sane_offset_millis <= 0.0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "drift_latch.sane_offset_millis 必须为正值",
+            ));
+        }
+        Ok(())
+    }
+}
+/// 网络切换后部分重置服务器声誉的灵敏度：连续失败多少个周期视为网络变化，
+/// 以及重置时每个服务器保留的最近样本数。
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct NetworkChangeConfig {
+    pub failure_cycles: usize,
+    pub retained_samples: usize,
+}
+impl NetworkChangeConfig {
+    fn validate(&self) -> io::Result<()> {
+        check_range(
+            "network_change.failure_cycles",
+            self.failure_cycles,
+            1,
+            100,
+            "过小时单次丢包即会清空声誉，过大时网络切换后长时间沿用旧排名",
+        )?;
+        check_range(
+            "network_change.retained_samples",
+            self.retained_samples,
+            0,
+            8,
+            "每个服务器最多保留最近 8 个样本用于排名",
+        )
+    }
+}
+/// 串口时间输出支持的波特率。
+pub const SERIAL_BAUD_RATES: [u32; 7] = [4800, 9600, 19200, 38400, 57600, 115_200, 230_400];
+/// 串口每秒输出的时间格式。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SerialTimeFormat {
+    /// ISO 8601 UTC 时间戳，如 `2024-01-01T00:00:00Z`。
+    #[default]
+    Iso,
+    /// NMEA 0183 ZDA 语句，兼容按 GPS 接收机解析时间的设备。
+    Zda,
+}
+#[derive(Debug, Clone, Deserialize)]
+pub struct SerialOutputConfig {
+    pub device: PathBuf,
+    pub baud_rate: u32,
+    #[serde(default)]
+    pub format: SerialTimeFormat,
+}
+impl SerialOutputConfig {
+    fn validate(&self) -> io::Result<()> {
+        if !cfg!(feature = "serial-output") {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "serial_output 配置段仅在启用 serial-output 特性编译时可用",
+            ));
+        }
+        if self.device.as_os_str().is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "serial_output.device 不能为空",
+            ));
+        }
+        if !SERIAL_BAUD_RATES.contains(&self.baud_rate) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "serial_output.baud_rate 为 {}，仅支持 {SERIAL_BAUD_RATES:?}",
+                    self.baud_rate
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+/// 检查取值是否落在闭区间内，错误信息包含当前值、允许范围与建议。
+fn check_range<T: PartialOrd + Display + Copy>(
+    name: &str,
+    value: T,
+    min: T,
+    max: T,
+    hint: &str,
+) -> io::Result<()> {
+    if value < min || value > max {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{name} 为 {value}，超出允许范围 {min} ~ {max}（{hint}）"),
+        ));
+    }
+    Ok(())
+}
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn write_temp(name: &str, content: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("khronos-{}-{name}", std::process::id()));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn layered_files_override_and_append_servers() {
+        let base = write_temp("base.yaml", BUILTIN_CONFIG);
+        let host = write_temp(
+            "host.yaml",
+            "servers_merge: append\nntp:\n  port: 1123\n  servers: [\"ntp.example.com\"]\n",
+        );
+        let single = AppConfig::load_from_path(&base).unwrap();
+        let layered = AppConfig::load_from_paths(&[&base, &host]).unwrap();
+        fs::remove_file(&base).unwrap();
+        fs::remove_file(&host).unwrap();
+        assert_eq!(layered.ntp.port, 1123);
+        assert_eq!(layered.ntp.servers.len(), single.ntp.servers.len() + 1);
+        assert_eq!(layered.ntp.servers.last().unwrap(), "ntp.example.com");
+        assert_eq!(layered.ui.time_format, single.ui.time_format);
+    }
+
+    #[test]
+    fn layered_servers_replace_by_default() {
+        let base = write_temp("replace-base.yaml", BUILTIN_CONFIG);
+        let host = write_temp(
+            "replace-host.yaml",
+            "ntp:\n  servers: [\"ntp.example.com\"]\n",
+        );
+        let layered = AppConfig::load_from_paths(&[&base, &host]).unwrap();
+        fs::remove_file(&base).unwrap();
+        fs::remove_file(&host).unwrap();
+        assert_eq!(layered.ntp.servers, ["ntp.example.com"]);
+    }
+
+    fn with_profile(profile: &str) -> String {
+        BUILTIN_CONFIG.replace(
+            "convergence_profile: balanced",
+            &format!("convergence_profile: {profile}"),
+        )
+    }
+
+    #[test]
+    fn profiles_set_gating_thresholds() {
+        let balanced = AppConfig::parse(&with_profile("balanced")).unwrap().kalman;
+        assert_eq!(balanced.outlier_nis_threshold, None);
+        assert!((balanced.max_nis_ema() - 100.0).abs() < f64::EPSILON);
+        let stable = AppConfig::parse(&with_profile("stable")).unwrap().kalman;
+        assert_eq!(stable.outlier_nis_threshold, Some(10.83));
+        assert!((stable.max_nis_ema() - 50.0).abs() < f64::EPSILON);
+        let fast = AppConfig::parse(&with_profile("fast")).unwrap().kalman;
+        assert_eq!(fast.outlier_nis_threshold, None);
+        assert!((fast.max_nis_ema() - 200.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn explicit_thresholds_override_the_profile() {
+        let content = with_profile("stable")
+            .replace(
+                "# outlier_nis_threshold: 10.83",
+                "outlier_nis_threshold: 20.0",
+            )
+            .replace("# max_nis_ema: 100.0", "max_nis_ema: 100.0");
+        let kalman = AppConfig::parse(&content).unwrap().kalman;
+        assert_eq!(kalman.outlier_nis_threshold, Some(20.0));
+        assert!((kalman.max_nis_ema() - 100.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn min_predict_dt_must_stay_below_the_predict_cap() {
+        let with_min_dt = |millis: u64| {
+            AppConfig::parse(&BUILTIN_CONFIG.replace(
+                "# min_predict_dt_millis: 10",
+                &format!("min_predict_dt_millis: {millis}"),
+            ))
+        };
+        assert!(with_min_dt(0).is_err());
+        assert!(with_min_dt(86_400_000).is_err());
+        assert!(with_min_dt(86_399_999).is_ok());
+    }
+
+    #[test]
+    fn missing_layer_is_reported() {
+        let missing = std::env::temp_dir().join("khronos-missing-layer.yaml");
+        let error = AppConfig::load_from_path(&missing).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+    }
+}
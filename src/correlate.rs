@@ -0,0 +1,114 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, ErrorKind},
+};
+
+use serde::Deserialize;
+
+use crate::stats::{SamplePoint, SyncStats};
+#[derive(Deserialize)]
+struct LoggedSample {
+    event: String,
+    #[serde(default)]
+    server: String,
+    #[serde(default)]
+    measured_offset_secs: f64,
+    #[serde(default)]
+    delay_secs: f64,
+}
+/// 延迟与偏移的线性关系：`slope` 为每秒延迟对应的偏移变化（无量纲）。
+struct Correlation {
+    coefficient: f64,
+    slope: f64,
+}
+/// 将结构化日志中的样本依次写入与实时运行相同的历史缓冲区，只保留最近 `capacity` 个。
+fn load_history(path: &str, capacity: usize) -> io::Result<SyncStats> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut stats = SyncStats::new(capacity);
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let logged: LoggedSample = serde_json::from_str(&line).map_err(|e| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!("第 {} 行解析失败: {e}", index + 1),
+            )
+        })?;
+        if logged.event != "sample" {
+            continue;
+        }
+        stats.record_sample(
+            &logged.server,
+            SamplePoint {
+                offset_secs: logged.measured_offset_secs,
+                delay_secs: logged.delay_secs,
+            },
+        );
+    }
+    Ok(stats)
+}
+/// 皮尔逊相关系数与最小二乘斜率；样本不足两个或延迟没有变化时无法计算。
+fn correlate<'a>(
+    points: impl ExactSizeIterator<Item = &'a SamplePoint> + Clone,
+) -> Option<Correlation> {
+    #[allow(clippy::cast_precision_loss)]
+    let count = points.len() as f64;
+    if count < 2.0 {
+        return None;
+    }
+    let mean_delay = points.clone().map(|p| p.delay_secs).sum::<f64>() / count;
+    let mean_offset = points.clone().map(|p| p.offset_secs).sum::<f64>() / count;
+    let (mut covariance, mut delay_variance, mut offset_variance) = (0.0, 0.0, 0.0);
+    for point in points {
+        let delay = point.delay_secs - mean_delay;
+        let offset = point.offset_secs - mean_offset;
+        covariance += delay * offset;
+        delay_variance += delay * delay;
+        offset_variance += offset * offset;
+    }
+    if delay_variance <= 0.0 || offset_variance <= 0.0 {
+        return None;
+    }
+    Some(Correlation {
+        coefficient: covariance / (delay_variance * offset_variance).sqrt(),
+        slope: covariance / delay_variance,
+    })
+}
+pub fn run(history_size: usize, log_path: &str, dump_points: bool) -> io::Result<()> {
+    let stats = load_history(log_path, history_size)?;
+    let history = stats.history();
+    if history.is_empty() {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("{log_path} 中没有 sample 记录"),
+        ));
+    }
+    println!(
+        "分析最近 {} 个样本（历史容量 {history_size}，来自 {} 个服务器）",
+        history.len(),
+        stats.servers().len()
+    );
+    match correlate(history.iter()) {
+        Some(correlation) => {
+            println!("相关系数 r = {:+.3}", correlation.coefficient);
+            println!("拟合斜率   = {:+.3} ms 偏移 / ms 延迟", correlation.slope);
+            if correlation.coefficient.abs() >= 0.5 {
+                println!("偏移随延迟显著变化，路径可能不对称，可考虑引入非对称校正系数。");
+            }
+        }
+        None => println!("样本不足或延迟没有变化，无法计算相关系数。"),
+    }
+    if dump_points {
+        println!("{:>12} {:>12}", "延迟(ms)", "偏移(ms)");
+        for point in history {
+            println!(
+                "{:>12.3} {:>12.3}",
+                point.delay_secs * 1000.0,
+                point.offset_secs * 1000.0
+            );
+        }
+    }
+    Ok(())
+}
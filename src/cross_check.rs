@@ -0,0 +1,71 @@
+use crate::{config::CrossCheckConfig, kalman_filter::KalmanFilter};
+/// 最近一次交叉校验的结果：主滤波器减参考滤波器的偏移估计（秒），及是否处于异常状态。
+#[derive(Copy, Clone, Debug)]
+pub struct FilterDivergence {
+    pub secs: f64,
+    pub anomalous: bool,
+}
+/// 双滤波器交叉校验：主滤波器驱动时钟，另以固定的较小过程噪声运行一个保守的参考滤波器。
+/// 两者以相同测量更新，偏移估计分歧超过阈值通常意味着阶跃变化或异常数据。
+pub struct FilterCrossCheck {
+    config: CrossCheckConfig,
+    reference: KalmanFilter,
+    divergence_secs: Option<f64>,
+    anomalous: bool,
+}
+impl FilterCrossCheck {
+    pub fn new(config: CrossCheckConfig, primary: &KalmanFilter) -> Self {
+        let reference = primary
+            .clone()
+            .with_fixed_process_noise(config.reference_process_noise_q);
+        Self {
+            config,
+            reference,
+            divergence_secs: None,
+            anomalous: false,
+        }
+    }
+
+    /// 用与主滤波器相同的测量更新参考滤波器；分歧刚超过阈值时返回其大小（秒），
+    /// 持续异常期间不再重复返回，回落到阈值以内后重新计数。
+    pub fn update(
+        &mut self,
+        primary: &KalmanFilter,
+        measurement: f64,
+        measurement_noise_r: f64,
+        dt: f64,
+    ) -> Option<f64> {
+        let divergence = primary.get_offset()
+            - self
+                .reference
+                .update_with_dt(measurement, measurement_noise_r, dt);
+        self.divergence_secs = Some(divergence);
+        if divergence.abs() <= self.config.divergence_threshold_millis / 1000.0 {
+            self.anomalous = false;
+            return None;
+        }
+        if self.anomalous {
+            return None;
+        }
+        self.anomalous = true;
+        Some(divergence)
+    }
+
+    /// 重新同步：同时放大两个滤波器的协方差，让它们都以接下来的测量为准重新收敛。
+    pub fn resync(&mut self, primary: &mut KalmanFilter) {
+        primary.inflate_covariance(self.config.covariance_inflation);
+        self.reference
+            .inflate_covariance(self.config.covariance_inflation);
+    }
+
+    pub fn divergence(&self) -> Option<FilterDivergence> {
+        self.divergence_secs.map(|secs| FilterDivergence {
+            secs,
+            anomalous: self.anomalous,
+        })
+    }
+
+    pub const fn resync_on_anomaly(&self) -> bool {
+        self.config.resync_on_anomaly
+    }
+}
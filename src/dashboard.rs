@@ -0,0 +1,309 @@
+use std::{
+    fmt::Display,
+    io::{self, Write},
+    time::Duration,
+};
+
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    execute, queue,
+    style::Print,
+    terminal,
+};
+
+use crate::{
+    display::{self, DriftSmoother, OffsetTrend},
+    stats::SyncStats,
+    synchronizer::{SyncEvent, SyncReport, SyncState},
+};
+const SPARK_LEVELS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+const SERVER_COLUMN_WIDTH: usize = 28;
+pub enum DashboardAction {
+    Quit,
+    TogglePause,
+    ResetSafety,
+}
+pub struct Dashboard {
+    show_stability: bool,
+    status: String,
+    last_report: Option<SyncReport>,
+    drift: DriftSmoother,
+    offset_trend: Option<OffsetTrend>,
+}
+impl Dashboard {
+    pub fn enter(
+        show_stability: bool,
+        show_offset_trend: bool,
+        drift: DriftSmoother,
+    ) -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stdout(), terminal::EnterAlternateScreen, cursor::Hide)?;
+        Ok(Self {
+            show_stability,
+            status: "等待同步...".to_owned(),
+            last_report: None,
+            drift,
+            offset_trend: show_offset_trend.then(OffsetTrend::new),
+        })
+    }
+
+    pub fn poll_action() -> io::Result<Option<DashboardAction>> {
+        while event::poll(Duration::ZERO)? {
+            if let Event::Key(key) = event::read()?
+                && key.kind == KeyEventKind::Press
+            {
+                match key.code {
+                    KeyCode::Char('q') | KeyCode::Esc => return Ok(Some(DashboardAction::Quit)),
+                    KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                        return Ok(Some(DashboardAction::Quit));
+                    }
+                    KeyCode::Char('p') => return Ok(Some(DashboardAction::TogglePause)),
+                    KeyCode::Char('r') => return Ok(Some(DashboardAction::ResetSafety)),
+                    _ => {}
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn handle_event(&mut self, event: SyncEvent) {
+        match event {
+            SyncEvent::Syncing(server) => {
+                self.status = format!("重新同步中 (来自: {server})...");
+            }
+            SyncEvent::Failed(error) => {
+                self.status = format!("同步失败: {error}");
+            }
+            SyncEvent::Notice(notice) => {
+                self.status = format!("注意: {notice}");
+            }
+            SyncEvent::Updated(report) => {
+                "同步成功".clone_into(&mut self.status);
+                self.drift.update(report.drift_ppm);
+                if let Some(trend) = &mut self.offset_trend {
+                    trend.update(report.measured_offset_secs, report.offset_sigma_secs);
+                }
+                self.last_report = Some(report);
+            }
+        }
+    }
+
+    pub fn render(
+        &self,
+        time: &impl Display,
+        snapshot: &SyncState,
+        stats: &SyncStats,
+    ) -> io::Result<()> {
+        let (width, _) = terminal::size()?;
+        let width = usize::from(width).max(20);
+        let last_sync = snapshot.last_sync_age.map_or_else(
+            || "尚未同步".to_owned(),
+            |age| format!("{:.1}s 前", age.as_secs_f64()),
+        );
+        let mut lines = vec![
+            "Khronos 仪表盘".to_owned(),
+            format!("时间: {time}"),
+            self.status_line(snapshot),
+            format!(
+                "上次同步: {last_sync}  当前偏移估计: {} ± {}  漂移率: {:.2} ppm",
+                display::format_offset(snapshot.offset_secs),
+                display::format_offset(snapshot.offset_variance.sqrt()),
+                self.drift
+                    .value()
+                    .unwrap_or_else(|| self.drift.clamp(snapshot.drift_ppm))
+            ),
+            format!(
+                "自报质量: precision 2^{}  root delay {} (0x{:08x})  root dispersion {} (0x{:08x})",
+                snapshot.quality.precision,
+                display::format_offset(snapshot.quality.root_delay_secs),
+                snapshot.quality.ntp_root_delay(),
+                display::format_offset(snapshot.quality.root_dispersion_secs),
+                snapshot.quality.ntp_root_dispersion()
+            ),
+        ];
+        if let Some(stratum) = snapshot.stratum {
+            lines.push(format!("上游服务器 stratum: {stratum}"));
+        }
+        if let Some(queries) = snapshot.queries_last_minute {
+            lines.push(format!("最近一分钟查询数: {queries}"));
+        }
+        if let Some(accuracy) = snapshot.accuracy {
+            lines.push(format!(
+                "实测精度 (多服务器共识，{} 次): p50 ≤ {}  p95 ≤ {}  p99 ≤ {}",
+                accuracy.samples,
+                display::format_offset(accuracy.p50_secs),
+                display::format_offset(accuracy.p95_secs),
+                display::format_offset(accuracy.p99_secs)
+            ));
+        }
+        lines.push(String::new());
+        lines.push("── 滤波器 ──".to_owned());
+        lines.extend(self.filter_lines(snapshot));
+        lines.push(String::new());
+        lines.push(format!(
+            "── 测量偏移曲线 (最近 {} 次) ──",
+            stats.history().len()
+        ));
+        lines.extend(offset_graph(stats, width));
+        lines.push(String::new());
+        lines.push("── 服务器 ──".to_owned());
+        lines.push(format!(
+            "{:<SERVER_COLUMN_WIDTH$} {:>4} {:>6} {:>6} {:>4} {:>12} {:>10}",
+            "服务器", "排名", "成功", "失败", "跳变", "偏移(ms)", "延迟(ms)"
+        ));
+        for (server, server_stats) in &snapshot.servers {
+            let marker = if snapshot.current_server.as_deref() == Some(server.as_str()) {
+                '>'
+            } else {
+                ' '
+            };
+            lines.push(format!(
+                "{marker}{:<width$} {:>4} {:>6} {:>6} {:>4} {:>12} {:>10}",
+                truncate(server, SERVER_COLUMN_WIDTH - 1),
+                server_stats
+                    .discovery_rank
+                    .map_or_else(|| "-".to_owned(), |rank| rank.to_string()),
+                server_stats.successes,
+                server_stats.failures,
+                server_stats.steps,
+                format_millis(server_stats.last_offset_secs),
+                format_millis(server_stats.last_delay_secs),
+                width = SERVER_COLUMN_WIDTH - 1
+            ));
+        }
+        lines.push(String::new());
+        lines.push("按键: [q]/[Esc] 退出  [p] 暂停/恢复网络同步".to_owned());
+        let mut stdout = io::stdout();
+        queue!(stdout, cursor::MoveTo(0, 0))?;
+        for line in lines {
+            queue!(
+                stdout,
+                terminal::Clear(terminal::ClearType::CurrentLine),
+                Print(truncate(&line, width)),
+                cursor::MoveToNextLine(1)
+            )?;
+        }
+        queue!(stdout, terminal::Clear(terminal::ClearType::FromCursorDown))?;
+        stdout.flush()
+    }
+
+    fn status_line(&self, snapshot: &SyncState) -> String {
+        if let Some(drift_ppm) = snapshot.safed_drift_ppm {
+            return format!(
+                "状态: {} [FILTER SAFED：滤波器失稳，按 {drift_ppm:.2} ppm 保持，按 r 解除]",
+                self.status
+            );
+        }
+        let tag = if snapshot.paused {
+            " [网络同步已暂停，按 p 恢复]"
+        } else if snapshot.provisional {
+            " [临时状态：来自保存的时钟状态，等待首次网络同步]"
+        } else {
+            ""
+        };
+        format!("状态: {}{tag}", self.status)
+    }
+
+    fn filter_lines(&self, snapshot: &SyncState) -> Vec<String> {
+        let mut lines = Vec::new();
+        if let Some(divergence) = snapshot.filter_divergence {
+            lines.push(format!(
+                "双滤波器分歧: {}{}",
+                display::format_offset(divergence.secs),
+                if divergence.anomalous {
+                    "  [异常]"
+                } else {
+                    ""
+                }
+            ));
+        }
+        if let Some(raw) = &snapshot.last_raw {
+            lines.push(format!(
+                "原始测量 (滤波前): 偏移 {}  延迟 {}  来自 {} ({:.1}s 前)",
+                display::format_offset(raw.offset_secs),
+                display::format_offset(raw.delay_secs),
+                raw.server,
+                raw.measured_at.elapsed().as_secs_f64()
+            ));
+        }
+        match &self.last_report {
+            Some(report) => {
+                lines.push(format!(
+                    "测量偏移: {}{}  延迟: {}",
+                    display::format_offset(report.measured_offset_secs),
+                    self.offset_trend
+                        .as_ref()
+                        .map_or_else(String::new, |trend| format!(" {}", trend.arrow())),
+                    display::format_offset(report.measured_delay_secs)
+                ));
+                lines.push(format!(
+                    "滤波后偏移: {}  漂移率: {:.2} ppm (瞬时 {:.2} ppm)  过程噪声: {:.1e}",
+                    display::format_offset(report.smoothed_offset_secs),
+                    self.drift
+                        .value()
+                        .unwrap_or_else(|| self.drift.clamp(report.drift_ppm)),
+                    self.drift.clamp(report.drift_ppm),
+                    report.process_noise_q
+                ));
+                if let Some(interval) = report.effective_interval_secs {
+                    lines.push(format!("实际采样间隔: {interval:.1}s (近期成功同步的平均)"));
+                }
+                if self.show_stability {
+                    lines.push(format!(
+                        "偏移稳定度: {}",
+                        display::stability_bar(report.offset_sigma_secs)
+                    ));
+                }
+            }
+            None => lines.push("尚无同步结果".to_owned()),
+        }
+        lines
+    }
+}
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+fn offset_graph(stats: &SyncStats, width: usize) -> Vec<String> {
+    let history = stats.history();
+    let visible = history.len().min(width);
+    let offsets: Vec<f64> = history
+        .iter()
+        .skip(history.len() - visible)
+        .map(|sample| sample.offset_secs * 1000.0)
+        .collect();
+    if offsets.is_empty() {
+        return vec!["(暂无数据)".to_owned()];
+    }
+    let min = offsets.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = offsets.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let span = max - min;
+    let graph = offsets
+        .iter()
+        .map(|offset| {
+            if span <= f64::EPSILON {
+                return SPARK_LEVELS[SPARK_LEVELS.len() / 2];
+            }
+            #[allow(
+                clippy::cast_possible_truncation,
+                clippy::cast_precision_loss,
+                clippy::cast_sign_loss
+            )]
+            let level = {
+                let top = (SPARK_LEVELS.len() - 1) as f64;
+                (((offset - min) / span) * top).round() as usize
+            };
+            SPARK_LEVELS[level.min(SPARK_LEVELS.len() - 1)]
+        })
+        .collect();
+    vec![graph, format!("范围: {min:.2}ms ~ {max:.2}ms")]
+}
+fn format_millis(secs: Option<f64>) -> String {
+    secs.map_or_else(|| "-".to_owned(), |secs| format!("{:.3}", secs * 1000.0))
+}
+fn truncate(text: &str, width: usize) -> String {
+    text.chars().take(width).collect()
+}
@@ -0,0 +1,54 @@
+use std::{
+    sync::{Arc, Mutex, mpsc},
+    thread,
+    time::Duration,
+};
+
+use chrono::Utc;
+use quanta::Instant;
+use rand::Rng;
+
+use crate::{
+    ntp::{NtpSample, SyncMessage},
+    program_clock::ProgramClock,
+};
+const DEMO_SOURCE_NAME: &str = "演示 (系统时钟)";
+const DEMO_INTERVAL: Duration = Duration::from_secs(2);
+pub const DEMO_DRIFT_PPM: f64 = 5.0;
+const DEMO_NOISE_SECS: f64 = 200e-6;
+const DEMO_DELAY_MICROS: i64 = 1_000;
+/// 以系统时钟为参考、叠加微小的模拟漂移与噪声生成测量，让没有网络的用户也能看到滤波器收敛。
+pub fn start_demo_thread(clock: Arc<Mutex<ProgramClock>>, tx: mpsc::Sender<SyncMessage>) {
+    thread::spawn(move || {
+        let started_at = Instant::now();
+        let mut rng = rand::rng();
+        loop {
+            thread::sleep(DEMO_INTERVAL);
+            if tx
+                .send(SyncMessage::Syncing(DEMO_SOURCE_NAME.to_owned()))
+                .is_err()
+            {
+                break;
+            }
+            let simulated_secs = (started_at.elapsed().as_secs_f64() * DEMO_DRIFT_PPM)
+                .mul_add(1e-6, rng.random_range(-DEMO_NOISE_SECS..=DEMO_NOISE_SECS));
+            let simulated =
+                chrono::Duration::from_std(Duration::from_secs_f64(simulated_secs.abs()))
+                    .unwrap_or_default();
+            let reference = if simulated_secs < 0.0 {
+                Utc::now() - simulated
+            } else {
+                Utc::now() + simulated
+            };
+            let offset = reference - clock.lock().unwrap().disciplined_now();
+            let message = SyncMessage::Success(NtpSample::new(
+                DEMO_SOURCE_NAME.to_owned(),
+                offset,
+                chrono::Duration::microseconds(DEMO_DELAY_MICROS),
+            ));
+            if tx.send(message).is_err() {
+                break;
+            }
+        }
+    });
+}
@@ -0,0 +1,129 @@
+use std::collections::VecDeque;
+
+const STABILITY_BAR_WIDTH: usize = 10;
+const TREND_WINDOW: usize = 6;
+const STABLE_SIGMA_SECS: f64 = 1e-4;
+const UNSTABLE_SIGMA_SECS: f64 = 1.0;
+/// 仅用于显示的漂移率指数加权移动平均，与滤波器内部状态无关，避免收敛期间显示值剧烈跳动。
+pub struct DriftSmoother {
+    alpha: f64,
+    limit_ppm: f64,
+    smoothed_ppm: Option<f64>,
+}
+impl DriftSmoother {
+    pub const fn new(alpha: f64) -> Self {
+        Self {
+            alpha,
+            limit_ppm: f64::INFINITY,
+            smoothed_ppm: None,
+        }
+    }
+
+    /// 显示前把漂移率限制在 ±`limit_ppm` 内，收敛期间的瞬时异常读数不会显得骇人；
+    /// 只影响显示，滤波器与校正使用的漂移率不受影响。`None` 表示不限制。
+    pub fn with_display_limit(mut self, limit_ppm: Option<f64>) -> Self {
+        self.limit_ppm = limit_ppm.unwrap_or(f64::INFINITY);
+        self
+    }
+
+    /// 按显示限幅截断单个漂移率读数，用于不经平滑直接显示的瞬时值。
+    pub fn clamp(&self, raw_ppm: f64) -> f64 {
+        raw_ppm.clamp(-self.limit_ppm, self.limit_ppm)
+    }
+
+    pub fn update(&mut self, raw_ppm: f64) -> f64 {
+        let raw_ppm = self.clamp(raw_ppm);
+        let smoothed = self.smoothed_ppm.map_or(raw_ppm, |previous| {
+            self.alpha.mul_add(raw_ppm - previous, previous)
+        });
+        self.smoothed_ppm = Some(smoothed);
+        smoothed
+    }
+
+    pub const fn value(&self) -> Option<f64> {
+        self.smoothed_ppm
+    }
+}
+/// 仅用于显示的偏移趋势：比较最近几次测量偏移中较新一半与较旧一半的均值，
+/// 差值不超过滤波器偏移标准差时视为平稳，以免测量噪声使箭头来回翻转。
+pub struct OffsetTrend {
+    recent: VecDeque<f64>,
+    arrow: char,
+}
+impl OffsetTrend {
+    pub const fn new() -> Self {
+        Self {
+            recent: VecDeque::new(),
+            arrow: '→',
+        }
+    }
+
+    pub fn update(&mut self, offset_secs: f64, sigma_secs: f64) -> char {
+        if self.recent.len() == TREND_WINDOW {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(offset_secs);
+        let half = self.recent.len() / 2;
+        let (older, newer) = self.recent.make_contiguous().split_at(half);
+        if older.is_empty() {
+            return self.arrow;
+        }
+        let difference = mean(newer) - mean(older);
+        self.arrow = if difference.abs() <= sigma_secs {
+            '→'
+        } else if difference > 0.0 {
+            '↑'
+        } else {
+            '↓'
+        };
+        self.arrow
+    }
+
+    pub const fn arrow(&self) -> char {
+        self.arrow
+    }
+}
+fn mean(values: &[f64]) -> f64 {
+    #[allow(clippy::cast_precision_loss)]
+    let count = values.len() as f64;
+    values.iter().sum::<f64>() / count
+}
+/// 按数量级自动选择 s / ms / µs / ns 单位格式化时间偏移，保留亚毫秒精度。
+pub fn format_offset(secs: f64) -> String {
+    let magnitude = secs.abs();
+    if magnitude >= 1.0 {
+        format!("{secs:.6}s")
+    } else if magnitude >= 1e-3 {
+        format!("{:.3}ms", secs * 1e3)
+    } else if magnitude >= 1e-6 {
+        format!("{:.3}µs", secs * 1e6)
+    } else {
+        format!("{:.1}ns", secs * 1e9)
+    }
+}
+/// 把一秒内已经过的部分画成宽度为 `width` 的进度条，每整秒从空开始填满。
+pub fn subsecond_bar(subsec_nanos: u32, width: usize) -> String {
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    let filled = ((f64::from(subsec_nanos.min(999_999_999)) / 1e9) * width as f64) as usize;
+    format!("[{}{}]", "█".repeat(filled), "░".repeat(width - filled))
+}
+pub fn stability_bar(sigma_secs: f64) -> String {
+    let span = (UNSTABLE_SIGMA_SECS / STABLE_SIGMA_SECS).log10();
+    let position = (UNSTABLE_SIGMA_SECS / sigma_secs.max(f64::MIN_POSITIVE)).log10() / span;
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    let filled = (position.clamp(0.0, 1.0) * STABILITY_BAR_WIDTH as f64).round() as usize;
+    format!(
+        "σ: {} [{}{}]",
+        format_offset(sigma_secs),
+        "█".repeat(filled),
+        "░".repeat(STABILITY_BAR_WIDTH - filled)
+    )
+}
@@ -0,0 +1,95 @@
+use quanta::Instant;
+
+use crate::config::DriftLatchConfig;
+/// 锁定状态：保持所用的漂移率，及上次按该漂移率校正时钟的时刻。
+struct Safed {
+    drift_ppm: f64,
+    corrected_at: Instant,
+    sane_count: usize,
+}
+/// 漂移安全锁。其他保护都失效、滤波器的漂移率估计持续超出安全上限时锁定：
+/// 不再施加滤波器的校正，而以最近一次可信的漂移率保持时钟；连续多次测量正常或手动复位后解除。
+pub struct DriftSafetyLatch {
+    config: DriftLatchConfig,
+    exceed_count: usize,
+    last_good_drift_ppm: f64,
+    safed: Option<Safed>,
+}
+impl DriftSafetyLatch {
+    pub const fn new(config: DriftLatchConfig) -> Self {
+        Self {
+            config,
+            exceed_count: 0,
+            last_good_drift_ppm: 0.0,
+            safed: None,
+        }
+    }
+
+    pub const fn is_safed(&self) -> bool {
+        self.safed.is_some()
+    }
+
+    /// 锁定期间用于保持的可信漂移率，未锁定时为 `None`。
+    pub fn safed_drift_ppm(&self) -> Option<f64> {
+        self.safed.as_ref().map(|safed| safed.drift_ppm)
+    }
+
+    pub const fn max_drift_ppm(&self) -> f64 {
+        self.config.max_drift_ppm
+    }
+
+    /// 记录滤波器更新后的漂移率估计；本次导致锁定时返回保持所用的可信漂移率。
+    pub fn observe_drift(&mut self, drift_ppm: f64) -> Option<f64> {
+        if self.safed.is_some() {
+            return None;
+        }
+        if drift_ppm.abs() <= self.config.max_drift_ppm {
+            self.exceed_count = 0;
+            self.last_good_drift_ppm = drift_ppm;
+            return None;
+        }
+        self.exceed_count += 1;
+        if self.exceed_count < self.config.trip_samples {
+            return None;
+        }
+        self.safed = Some(Safed {
+            drift_ppm: self.last_good_drift_ppm,
+            corrected_at: Instant::now(),
+            sane_count: 0,
+        });
+        Some(self.last_good_drift_ppm)
+    }
+
+    /// 锁定期间记录一个测量偏移；连续正常的次数达到要求时解除锁定，返回应恢复的可信漂移率。
+    pub fn observe_offset(&mut self, offset_secs: f64) -> Option<f64> {
+        let safed = self.safed.as_mut()?;
+        if offset_secs.abs() * 1000.0 <= self.config.sane_offset_millis {
+            safed.sane_count += 1;
+        } else {
+            safed.sane_count = 0;
+        }
+        if safed.sane_count < self.config.release_samples {
+            return None;
+        }
+        self.release()
+    }
+
+    /// 手动解除锁定，返回应恢复的可信漂移率；未锁定时返回 `None`。
+    pub fn release(&mut self) -> Option<f64> {
+        let safed = self.safed.take()?;
+        self.exceed_count = 0;
+        Some(safed.drift_ppm)
+    }
+
+    /// 锁定期间按可信漂移率推算自上次保持校正以来累积的偏移（秒），并以当前时刻为新的起点。
+    pub fn holdover_correction(&mut self) -> Option<(f64, f64)> {
+        let safed = self.safed.as_mut()?;
+        let now = Instant::now();
+        let elapsed = now.saturating_duration_since(safed.corrected_at);
+        safed.corrected_at = now;
+        Some((
+            safed.drift_ppm * 1e-6 * elapsed.as_secs_f64(),
+            safed.drift_ppm,
+        ))
+    }
+}
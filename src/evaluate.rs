@@ -0,0 +1,153 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, ErrorKind},
+};
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+use crate::{config::KalmanConfig, kalman_filter::KalmanFilter};
+const CONVERGENCE_THRESHOLD_SECS: f64 = 1e-3;
+#[derive(Deserialize)]
+struct LoggedSample {
+    time: String,
+    event: String,
+    #[serde(default)]
+    measured_offset_secs: f64,
+    #[serde(default)]
+    delay_secs: f64,
+    #[serde(default)]
+    smoothed_offset_secs: f64,
+}
+/// 回放用的样本（单位均为秒）：`raw_offset` 为未经任何校正的时钟相对参考时间的偏移。
+struct ReplaySample {
+    elapsed: f64,
+    raw_offset: f64,
+    delay: f64,
+}
+/// 回放结果，单位均为秒。
+struct Evaluation {
+    rms_error: f64,
+    max_excursion: f64,
+    convergence_time: Option<f64>,
+}
+/// 从结构化日志读取样本。日志中的测量偏移是相对已校正时钟的残差，
+/// 这里累加每次应用的滤波偏移，还原出未校正时钟的偏移序列。
+fn load_samples(path: &str) -> io::Result<Vec<ReplaySample>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut samples = Vec::new();
+    let mut applied_secs = 0.0;
+    let mut first_time = None;
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let logged: LoggedSample = serde_json::from_str(&line).map_err(|e| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!("第 {} 行解析失败: {e}", index + 1),
+            )
+        })?;
+        if logged.event != "sample" {
+            continue;
+        }
+        let time = DateTime::parse_from_rfc3339(&logged.time)
+            .map(|time| time.with_timezone(&Utc))
+            .map_err(|e| {
+                io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("第 {} 行时间格式无效: {e}", index + 1),
+                )
+            })?;
+        let first_time = *first_time.get_or_insert(time);
+        let elapsed_secs = (time - first_time)
+            .to_std()
+            .map_or(0.0, |elapsed| elapsed.as_secs_f64());
+        samples.push(ReplaySample {
+            elapsed: elapsed_secs,
+            raw_offset: logged.measured_offset_secs + applied_secs,
+            delay: logged.delay_secs,
+        });
+        applied_secs += logged.smoothed_offset_secs;
+    }
+    Ok(samples)
+}
+/// 按实时运行的方式回放：每次将滤波结果应用到模拟时钟，误差为校正后仍残留的偏移。
+fn replay(samples: &[ReplaySample], config: &KalmanConfig, adaptation_rate_eta: f64) -> Evaluation {
+    let mut filter = KalmanFilter::new(
+        config.model,
+        0.0,
+        config.initial_uncertainty,
+        config.initial_process_noise_q,
+        adaptation_rate_eta,
+        config.nis_ema_alpha,
+        config.min_predict_dt().as_secs_f64(),
+    )
+    .with_adaptive_q(config.adaptive_q())
+    .with_process_noise_range(config.min_process_noise_q, config.max_process_noise_q)
+    .with_initial_nis_ema(config.initial_nis_ema())
+    .with_max_nis_ema(config.max_nis_ema())
+    .with_max_drift_ppm(config.max_applied_drift_ppm)
+    .with_outlier_nis_threshold(config.outlier_nis_threshold)
+    .with_warmup_decay(config.warmup_decay);
+    let mut applied_secs = 0.0;
+    let mut previous_elapsed = 0.0;
+    let mut squared_error_sum = 0.0;
+    let mut max_excursion_secs: f64 = 0.0;
+    let mut convergence_secs = None;
+    for sample in samples {
+        let measurement = sample.raw_offset - applied_secs;
+        applied_secs += filter.update_with_dt(
+            measurement,
+            sample.delay * config.delay_to_r_factor,
+            sample.elapsed - previous_elapsed,
+        );
+        previous_elapsed = sample.elapsed;
+        let error = sample.raw_offset - applied_secs;
+        squared_error_sum += error * error;
+        max_excursion_secs = max_excursion_secs.max(error.abs());
+        if error.abs() > CONVERGENCE_THRESHOLD_SECS {
+            convergence_secs = None;
+        } else if convergence_secs.is_none() {
+            convergence_secs = Some(sample.elapsed);
+        }
+    }
+    #[allow(clippy::cast_precision_loss)]
+    let count = samples.len() as f64;
+    Evaluation {
+        rms_error: (squared_error_sum / count).sqrt(),
+        max_excursion: max_excursion_secs,
+        convergence_time: convergence_secs,
+    }
+}
+pub fn run(config: &KalmanConfig, log_path: &str) -> io::Result<()> {
+    let samples = load_samples(log_path)?;
+    if samples.is_empty() {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("{log_path} 中没有 sample 记录"),
+        ));
+    }
+    println!(
+        "回放 {} 个样本（收敛阈值 {:.1}ms）",
+        samples.len(),
+        CONVERGENCE_THRESHOLD_SECS * 1000.0
+    );
+    println!(
+        "{:<14} {:>14} {:>14} {:>12}",
+        "模式", "RMS 误差(ms)", "最大偏离(ms)", "收敛时间(s)"
+    );
+    for (name, eta) in [("自适应 Q", config.adaptation_rate_eta), ("固定 Q", 0.0)] {
+        let evaluation = replay(&samples, config, eta);
+        println!(
+            "{name:<14} {:>14.3} {:>14.3} {:>12}",
+            evaluation.rms_error * 1000.0,
+            evaluation.max_excursion * 1000.0,
+            evaluation
+                .convergence_time
+                .map_or_else(|| "未收敛".to_owned(), |secs| format!("{secs:.1}"))
+        );
+    }
+    Ok(())
+}
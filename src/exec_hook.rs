@@ -0,0 +1,121 @@
+use std::{
+    process::{Command, Stdio},
+    thread,
+    time::Duration,
+};
+
+use crate::config::{ExecConfig, ExecEvent};
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum HookState {
+    Unsynchronized,
+    Synchronized,
+    Holdover,
+}
+impl HookState {
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Unsynchronized => "unsynchronized",
+            Self::Synchronized => "synchronized",
+            Self::Holdover => "holdover",
+        }
+    }
+}
+/// 触发钩子时传给命令的上下文，以环境变量的形式提供。
+#[derive(Copy, Clone)]
+pub struct HookContext {
+    pub offset_secs: f64,
+    pub drift_ppm: f64,
+}
+/// 跟踪同步状态的转换，在转换发生时执行配置的命令。
+/// 命令直接以参数列表启动而不经过 shell，避免配置或上下文中的特殊字符被解释。
+pub struct ExecHooks {
+    config: ExecConfig,
+    state: HookState,
+}
+impl ExecHooks {
+    pub const fn new(config: ExecConfig) -> Self {
+        Self {
+            config,
+            state: HookState::Unsynchronized,
+        }
+    }
+
+    /// 每次校正时钟后调用：校正量超过阈值时触发 `large_step`，
+    /// 从未同步或保持状态恢复时触发 `synchronized`。返回启动失败的提示。
+    pub fn on_correction(&mut self, correction_secs: f64, context: HookContext) -> Vec<String> {
+        let mut failures = Vec::new();
+        if correction_secs.abs() * 1000.0 > self.config.large_step_millis {
+            failures.extend(self.fire(ExecEvent::LargeStep, context));
+        }
+        if self.state != HookState::Synchronized {
+            self.state = HookState::Synchronized;
+            failures.extend(self.fire(ExecEvent::Synchronized, context));
+        }
+        failures
+    }
+
+    /// 距上次成功同步超过 `holdover_after_secs` 时转入保持状态并触发 `holdover`。
+    pub fn check_holdover(
+        &mut self,
+        last_sync_age: Option<Duration>,
+        context: HookContext,
+    ) -> Vec<String> {
+        if self.state != HookState::Synchronized
+            || last_sync_age.is_none_or(|age| age < self.config.holdover_after())
+        {
+            return Vec::new();
+        }
+        self.state = HookState::Holdover;
+        self.fire(ExecEvent::Holdover, context)
+    }
+
+    fn fire(&self, event: ExecEvent, context: HookContext) -> Vec<String> {
+        self.config
+            .hooks
+            .iter()
+            .filter(|hook| hook.event == event)
+            .filter_map(|hook| {
+                let [program, args @ ..] = hook.command.as_slice() else {
+                    return None;
+                };
+                self.spawn(program, args, event, context)
+                    .err()
+                    .map(|e| format!("执行钩子命令 {program} 失败: {e}"))
+            })
+            .collect()
+    }
+
+    /// 启动命令后立即返回，由后台线程等待其结束，超过超时时间仍未结束则终止。
+    fn spawn(
+        &self,
+        program: &str,
+        args: &[String],
+        event: ExecEvent,
+        context: HookContext,
+    ) -> std::io::Result<()> {
+        let mut child = Command::new(program)
+            .args(args)
+            .env("KHRONOS_EVENT", event.name())
+            .env("KHRONOS_STATE", self.state.name())
+            .env("KHRONOS_OFFSET_SECS", context.offset_secs.to_string())
+            .env("KHRONOS_DRIFT_PPM", context.drift_ppm.to_string())
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let timeout = self.config.timeout();
+        thread::spawn(move || {
+            let started = std::time::Instant::now();
+            while matches!(child.try_wait(), Ok(None)) {
+                if started.elapsed() >= timeout {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return;
+                }
+                thread::sleep(WAIT_POLL_INTERVAL);
+            }
+        });
+        Ok(())
+    }
+}
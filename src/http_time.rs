@@ -0,0 +1,62 @@
+use std::{
+    io::{self, ErrorKind},
+    process::Command,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use quanta::Instant;
+
+use crate::{config::HttpFallbackConfig, program_clock::ProgramClock};
+pub fn source_name(config: &HttpFallbackConfig) -> String {
+    format!("HTTP 粗略时间 ({})", config.url)
+}
+pub fn query_http_date(
+    config: &HttpFallbackConfig,
+    program_clock: &Arc<Mutex<ProgramClock>>,
+) -> io::Result<(chrono::Duration, chrono::Duration)> {
+    let timeout_secs = format!("{:.3}", config.timeout().as_secs_f64());
+    let t1 = { program_clock.lock().unwrap().disciplined_now() };
+    let send_instant = Instant::now();
+    let output = Command::new("curl")
+        .args(["--silent", "--show-error", "--head", "--max-time"])
+        .arg(&timeout_secs)
+        .arg(&config.url)
+        .output()?;
+    let round_trip = send_instant.elapsed();
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "curl 执行失败: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    let date = parse_date_header(&String::from_utf8_lossy(&output.stdout))?;
+    let round_trip = chrono::Duration::from_std(round_trip)
+        .map_err(|e| io::Error::other(format!("Round trip duration error: {e}")))?;
+    // Date 头截断到整秒，取该秒的中点作为服务器时间的估计。
+    let server_time = date + chrono::Duration::milliseconds(500);
+    let offset = server_time - (t1 + round_trip / 2);
+    let extra_delay = chrono::Duration::from_std(Duration::from_secs_f64(config.extra_delay_secs))
+        .map_err(|e| io::Error::new(ErrorKind::InvalidData, e.to_string()))?;
+    Ok((offset, round_trip + extra_delay))
+}
+fn parse_date_header(headers: &str) -> io::Result<DateTime<Utc>> {
+    let value = headers
+        .lines()
+        .find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.trim()
+                .eq_ignore_ascii_case("date")
+                .then(|| value.trim())
+        })
+        .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "HTTP 响应缺少 Date 头"))?;
+    DateTime::parse_from_rfc2822(value)
+        .map(|date| date.with_timezone(&Utc))
+        .map_err(|e| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!("无法解析 Date 头 {value}: {e}"),
+            )
+        })
+}
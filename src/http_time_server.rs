@@ -0,0 +1,77 @@
+use std::{
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::SecondsFormat;
+
+use crate::{program_clock::ProgramClock, server_handle::ServerHandle};
+const MAX_REQUEST_HEAD_BYTES: usize = 8 * 1024;
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(2);
+/// 极简的 HTTP 时间服务：对任何请求都返回带当前校正时间的 Date 头，
+/// 响应体为含亚秒精度的 JSON，供只能使用 HTTP 的受限设备在局域网内获取时间。
+pub fn start_http_time_server(
+    clock: Arc<Mutex<ProgramClock>>,
+    port: u16,
+) -> io::Result<ServerHandle> {
+    let listener = TcpListener::bind(("0.0.0.0", port))?;
+    let mut handle = ServerHandle::default();
+    handle.spawn_tcp(listener, move |stream| {
+        let _ = serve(stream, &clock);
+    })?;
+    Ok(handle)
+}
+fn serve(mut stream: TcpStream, clock: &Arc<Mutex<ProgramClock>>) -> io::Result<()> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+    read_request_head(&mut stream)?;
+    let (now, synchronized) = {
+        let clock = clock.lock().unwrap();
+        (clock.now(), clock.is_synchronized())
+    };
+    let (status, body) = if synchronized {
+        (
+            "200 OK",
+            format!(
+                "{{\"utc\":\"{}\",\"unix_secs\":{}.{:09}}}",
+                now.to_rfc3339_opts(SecondsFormat::Nanos, true),
+                now.timestamp(),
+                now.timestamp_subsec_nanos()
+            ),
+        )
+    } else {
+        (
+            "503 Service Unavailable",
+            "{\"error\":\"clock not synchronized\"}".to_owned(),
+        )
+    };
+    let response = format!(
+        "HTTP/1.1 {status}\r\nDate: {}\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{body}",
+        now.format("%a, %d %b %Y %H:%M:%S GMT"),
+        body.len()
+    );
+    stream.write_all(response.as_bytes())?;
+    stream.flush()
+}
+/// 读取并丢弃请求头，直到空行；请求内容不影响响应，只需避免在客户端发送完之前关闭连接。
+fn read_request_head(stream: &mut TcpStream) -> io::Result<()> {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        head.extend_from_slice(&buf[..n]);
+        if head.len() > MAX_REQUEST_HEAD_BYTES {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "HTTP 请求头过长",
+            ));
+        }
+    }
+    Ok(())
+}
@@ -1,38 +1,153 @@
+use std::time::Duration;
+
 use quanta::Instant;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{KalmanModel, WarmupDecayConfig};
+/// 预测步长的上限。时间源异常时可能给出极大的步长，一步之内把协方差放大到失去意义。
+pub const MAX_PREDICT_DT: Duration = Duration::from_hours(24);
+/// 可持久化的滤波器状态：状态向量、协方差与当前过程噪声，用于跨重启保留学到的漂移率。
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FilterState {
+    model: KalmanModel,
+    x_hat: [f64; 2],
+    p_matrix: [[f64; 2]; 2],
+    process_noise_q: f64,
+}
+#[derive(Clone)]
 pub struct KalmanFilter {
+    model: KalmanModel,
     x_hat: [f64; 2],
     p_matrix: [[f64; 2]; 2],
     process_noise_q: f64,
     last_timestamp: Instant,
     nis_ema: f64,
     adaptation_rate_eta: f64,
+    adaptive_q: bool,
     nis_ema_alpha: f64,
+    min_dt: f64,
+    warmup_decay: Option<WarmupDecayConfig>,
+    update_count: usize,
+    max_nis_ema: f64,
+    max_drift: f64,
+    baseline_nis_ema: f64,
+    baseline_process_noise_q: f64,
+    min_process_noise_q: f64,
+    max_process_noise_q: f64,
+    runaway_resets: usize,
+    outlier_nis_threshold: f64,
+    rejected_outliers: usize,
 }
 impl KalmanFilter {
     pub fn new(
+        model: KalmanModel,
         initial_offset: f64,
         initial_uncertainty: f64,
         initial_process_noise_q: f64,
         adaptation_rate_eta: f64,
         nis_ema_alpha: f64,
+        min_dt: f64,
     ) -> Self {
+        let drift_uncertainty = match model {
+            KalmanModel::OffsetOnly => 0.0,
+            KalmanModel::OffsetDrift => initial_uncertainty,
+        };
         Self {
+            model,
             x_hat: [initial_offset, 0.0],
-            p_matrix: [[initial_uncertainty, 0.0], [0.0, initial_uncertainty]],
+            p_matrix: [[initial_uncertainty, 0.0], [0.0, drift_uncertainty]],
             process_noise_q: initial_process_noise_q,
             last_timestamp: Instant::now(),
             nis_ema: 1.0,
             adaptation_rate_eta,
+            adaptive_q: true,
             nis_ema_alpha,
+            min_dt,
+            warmup_decay: None,
+            update_count: 0,
+            max_nis_ema: f64::INFINITY,
+            max_drift: f64::INFINITY,
+            baseline_nis_ema: 1.0,
+            baseline_process_noise_q: initial_process_noise_q,
+            min_process_noise_q: 0.0,
+            max_process_noise_q: f64::INFINITY,
+            runaway_resets: 0,
+            outlier_nis_threshold: f64::INFINITY,
+            rejected_outliers: 0,
         }
     }
 
-    fn predict(&self, dt: f64) -> ([f64; 2], [[f64; 2]; 2]) {
-        let f_matrix = [[1.0, dt], [0.0, 1.0]];
+    /// 改为固定的过程噪声 `q` 并关闭自适应，用于派生保守的参考滤波器。
+    pub const fn with_fixed_process_noise(mut self, q: f64) -> Self {
+        self.process_noise_q = q;
+        self.baseline_process_noise_q = q;
+        self.adaptation_rate_eta = 0.0;
+        self
+    }
+
+    /// 设置是否按 NIS 自适应过程噪声。关闭时过程噪声固定为初值，校正后不再计算 NIS 平均。
+    pub const fn with_adaptive_q(mut self, adaptive_q: bool) -> Self {
+        self.adaptive_q = adaptive_q;
+        self
+    }
+
+    /// 设置自适应过程噪声的允许范围，`None` 表示该侧不限制。
+    pub fn with_process_noise_range(mut self, min: Option<f64>, max: Option<f64>) -> Self {
+        self.min_process_noise_q = min.unwrap_or(0.0);
+        self.max_process_noise_q = max.unwrap_or(f64::INFINITY);
+        self
+    }
+
+    /// 设置 NIS 指数平均的初值。默认 1.0 为调校良好时的期望值；已知环境噪声较大时，
+    /// 取更大的初值可避免最初几分钟过程噪声的剧烈自适应。
+    pub const fn with_initial_nis_ema(mut self, initial_nis_ema: f64) -> Self {
+        self.nis_ema = initial_nis_ema;
+        self.baseline_nis_ema = initial_nis_ema;
+        self
+    }
+
+    /// 设置 NIS 指数平均的上限，达到上限时视为自适应失控并恢复初始的 NIS 平均与过程噪声。
+    pub const fn with_max_nis_ema(mut self, max_nis_ema: f64) -> Self {
+        self.max_nis_ema = max_nis_ema;
+        self
+    }
+
+    /// 限制漂移率估计的绝对值（ppm）。与仅作用于显示的限幅不同，它直接约束状态中的漂移分量，
+    /// 因而约束预测、样本时差补偿与保持期间实际使用的漂移率；`None` 表示不限制。
+    pub fn with_max_drift_ppm(mut self, max_drift_ppm: Option<f64>) -> Self {
+        self.max_drift = max_drift_ppm.map_or(f64::INFINITY, |ppm| ppm / 1_000_000.0);
+        self
+    }
+
+    /// 设置离群测量的 NIS 门限：单个测量的 NIS 超过门限时只做预测、跳过校正；`None` 表示不拒绝。
+    pub fn with_outlier_nis_threshold(mut self, threshold: Option<f64>) -> Self {
+        self.outlier_nis_threshold = threshold.unwrap_or(f64::INFINITY);
+        self
+    }
+
+    /// 在最初若干次更新中额外收缩协方差，加快早期收敛。
+    pub const fn with_warmup_decay(mut self, warmup_decay: Option<WarmupDecayConfig>) -> Self {
+        self.warmup_decay = warmup_decay;
+        self
+    }
+
+    fn transition(&self, dt: f64) -> ([[f64; 2]; 2], [[f64; 2]; 2]) {
         let q = self.process_noise_q;
-        let dt2 = dt * dt;
-        let dt3 = dt2 * dt;
-        let q_matrix = [[dt3 / 3.0 * q, dt2 / 2.0 * q], [dt2 / 2.0 * q, dt * q]];
+        match self.model {
+            KalmanModel::OffsetOnly => ([[1.0, 0.0], [0.0, 0.0]], [[dt * q, 0.0], [0.0, 0.0]]),
+            KalmanModel::OffsetDrift => {
+                let dt2 = dt * dt;
+                let dt3 = dt2 * dt;
+                (
+                    [[1.0, dt], [0.0, 1.0]],
+                    [[dt3 / 3.0 * q, dt2 / 2.0 * q], [dt2 / 2.0 * q, dt * q]],
+                )
+            }
+        }
+    }
+
+    fn predict(&self, dt: f64) -> ([f64; 2], [[f64; 2]; 2]) {
+        let (f_matrix, q_matrix) = self.transition(dt);
         let x_hat_predicted = [
             self.x_hat[0].mul_add(f_matrix[0][0], self.x_hat[1] * f_matrix[0][1]),
             self.x_hat[0].mul_add(f_matrix[1][0], self.x_hat[1] * f_matrix[1][1]),
@@ -64,6 +179,19 @@ impl KalmanFilter {
         (x_hat_predicted, p_predicted)
     }
 
+    /// 测量相对预测的新息及其方差 `S = P₀₀ + R`。
+    fn innovation(
+        measurement: f64,
+        measurement_noise_r: f64,
+        x_hat_predicted: [f64; 2],
+        p_predicted: [[f64; 2]; 2],
+    ) -> (f64, f64) {
+        (
+            measurement - x_hat_predicted[0],
+            p_predicted[0][0] + measurement_noise_r,
+        )
+    }
+
     fn correct(
         &mut self,
         measurement: f64,
@@ -92,7 +220,9 @@ impl KalmanFilter {
             [k_gain[1] * h_matrix[0], k_gain[1] * h_matrix[1]],
         ];
         let ikh = [[1.0 - kh[0][0], -kh[0][1]], [-kh[1][0], 1.0 - kh[1][1]]];
-        self.p_matrix = [
+        // Joseph 形式 (I-KH)P(I-KH)ᵀ + KRKᵀ：与简化的 (I-KH)P 在数学上等价，
+        // 但对增益的舍入误差不敏感，长期运行中协方差保持对称正定，不会出现负方差。
+        let ikh_p = [
             [
                 ikh[0][0].mul_add(p_predicted[0][0], ikh[0][1] * p_predicted[1][0]),
                 ikh[0][0].mul_add(p_predicted[0][1], ikh[0][1] * p_predicted[1][1]),
@@ -102,22 +232,140 @@ impl KalmanFilter {
                 ikh[1][0].mul_add(p_predicted[0][1], ikh[1][1] * p_predicted[1][1]),
             ],
         ];
+        let joseph = |i: usize, j: usize| {
+            let krk_t = k_gain[i] * measurement_noise_r * k_gain[j];
+            ikh_p[i][0].mul_add(ikh[j][0], ikh_p[i][1].mul_add(ikh[j][1], krk_t))
+        };
+        self.p_matrix = [[joseph(0, 0), joseph(0, 1)], [joseph(1, 0), joseph(1, 1)]];
+        if !self.adaptive_q {
+            return;
+        }
         let nis = y * y / s;
         self.nis_ema = (1.0 - self.nis_ema_alpha).mul_add(self.nis_ema, self.nis_ema_alpha * nis);
+        // 测量持续与预测不符时 NIS 平均不断增大，Q 随之指数膨胀且无法自行恢复；
+        // 达到上限即视为失控，把自适应状态恢复到初始值重新开始。
+        if self.nis_ema >= self.max_nis_ema {
+            self.nis_ema = self.baseline_nis_ema;
+            self.process_noise_q = self.baseline_process_noise_q;
+            self.runaway_resets += 1;
+            return;
+        }
         let factor = (self.adaptation_rate_eta * (self.nis_ema - 1.0)).exp();
-        self.process_noise_q *= factor;
+        // 一阵坏样本可让 Q 膨胀一个数量级，之后很久都难以回落，因此限制在配置的范围内。
+        self.process_noise_q = (self.process_noise_q * factor)
+            .clamp(self.min_process_noise_q, self.max_process_noise_q);
     }
 
-    pub fn update(&mut self, measurement: f64, measurement_noise_r: f64) -> f64 {
-        let dt = self.last_timestamp.elapsed().as_secs_f64();
-        self.last_timestamp = Instant::now();
+    /// 以显式给定的时间步长更新，步长由调用方按配置的时间来源计算，回放历史样本时取样本间隔。
+    pub fn update_with_dt(&mut self, measurement: f64, measurement_noise_r: f64, dt: f64) -> f64 {
+        // 突发样本间隔接近 0 时 Q 项随之消失，协方差不再增长，滤波器会变得过度自信；
+        // 因此预测步长至少取 min_dt，保证每次更新前都传播一定的不确定度。
+        // 时间源异常给出的负值、非有限值或极大步长同样在此截断。
+        let dt = if dt.is_finite() {
+            dt.clamp(self.min_dt, MAX_PREDICT_DT.as_secs_f64())
+        } else {
+            self.min_dt
+        };
         let (x_hat_predicted, p_predicted) = self.predict(dt);
+        // 新息远超预测不确定度的单个测量（如配置错误的服务器）直接丢弃，不参与校正与自适应；
+        // 预测照常进行，协方差随之增大，真实的时间变化最终仍会落入门限内被接受。
+        let (y, s) = Self::innovation(
+            measurement,
+            measurement_noise_r,
+            x_hat_predicted,
+            p_predicted,
+        );
+        if y * y / s > self.outlier_nis_threshold {
+            self.x_hat = x_hat_predicted;
+            self.p_matrix = p_predicted;
+            self.rejected_outliers += 1;
+            return self.x_hat[0];
+        }
         self.correct(
             measurement,
             measurement_noise_r,
             x_hat_predicted,
             p_predicted,
         );
+        self.x_hat[1] = self.x_hat[1].clamp(-self.max_drift, self.max_drift);
+        self.apply_warmup_decay();
+        self.x_hat[0]
+    }
+
+    /// 预热期内按计划收缩协方差：第 k 次更新的系数从 `factor` 线性过渡到 1，
+    /// 预热结束时收缩消失，平滑地交还给常规的卡尔曼收敛。
+    fn apply_warmup_decay(&mut self) {
+        let Some(warmup) = self.warmup_decay else {
+            return;
+        };
+        if self.update_count >= warmup.updates {
+            return;
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let progress = self.update_count as f64 / warmup.updates as f64;
+        self.update_count += 1;
+        self.inflate_covariance((1.0 - warmup.factor).mul_add(progress, warmup.factor));
+    }
+
+    /// 重新以当前时刻作为下一次更新的时间基准，丢弃可能失真的步长。
+    pub fn reset_timestamp(&mut self) {
+        self.last_timestamp = Instant::now();
+    }
+
+    /// 以给定的漂移率（ppm）替换当前估计，并以当前时刻为新的时间基准，用于从失稳状态恢复。
+    pub fn restore_drift(&mut self, drift_ppm: f64) {
+        self.x_hat[1] = (drift_ppm / 1_000_000.0).clamp(-self.max_drift, self.max_drift);
+        self.reset_timestamp();
+    }
+
+    pub const fn state(&self) -> FilterState {
+        FilterState {
+            model: self.model,
+            x_hat: self.x_hat,
+            p_matrix: self.p_matrix,
+            process_noise_q: self.process_noise_q,
+        }
+    }
+
+    /// 恢复保存的状态，并以当前时刻为新的时间基准。模型与当前配置不同，或数值非有限、
+    /// 方差为负、过程噪声不为正时不做任何改变并返回 `false`。
+    pub fn restore_state(&mut self, state: &FilterState) -> bool {
+        let finite = state
+            .x_hat
+            .iter()
+            .chain(state.p_matrix.iter().flatten())
+            .all(|value| value.is_finite());
+        if state.model != self.model
+            || !finite
+            || state.p_matrix[0][0] < 0.0
+            || state.p_matrix[1][1] < 0.0
+            || !(state.process_noise_q.is_finite() && state.process_noise_q > 0.0)
+        {
+            return false;
+        }
+        self.x_hat = state.x_hat;
+        self.x_hat[1] = self.x_hat[1].clamp(-self.max_drift, self.max_drift);
+        self.p_matrix = state.p_matrix;
+        // 固定过程噪声时沿用配置的初值，不采用保存的自适应结果。
+        if self.adaptive_q {
+            self.process_noise_q = state
+                .process_noise_q
+                .clamp(self.min_process_noise_q, self.max_process_noise_q);
+        }
+        self.reset_timestamp();
+        true
+    }
+
+    /// 软重置：按倍数放大协方差，让滤波器更信任接下来的测量以便快速重新收敛。
+    pub fn inflate_covariance(&mut self, factor: f64) {
+        for row in &mut self.p_matrix {
+            for value in row {
+                *value *= factor;
+            }
+        }
+    }
+
+    pub const fn get_offset(&self) -> f64 {
         self.x_hat[0]
     }
 
@@ -125,7 +373,126 @@ impl KalmanFilter {
         self.x_hat[1] * 1_000_000.0
     }
 
+    pub const fn get_offset_variance(&self) -> f64 {
+        self.p_matrix[0][0]
+    }
+
+    pub const fn get_drift_variance(&self) -> f64 {
+        self.p_matrix[1][1]
+    }
+
+    /// 偏移估计的标准差（秒）。
+    pub fn get_offset_uncertainty(&self) -> f64 {
+        self.p_matrix[0][0].max(0.0).sqrt()
+    }
+
+    /// 漂移估计的标准差（秒/秒）。
+    pub fn get_drift_uncertainty(&self) -> f64 {
+        self.p_matrix[1][1].max(0.0).sqrt()
+    }
+
+    pub fn since_last_update(&self) -> f64 {
+        self.last_timestamp.elapsed().as_secs_f64()
+    }
+
+    /// 因 NIS 平均达到上限而重置自适应状态的累计次数。
+    pub const fn runaway_resets(&self) -> usize {
+        self.runaway_resets
+    }
+
+    /// 因 NIS 超过离群门限而跳过校正的累计测量数。
+    pub const fn rejected_outliers(&self) -> usize {
+        self.rejected_outliers
+    }
+
     pub const fn get_process_noise_q(&self) -> f64 {
         self.process_noise_q
     }
 }
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+
+    use super::*;
+
+    const INITIAL_Q: f64 = 5e-10;
+
+    fn filter() -> KalmanFilter {
+        KalmanFilter::new(
+            KalmanModel::OffsetDrift,
+            0.0,
+            10.0,
+            INITIAL_Q,
+            0.05,
+            0.05,
+            0.01,
+        )
+    }
+
+    #[test]
+    fn joseph_update_matches_the_simple_form() {
+        let mut filter = filter();
+        let (x_hat_predicted, p_predicted) = filter.predict(1.0);
+        let r = 1e-3;
+        filter.correct(0.01, r, x_hat_predicted, p_predicted);
+        let s = p_predicted[0][0] + r;
+        let k = [p_predicted[0][0] / s, p_predicted[1][0] / s];
+        let simple = [
+            [
+                (1.0 - k[0]) * p_predicted[0][0],
+                (1.0 - k[0]) * p_predicted[0][1],
+            ],
+            [
+                k[1].mul_add(-p_predicted[0][0], p_predicted[1][0]),
+                k[1].mul_add(-p_predicted[0][1], p_predicted[1][1]),
+            ],
+        ];
+        for (row, expected_row) in filter.p_matrix.iter().zip(simple) {
+            for (value, expected) in row.iter().zip(expected_row) {
+                assert!((value - expected).abs() <= 1e-9 * expected.abs().max(1e-12));
+            }
+        }
+    }
+
+    #[test]
+    fn covariance_stays_positive_and_symmetric_over_long_runs() {
+        let mut rng = StdRng::seed_from_u64(0x4b48_524f);
+        let mut filter = filter();
+        for step in 0..100_000_u32 {
+            let noise: f64 = rng.random_range(-0.005..0.005);
+            let measurement = f64::from(step).mul_add(2e-5, noise);
+            let r = rng.random_range(1e-7..1e-4);
+            filter.update_with_dt(measurement, r, rng.random_range(0.5..2.0));
+            let p = filter.p_matrix;
+            assert!(p[0][0] > 0.0 && p[1][1] > 0.0, "step {step}: {p:?}");
+            let tolerance = 1e-9 * p[0][0].abs().max(p[1][1].abs());
+            assert!((p[0][1] - p[1][0]).abs() <= tolerance, "step {step}: {p:?}");
+        }
+    }
+
+    #[test]
+    fn sustained_high_innovation_resets_adaptation() {
+        let mut filter = filter().with_max_nis_ema(100.0);
+        for step in 0..10_000 {
+            // 测量在 ±1 秒之间来回跳动而测量噪声极小，新息始终远超预测的不确定度。
+            let measurement = if step % 2 == 0 { 1.0 } else { -1.0 };
+            let resets = filter.runaway_resets();
+            filter.update_with_dt(measurement, 1e-8, 1.0);
+            assert!(filter.nis_ema < 100.0);
+            if filter.runaway_resets() > resets {
+                assert!((filter.get_process_noise_q() - INITIAL_Q).abs() < f64::EPSILON);
+            }
+        }
+        assert!(filter.runaway_resets() > 0);
+        assert!(filter.get_process_noise_q().is_finite());
+    }
+
+    #[test]
+    fn consistent_measurements_never_reset() {
+        let mut filter = filter().with_max_nis_ema(100.0);
+        for _ in 0..1_000 {
+            filter.update_with_dt(0.0, 1e-6, 1.0);
+        }
+        assert_eq!(filter.runaway_resets(), 0);
+    }
+}
@@ -0,0 +1,52 @@
+//! 程序时钟与 NTP 同步的库接口，供把校正后的时间嵌入其他程序使用。
+//!
+//! [`SyncService`] 在后台线程中完成同步与滤波，不向标准输出打印，也不安装 Ctrl+C 处理器；
+//! [`ProgramClock::now`] 可随时读取校正后的时间。同包的 `khronos` 程序也基于这些模块构建。
+// 公开的内部模块原本只供本包的程序使用，不为其逐一补充面向库用户的文档段落与 must_use 标注。
+#![allow(
+    clippy::must_use_candidate,
+    clippy::missing_errors_doc,
+    clippy::missing_panics_doc,
+    clippy::return_self_not_must_use
+)]
+mod accuracy;
+mod broadcast;
+mod clock_step;
+pub mod config;
+mod cross_check;
+pub mod demo;
+mod drift_latch;
+mod exec_hook;
+pub mod http_time;
+mod http_time_server;
+pub mod kalman_filter;
+mod local_source;
+pub mod ntp;
+mod ntpstats;
+mod path_change;
+pub mod program_clock;
+mod rate_limit;
+mod rfc868_server;
+mod sanity;
+pub mod selection;
+#[cfg(feature = "serial-output")]
+mod serial_output;
+mod server_handle;
+mod server_step;
+pub mod service;
+pub mod startup_state;
+pub mod stats;
+mod structured_log;
+pub mod synchronizer;
+mod temperature;
+pub mod time_file;
+pub mod timescale;
+mod update_quota;
+pub mod version;
+mod watchdog;
+pub use config::{AppConfig, NtpConfig};
+pub use kalman_filter::KalmanFilter;
+pub use ntp::query_ntp;
+pub use program_clock::ProgramClock;
+pub use service::SyncService;
+pub use version::{BuildInfo, build_info};
@@ -0,0 +1,165 @@
+use std::{
+    io::{self, ErrorKind},
+    process::Command,
+    sync::{Arc, Mutex, mpsc},
+    thread,
+    time::Duration,
+};
+
+use chrono::Utc;
+
+use crate::{
+    config::{LocalSourceConfig, LocalSourceKind},
+    ntp::{NtpSample, SyncMessage},
+    program_clock::ProgramClock,
+};
+pub struct LocalEstimate {
+    pub correction_secs: f64,
+    pub error_secs: f64,
+}
+pub trait LocalSource: Send {
+    fn name(&self) -> &'static str;
+    fn read_estimate(&self) -> io::Result<LocalEstimate>;
+}
+pub struct Chrony;
+impl LocalSource for Chrony {
+    fn name(&self) -> &'static str {
+        "chronyd"
+    }
+
+    fn read_estimate(&self) -> io::Result<LocalEstimate> {
+        let output = run_command("chronyc", &["-c", "tracking"])?;
+        parse_chrony_tracking(&output)
+    }
+}
+pub struct Timesyncd;
+impl LocalSource for Timesyncd {
+    fn name(&self) -> &'static str {
+        "systemd-timesyncd"
+    }
+
+    fn read_estimate(&self) -> io::Result<LocalEstimate> {
+        let output = run_command("timedatectl", &["timesync-status"])?;
+        parse_timesyncd_status(&output)
+    }
+}
+fn run_command(program: &str, args: &[&str]) -> io::Result<String> {
+    let output = Command::new(program).args(args).output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "{program} 执行失败: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    String::from_utf8(output.stdout)
+        .map_err(|_| io::Error::new(ErrorKind::InvalidData, format!("{program} 输出不是 UTF-8")))
+}
+fn parse_chrony_tracking(output: &str) -> io::Result<LocalEstimate> {
+    let fields: Vec<&str> = output.trim().split(',').collect();
+    let field = |index: usize, what: &str| -> io::Result<f64> {
+        fields
+            .get(index)
+            .and_then(|value| value.parse::<f64>().ok())
+            .ok_or_else(|| {
+                io::Error::new(ErrorKind::InvalidData, format!("chronyc 输出缺少 {what}"))
+            })
+    };
+    let system_time = field(4, "System time")?;
+    let root_delay = field(10, "Root delay")?;
+    let root_dispersion = field(11, "Root dispersion")?;
+    Ok(LocalEstimate {
+        correction_secs: system_time,
+        error_secs: root_delay.mul_add(0.5, root_dispersion),
+    })
+}
+fn parse_timesyncd_status(output: &str) -> io::Result<LocalEstimate> {
+    let value_of = |key: &str| {
+        output.lines().find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            (name.trim() == key).then(|| value.trim())
+        })
+    };
+    let offset = value_of("Offset")
+        .map(parse_timespan)
+        .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "timesyncd 尚未完成同步"))??;
+    let delay = value_of("Delay")
+        .map(parse_timespan)
+        .transpose()?
+        .unwrap_or(0.0);
+    Ok(LocalEstimate {
+        correction_secs: 0.0,
+        error_secs: delay.mul_add(0.5, offset.abs()),
+    })
+}
+fn parse_timespan(text: &str) -> io::Result<f64> {
+    let split = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-' || c == '+'))
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| io::Error::new(ErrorKind::InvalidData, format!("无法解析时间值: {text}")))?;
+    let scale = match unit.trim() {
+        "ns" => 1e-9,
+        "us" | "μs" | "µs" => 1e-6,
+        "ms" => 1e-3,
+        "s" | "" => 1.0,
+        "min" => 60.0,
+        other => {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("未知的时间单位: {other}"),
+            ));
+        }
+    };
+    Ok(number * scale)
+}
+fn secs_to_duration(secs: f64) -> io::Result<chrono::Duration> {
+    let magnitude = chrono::Duration::from_std(Duration::from_secs_f64(secs.abs()))
+        .map_err(|e| io::Error::new(ErrorKind::InvalidData, e.to_string()))?;
+    Ok(if secs < 0.0 { -magnitude } else { magnitude })
+}
+fn measure(
+    source: &dyn LocalSource,
+    clock: &Arc<Mutex<ProgramClock>>,
+    noise_scale: f64,
+) -> io::Result<(chrono::Duration, chrono::Duration)> {
+    let estimate = source.read_estimate()?;
+    let reference = Utc::now() + secs_to_duration(estimate.correction_secs)?;
+    let offset = reference - clock.lock().unwrap().disciplined_now();
+    let delay = secs_to_duration(estimate.error_secs * noise_scale)?;
+    Ok((offset, delay))
+}
+pub fn create_source(kind: LocalSourceKind) -> Box<dyn LocalSource> {
+    match kind {
+        LocalSourceKind::Chrony => Box::new(Chrony),
+        LocalSourceKind::Timesyncd => Box::new(Timesyncd),
+    }
+}
+pub fn start_local_source_thread(
+    clock: Arc<Mutex<ProgramClock>>,
+    config: LocalSourceConfig,
+    tx: mpsc::Sender<SyncMessage>,
+) {
+    let source = create_source(config.kind);
+    thread::spawn(move || {
+        loop {
+            thread::sleep(config.poll_interval());
+            if tx
+                .send(SyncMessage::Syncing(source.name().to_owned()))
+                .is_err()
+            {
+                break;
+            }
+            let message = match measure(source.as_ref(), &clock, config.noise_scale) {
+                Ok((offset, delay)) => {
+                    SyncMessage::Success(NtpSample::new(source.name().to_owned(), offset, delay))
+                }
+                Err(e) => SyncMessage::Failed(source.name().to_owned(), e.to_string()),
+            };
+            if tx.send(message).is_err() {
+                break;
+            }
+        }
+    });
+}
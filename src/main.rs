@@ -1,10 +1,51 @@
 mod app;
-mod config;
-mod kalman_filter;
-mod ntp;
-mod program_clock;
-use std::io;
+mod cli;
+mod correlate;
+mod dashboard;
+mod display;
+mod evaluate;
+mod ntp_control;
+use khronos::{
+    config, demo, kalman_filter, ntp, program_clock, startup_state, stats, synchronizer, timescale,
+};
+use std::{io, path::Path};
 fn main() -> io::Result<()> {
+    match cli::Command::parse(std::env::args().skip(1))? {
+        cli::Command::Run { force, demo } => {
+            if demo
+                || (std::env::var_os(config::CONFIG_PATHS_ENV).is_none()
+                    && !Path::new(config::DEFAULT_CONFIG_PATH).exists())
+            {
+                if !demo {
+                    println!(
+                        "未找到 {}，使用内置默认配置进入演示模式。",
+                        config::DEFAULT_CONFIG_PATH
+                    );
+                }
+                app::run_demo(&config::AppConfig::builtin()?)
+            } else {
+                app::run(&load_config()?, force)
+            }
+        }
+        cli::Command::Now { format, utc } => {
+            app::print_now(&load_config()?, format.as_deref(), utc)
+        }
+        cli::Command::Once { format } => app::run_once(&load_config()?, format),
+        cli::Command::Probe { server } => app::probe(&load_config()?, &server),
+        cli::Command::Evaluate { log_path } => evaluate::run(&load_config()?.kalman, &log_path),
+        cli::Command::Correlate { log_path, points } => {
+            correlate::run(load_config()?.ui.history_size(), &log_path, points)
+        }
+        cli::Command::Version => {
+            println!("{}", khronos::build_info());
+            Ok(())
+        }
+    }
+}
+fn load_config() -> io::Result<config::AppConfig> {
     let config = config::AppConfig::load()?;
-    app::run(&config)
+    for warning in config.warnings() {
+        eprintln!("配置警告: {warning}");
+    }
+    Ok(config)
 }
@@ -1,16 +1,34 @@
 use std::{
+    error::Error,
+    fmt,
     io::{self, ErrorKind},
-    net::{ToSocketAddrs, UdpSocket},
-    sync::{Arc, Mutex, mpsc},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+    sync::{
+        Arc, Mutex, OnceLock,
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
     thread,
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use chrono::{DateTime, Utc};
+use nix::sys::socket::{setsockopt, sockopt};
 use quanta::Instant;
-use rand::Rng;
+use rand::{
+    Rng,
+    seq::{IndexedRandom, SliceRandom},
+};
 
-use crate::{config::NtpConfig, program_clock::ProgramClock};
+use crate::{
+    config::{HttpFallbackConfig, NTP_TIMESTAMP_SIZE, NtpConfig, RefidLoopPolicy, SampleCombine},
+    http_time,
+    program_clock::ProgramClock,
+    rate_limit::QueryRateLimiter,
+    sanity::SanityCheck,
+    selection::{NetworkChange, Quorum, ServerBackoff, ServerScores, ServerTiers},
+    watchdog::Heartbeat,
+};
 #[derive(Copy, Clone, Debug)]
 struct NtpTimestamp {
     seconds: u32,
@@ -35,7 +53,7 @@ impl NtpTimestamp {
         Ok(Self { seconds, fraction })
     }
 
-    fn to_system_time(self, config: &NtpConfig) -> io::Result<SystemTime> {
+    fn to_unix_picos(self, config: &NtpConfig) -> io::Result<i128> {
         let epoch_diff = config.unix_epoch_diff_u32()?;
         if self.seconds < epoch_diff {
             return Err(io::Error::new(
@@ -43,12 +61,14 @@ impl NtpTimestamp {
                 "NTP time is earlier than Unix epoch",
             ));
         }
-        let unix_secs = u64::from(self.seconds) - u64::from(epoch_diff);
-        let nanos = u32::try_from(
-            (u128::from(self.fraction) * 1_000_000_000u128) / u128::from(0x1_0000_0000u64),
-        )
-        .map_err(|_| io::Error::other("NTP fraction overflow"))?;
-        Ok(UNIX_EPOCH + Duration::new(unix_secs, nanos))
+        let unix_secs = i128::from(self.seconds - epoch_diff);
+        let fraction_picos = (i128::from(self.fraction) * 1_000_000_000_000) >> 32;
+        Ok(unix_secs * 1_000_000_000_000 + fraction_picos)
+    }
+
+    /// 全零时间戳表示服务器未填写该字段（如尚未同步或实现有缺陷）。
+    const fn is_null(self) -> bool {
+        self.seconds == 0 && self.fraction == 0
     }
 
     const fn from_bytes(bytes: [u8; 8]) -> Self {
@@ -56,112 +76,1365 @@ impl NtpTimestamp {
         let fraction = u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
         Self { seconds, fraction }
     }
+}
+/// 自 1900 年起的整秒数，即 NTP 时间戳的秒字段，与 RFC 868 TIME 协议的编码相同。
+pub fn seconds_since_1900(time: DateTime<Utc>, config: &NtpConfig) -> io::Result<u32> {
+    NtpTimestamp::from_chrono_utc(time, config).map(|timestamp| timestamp.seconds)
+}
+/// 将秒数编码为 NTP 短格式（16.16 定点数），超出范围时饱和。
+pub fn to_short_format(secs: f64) -> u32 {
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let encoded = (secs * 65_536.0).round().clamp(0.0, f64::from(u32::MAX)) as u32;
+    encoded
+}
+/// 启动发现阶段测得的服务器延迟，`delay_secs` 为 `None` 表示未响应。
+#[derive(Clone, Debug)]
+pub struct ServerRank {
+    pub server: String,
+    pub delay_secs: Option<f64>,
+}
+/// 一次成功的测量。`offset` 为参考时间减程序时钟，`measured_at` 为收到响应的时刻；
+/// 非 NTP 来源没有层级与根距离，对应字段为 `None`。
+#[derive(Clone, Debug)]
+pub struct NtpSample {
+    pub server: String,
+    pub offset: chrono::Duration,
+    pub delay: chrono::Duration,
+    pub stratum: Option<u8>,
+    pub root_distance_secs: Option<f64>,
+    pub measured_at: Instant,
+}
+impl NtpSample {
+    /// 非 NTP 来源的测量，以当前时刻作为测量时刻。
+    pub fn new(server: String, offset: chrono::Duration, delay: chrono::Duration) -> Self {
+        Self {
+            server,
+            offset,
+            delay,
+            stratum: None,
+            root_distance_secs: None,
+            measured_at: Instant::now(),
+        }
+    }
+}
+/// 服务器以 stratum 0 返回的 Kiss-o'-Death 包。`code` 为参考 ID 中的 ASCII 代码，
+/// 如 RATE（查询过于频繁）、DENY 与 RSTR（拒绝访问）；此时包中的时间戳没有意义。
+#[derive(Debug, Clone)]
+pub struct KissOfDeath {
+    pub code: String,
+}
+impl KissOfDeath {
+    /// 从查询错误中取出 Kiss-o'-Death，其他错误返回 `None`。
+    pub fn from_error(error: &io::Error) -> Option<&Self> {
+        error.get_ref()?.downcast_ref()
+    }
 
-    fn to_bytes(self) -> [u8; 8] {
-        let mut bytes = [0u8; 8];
-        bytes[0..4].copy_from_slice(&self.seconds.to_be_bytes());
-        bytes[4..8].copy_from_slice(&self.fraction.to_be_bytes());
-        bytes
+    pub fn is_rate(&self) -> bool {
+        self.code == "RATE"
+    }
+
+    pub fn denies_access(&self) -> bool {
+        matches!(self.code.as_str(), "DENY" | "RSTR")
+    }
+}
+impl fmt::Display for KissOfDeath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "服务器返回 Kiss-o'-Death（{}），样本无效", self.code)
     }
 }
+impl Error for KissOfDeath {}
+/// 同步器与同步线程共享的控制标志：暂停查询，以及同步器检测到网络路径切换的信号。
+#[derive(Clone, Default)]
+pub struct SyncControl {
+    pub paused: Arc<AtomicBool>,
+    pub network_changed: Arc<AtomicBool>,
+}
 pub enum SyncMessage {
+    Discovered(Vec<ServerRank>),
     Syncing(String),
-    Success(chrono::Duration, chrono::Duration),
+    Success(NtpSample),
+    Failed(String, String),
+    Notice(String, String),
 }
 pub fn query_ntp(
     server: &str,
     timeout: Duration,
     program_clock: &Arc<Mutex<ProgramClock>>,
     config: &NtpConfig,
-) -> io::Result<(chrono::Duration, chrono::Duration)> {
-    let addr = (server, config.port)
-        .to_socket_addrs()?
-        .next()
-        .ok_or_else(|| io::Error::other(format!("Cannot resolve NTP server: {server}")))?;
-    let socket = UdpSocket::bind("0.0.0.0:0")?;
-    socket.connect(addr)?;
-    socket.set_read_timeout(Some(timeout))?;
-    socket.set_write_timeout(Some(timeout))?;
+) -> io::Result<NtpSample> {
+    exchange(server, timeout, timeout, program_clock, config).map(|(sample, _)| sample)
+}
+/// 按层级从高到低、同层随机的顺序逐一查询，以第一台响应的服务器直接一步校正时钟，
+/// 不经过滤波器，返回所用的样本。
+pub fn quick_sync(
+    clock: &Arc<Mutex<ProgramClock>>,
+    ntp_config: &NtpConfig,
+) -> io::Result<NtpSample> {
+    let mut servers: Vec<&String> = ntp_config.servers.iter().collect();
+    servers.shuffle(&mut rand::rng());
+    servers.sort_by_key(|server| ntp_config.server_tier(server));
+    let mut last_error = None;
+    for server in servers {
+        match query_ntp(server, ntp_config.initial_sync_timeout(), clock, ntp_config) {
+            Ok(sample) => {
+                clock.lock().unwrap().apply_offset_stepped(sample.offset);
+                return Ok(sample);
+            }
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| io::Error::other("没有可用的 NTP 服务器")))
+}
+/// 同时查询多台服务器，按 Marzullo 算法取至少 `min_servers` 台一致的时间区间内延迟最小的样本，
+/// 单台出错或被篡改的服务器因此无法带偏时钟。响应的服务器不足法定数量时返回错误；
+/// 响应足够但时间区间互不一致时同样不接受时间，而是以 [`ErrorKind::InvalidData`] 单独报告分歧。
+pub fn query_best(
+    servers: &[&str],
+    min_servers: usize,
+    timeout: Duration,
+    program_clock: &Arc<Mutex<ProgramClock>>,
+    config: &NtpConfig,
+) -> io::Result<NtpSample> {
+    let results: Vec<io::Result<NtpSample>> = thread::scope(|scope| {
+        let mut queries = Vec::with_capacity(servers.len());
+        for &server in servers {
+            queries.push(scope.spawn(move || query_ntp(server, timeout, program_clock, config)));
+        }
+        queries
+            .into_iter()
+            .filter_map(|query| query.join().ok())
+            .collect()
+    });
+    let mut quorum = Quorum::new(min_servers);
+    let mut last_error = None;
+    for result in results {
+        match result {
+            Ok(sample) => quorum.record(sample),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    if let Some(sample) = quorum.agreed() {
+        return Ok(sample.clone());
+    }
+    Err(quorum_error(servers.len(), &quorum, last_error.as_ref()))
+}
+/// 未达成一致时的错误：响应不足法定数量与响应足够但区间不一致分别报告，后者为无效数据。
+fn quorum_error(queried: usize, quorum: &Quorum, last_error: Option<&io::Error>) -> io::Error {
+    if quorum.responded() < quorum.min_servers() {
+        let cause = last_error.map_or_else(String::new, |e| format!("，最后一个错误：{e}"));
+        return io::Error::other(format!(
+            "{queried} 台服务器中只有 {} 台响应，不足法定数量 {}{cause}",
+            quorum.responded(),
+            quorum.min_servers()
+        ));
+    }
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!(
+            "{queried} 台服务器中 {} 台响应，但最多 {} 台的时间区间一致，未达到法定数量 {}",
+            quorum.responded(),
+            quorum.agreeing(),
+            quorum.min_servers()
+        ),
+    )
+}
+/// 初始同步的一轮尝试，成功时一步校正时钟并返回 `true`。配置了法定数量时要求足够多台服务器一致，
+/// 不退回单台服务器或 HTTP 时间；否则查询一台随机服务器，不可达时尝试 HTTP 时间。
+/// `progress` 接收每一步的进度描述，交互程序据此刷新提示，它返回的错误会直接传出。
+pub fn initial_sync_round(
+    clock: &Arc<Mutex<ProgramClock>>,
+    ntp_config: &NtpConfig,
+    http_fallback: Option<&HttpFallbackConfig>,
+    progress: &mut impl FnMut(&str) -> io::Result<()>,
+) -> io::Result<bool> {
+    if let Some(min_servers) = ntp_config.discipline_quorum() {
+        return initial_sync_with_quorum(clock, ntp_config, min_servers, progress);
+    }
+    let server_index = rand::rng().random_range(0..ntp_config.servers.len());
+    let server = &ntp_config.servers[server_index];
+    progress(&format!("正在尝试从 {server} 进行初始同步..."))?;
+    if let Ok(sample) = query_ntp(server, ntp_config.initial_sync_timeout(), clock, ntp_config) {
+        clock.lock().unwrap().apply_offset_stepped(sample.offset);
+        return Ok(true);
+    }
+    if let Some(fallback) = http_fallback {
+        progress(&format!(
+            "NTP 不可达，尝试 {} ...",
+            http_time::source_name(fallback)
+        ))?;
+        if let Ok((initial_offset, _)) = http_time::query_http_date(fallback, clock) {
+            clock.lock().unwrap().apply_offset_stepped(initial_offset);
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+/// 法定数量模式下的初始同步：同时查询 `concurrent_queries` 台服务器，或依次查询服务器，
+/// 直到足够多台的时间区间一致才校正时钟。
+fn initial_sync_with_quorum(
+    clock: &Arc<Mutex<ProgramClock>>,
+    ntp_config: &NtpConfig,
+    min_servers: usize,
+    progress: &mut impl FnMut(&str) -> io::Result<()>,
+) -> io::Result<bool> {
+    if let Some(count) = ntp_config.concurrent_queries {
+        let servers = concurrent_servers(ntp_config, count);
+        progress(&format!(
+            "正在同时查询 {count} 台服务器进行初始同步（需 {min_servers} 台一致）..."
+        ))?;
+        let timeout = ntp_config.initial_sync_timeout();
+        let Ok(agreed) = query_best(&servers, min_servers, timeout, clock, ntp_config) else {
+            return Ok(false);
+        };
+        clock.lock().unwrap().apply_offset_stepped(agreed.offset);
+        return Ok(true);
+    }
+    let mut quorum = Quorum::new(min_servers);
+    for server in &ntp_config.servers {
+        progress(&format!(
+            "正在尝试从 {server} 进行初始同步（需 {min_servers} 台服务器一致）..."
+        ))?;
+        let Ok(sample) = query_ntp(server, ntp_config.initial_sync_timeout(), clock, ntp_config)
+        else {
+            continue;
+        };
+        quorum.record(sample);
+        if let Some(agreed) = quorum.agreed() {
+            clock.lock().unwrap().apply_offset_stepped(agreed.offset);
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+/// 同时查询用的服务器：按层级从高到低、同层随机排序后取前 `count` 台。
+pub fn concurrent_servers(ntp_config: &NtpConfig, count: usize) -> Vec<&str> {
+    let mut servers: Vec<&str> = ntp_config.servers.iter().map(String::as_str).collect();
+    servers.shuffle(&mut rand::rng());
+    servers.sort_by_key(|server| ntp_config.server_tier(server));
+    servers.truncate(count);
+    servers
+}
+const POLL_OFFSET: usize = 2;
+const PRECISION_OFFSET: usize = 3;
+const PRECISION_SAMPLES: usize = 1000;
+/// 本机单调时钟的精度（以 2 为底的对数秒），首次使用时测量一次：取连续读数间最小的非零间隔。
+fn local_precision() -> i8 {
+    static PRECISION: OnceLock<i8> = OnceLock::new();
+    *PRECISION.get_or_init(|| {
+        let mut resolution = Duration::MAX;
+        let mut previous = Instant::now();
+        for _ in 0..PRECISION_SAMPLES {
+            let current = Instant::now();
+            let step = current.saturating_duration_since(previous);
+            if !step.is_zero() {
+                resolution = resolution.min(step);
+            }
+            previous = current;
+        }
+        if resolution == Duration::MAX {
+            return -32;
+        }
+        #[allow(clippy::cast_possible_truncation)]
+        let precision = resolution.as_secs_f64().log2().ceil().clamp(-32.0, 0.0) as i8;
+        precision
+    })
+}
+/// 请求中的轮询间隔字段：周期同步基准间隔的以 2 为底的对数秒，按协议限制在 4 ~ 17。
+fn poll_exponent(config: &NtpConfig) -> i8 {
+    let base = (config.sync_interval_min() + config.sync_interval_max()) / 2;
+    #[allow(clippy::cast_possible_truncation)]
+    let exponent = base.as_secs_f64().max(1.0).log2().round().clamp(4.0, 17.0) as i8;
+    exponent
+}
+const REFERENCE_ID_OFFSET: usize = 12;
+const STRATUM_OFFSET: usize = 1;
+const MODE_SERVER: u8 = 4;
+const ROOT_DELAY_OFFSET: usize = 4;
+const ROOT_DISPERSION_OFFSET: usize = 8;
+/// 绑定发送查询用的本地套接字。配置了源端口范围时从范围内随机一处开始依次尝试，
+/// 跳过已被占用的端口，以穿越按源端口放行的防火墙；范围内全部被占用时报错。
+pub fn bind_client_socket(
+    ipv6: bool,
+    source_port_range: Option<(u16, u16)>,
+) -> io::Result<UdpSocket> {
+    let unspecified = if ipv6 {
+        IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+    } else {
+        IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+    };
+    let Some((first, last)) = source_port_range else {
+        return UdpSocket::bind((unspecified, 0));
+    };
+    let count = u32::from(last - first) + 1;
+    let start = rand::random_range(0..count);
+    for step in 0..count {
+        let port =
+            u16::try_from(u32::from(first) + (start + step) % count).map_err(io::Error::other)?;
+        match UdpSocket::bind((unspecified, port)) {
+            Ok(socket) => return Ok(socket),
+            Err(e) if e.kind() == ErrorKind::AddrInUse => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Err(io::Error::new(
+        ErrorKind::AddrInUse,
+        format!("source_port_range [{first}, {last}] 内的端口均已被占用"),
+    ))
+}
+/// 解析服务器地址，按解析结果的顺序（系统已按地址选择策略排好）逐个绑定同族的本地套接字并连接，
+/// 返回第一个成功的。服务器同时有 A 与 AAAA 记录时，本机协议栈不支持或没有路由的地址族会被跳过，
+/// 因此仅有 IPv6 或仅有 IPv4 的网络都能使用双栈服务器。
+pub fn connect_client_socket(
+    server: &str,
+    port: u16,
+    source_port_range: Option<(u16, u16)>,
+) -> io::Result<(UdpSocket, SocketAddr)> {
+    let mut last_error = None;
+    for addr in (server, port).to_socket_addrs()? {
+        let connected = bind_client_socket(addr.is_ipv6(), source_port_range)
+            .and_then(|socket| socket.connect(addr).map(|()| socket));
+        match connected {
+            Ok(socket) => return Ok((socket, addr)),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error
+        .unwrap_or_else(|| io::Error::other(format!("Cannot resolve NTP server: {server}"))))
+}
+/// 以 DSCP 标记发出的报文：写入 IPv4 的服务类型字节或 IPv6 的流量类别字节的高 6 位，
+/// 启用服务质量策略的网络据此优先转发，减少拥塞链路上的丢包与排队延迟。
+fn set_dscp(socket: &UdpSocket, ipv6: bool, dscp: u8) -> io::Result<()> {
+    let traffic_class = i32::from(dscp) << 2;
+    if ipv6 {
+        setsockopt(socket, sockopt::Ipv6TClass, &traffic_class)?;
+    } else {
+        setsockopt(socket, sockopt::Ipv4Tos, &traffic_class)?;
+    }
+    Ok(())
+}
+/// 完成一次 NTP 请求/响应交换；`write_timeout` 限制发送，`read_timeout` 为从发送起等待匹配响应的总时长。
+/// 发起时间戳不匹配的包被丢弃并继续等待，直到超时仍无匹配响应时报告为无效数据。
+fn exchange(
+    server: &str,
+    read_timeout: Duration,
+    write_timeout: Duration,
+    program_clock: &Arc<Mutex<ProgramClock>>,
+    config: &NtpConfig,
+) -> io::Result<(NtpSample, Vec<String>)> {
+    let (socket, addr) = connect_client_socket(server, config.port, config.source_port_range)?;
+    if let Some(dscp) = config.dscp {
+        set_dscp(&socket, addr.is_ipv6(), dscp)?;
+    }
+    socket.set_read_timeout(Some(read_timeout))?;
+    socket.set_write_timeout(Some(write_timeout))?;
     let mut req = vec![0u8; config.packet_size];
     req[0] = 0b00_100_011;
-    let t1 = { program_clock.lock().unwrap().now() };
-    let t1_ntp = NtpTimestamp::from_chrono_utc(t1, config)
-        .map_err(|e| io::Error::other(format!("Cannot convert program time: {e}")))?;
-    let tx_offset = config.transmit_timestamp_offset;
-    req[tx_offset..tx_offset + 8].copy_from_slice(&t1_ntp.to_bytes());
+    // 填写轮询间隔与本机精度，部分服务器与监控工具会记录或校验这些客户端字段。
+    req[POLL_OFFSET] = poll_exponent(config).cast_unsigned();
+    req[PRECISION_OFFSET] = local_precision().cast_unsigned();
+    // 发送时间戳字段不填本机时间，而是填满密码学安全的随机 nonce：服务器只需原样回显到
+    // 发起时间戳字段，路径外的攻击者无法靠猜测本机时钟伪造匹配的响应，请求也不再泄露本机时间。
+    // 这与协议中 T1 取发送时刻的做法不同，偏移与往返计算只使用本地记录的 t1。
+    let nonce: [u8; NTP_TIMESTAMP_SIZE] = rand::random();
+    timestamp_field(&mut req, config.transmit_timestamp_offset)?.copy_from_slice(&nonce);
+    // 请求准备好后紧挨发送读取 t1，不把生成 nonce 的耗时计入往返。
+    let t1 = { program_clock.lock().unwrap().disciplined_now() };
     let send_instant = Instant::now();
     socket.send(&req)?;
     let mut buf = vec![0u8; config.packet_size];
-    let n = socket.recv(&mut buf)?;
-    let recv_instant = Instant::now();
-    let min_size = config
+    let mut discarded = 0;
+    let (n, recv_instant) = loop {
+        let remaining = read_timeout
+            .checked_sub(send_instant.elapsed())
+            .filter(|remaining| !remaining.is_zero())
+            .ok_or_else(|| unmatched_response_error(discarded))?;
+        socket.set_read_timeout(Some(remaining))?;
+        let n = match socket.recv(&mut buf) {
+            Ok(n) => n,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                return Err(unmatched_response_error(discarded));
+            }
+            Err(e) => return Err(e),
+        };
+        let recv_instant = Instant::now();
+        match match_response(&buf[..n], &nonce, config) {
+            ResponseMatch::Matched => break (n, recv_instant),
+            ResponseMatch::RequestEcho => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    "收到的是本机请求的原样回显（发送时间戳未改变），不是服务器响应",
+                ));
+            }
+            ResponseMatch::Unmatched => discarded += 1,
+        }
+    };
+    discarded += drain_socket(&socket);
+    let stratum = check_response_header(&buf[..n], config)?;
+    let mut notices = Vec::new();
+    if discarded > 0 {
+        notices.push(format!("丢弃了 {discarded} 个重复或过期的响应包"));
+    }
+    if let Some(notice) = check_refid_loop(&buf[..n], &socket, config)? {
+        notices.push(notice);
+    }
+    let round_trip_picos = i128::try_from(recv_instant.duration_since(send_instant).as_nanos())
+        .map_err(|_| io::Error::other("Round trip duration overflow"))?
+        * 1000;
+    let t1_picos = utc_to_picos(t1)?;
+    let t4_picos = t1_picos + round_trip_picos;
+    // 直接在皮秒精度下计算，保留 NTP 小数字段约 233ps 的分辨率，最后才舍入到纳秒。
+    let t2_picos = read_timestamp_picos(&buf[..n], config.recv_timestamp_offset, config)?;
+    let t3_picos = read_timestamp_picos(&buf[..n], config.transmit_timestamp_offset, config)?;
+    let offset = picos_to_duration(i128::midpoint(t2_picos - t1_picos, t3_picos - t4_picos));
+    let delay = picos_to_duration((t4_picos - t1_picos) - (t3_picos - t2_picos));
+    let root_distance_secs = read_short_format(&buf[..n], ROOT_DELAY_OFFSET)
+        .zip(read_short_format(&buf[..n], ROOT_DISPERSION_OFFSET))
+        .map(|(root_delay, root_dispersion)| {
+            (root_delay + delay.as_seconds_f64().max(0.0)).mul_add(0.5, root_dispersion)
+        });
+    let sample = NtpSample {
+        server: server.to_owned(),
+        offset,
+        delay,
+        stratum,
+        root_distance_secs,
+        measured_at: recv_instant,
+    };
+    Ok((sample, notices))
+}
+/// 收到的数据报与本次请求的关系。
+#[derive(Debug, PartialEq, Eq)]
+enum ResponseMatch {
+    /// 发起时间戳回显了本次请求的 nonce。
+    Matched,
+    /// 发送时间戳仍是本次请求的 nonce，是请求被原样反射回来。
+    RequestEcho,
+    /// 发起时间戳与 nonce 不符或包过短：先前请求的迟到响应，或路径外伪造的回复。
+    Unmatched,
+}
+/// 按本次请求的 nonce 对收到的数据报 `packet`（只含实际收到的字节）分类。
+fn match_response(packet: &[u8], nonce: &[u8], config: &NtpConfig) -> ResponseMatch {
+    let field = |offset: usize| {
+        offset
+            .checked_add(NTP_TIMESTAMP_SIZE)
+            .and_then(|end| packet.get(offset..end))
+    };
+    if field(config.transmit_timestamp_offset) == Some(nonce) {
+        ResponseMatch::RequestEcho
+    } else if field(config.origin_timestamp_offset()) == Some(nonce) {
+        ResponseMatch::Matched
+    } else {
+        ResponseMatch::Unmatched
+    }
+}
+/// 超时前没有收到发起时间戳与本次请求匹配的响应。不匹配的包不会立即报错，而是丢弃后继续等待：
+/// 否则抢先到达的一个伪造包就能压制真正的响应。期间收到过不匹配的包时按无效数据报告，
+/// 它们可能是先前请求的迟到响应，也可能是路径外伪造的回复。
+fn unmatched_response_error(discarded: usize) -> io::Error {
+    if discarded == 0 {
+        return io::Error::new(ErrorKind::TimedOut, "等待匹配的 NTP 响应超时");
+    }
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!("收到 {discarded} 个发起时间戳与请求不匹配的响应，超时前没有有效响应"),
+    )
+}
+/// 读取 NTP 短格式（16.16 定点秒）字段，用于根延迟与根离散度。
+fn read_short_format(packet: &[u8], offset: usize) -> Option<f64> {
+    let bytes = packet.get(offset..offset.checked_add(4)?)?;
+    let raw = u32::from_be_bytes(bytes.try_into().ok()?);
+    Some(f64::from(raw) / 65_536.0)
+}
+/// 检查响应的长度、模式与层级，返回服务器的 stratum。
+/// 收到 Kiss-o'-Death 包时返回携带 [`KissOfDeath`] 的错误。
+fn check_response_header(packet: &[u8], config: &NtpConfig) -> io::Result<Option<u8>> {
+    let required_len = required_response_len(config);
+    if packet.len() < required_len {
+        return Err(io::Error::new(
+            ErrorKind::UnexpectedEof,
+            format!(
+                "NTP 响应只有 {} 字节，不足以覆盖配置的时间戳字段（需要 {required_len} 字节）",
+                packet.len()
+            ),
+        ));
+    }
+    let stratum = packet.get(STRATUM_OFFSET).copied();
+    let mode = packet[0] & 0b111;
+    if mode != MODE_SERVER {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "响应的模式为 {mode}，不是服务器响应（模式 {MODE_SERVER}），可能是中间设备回显了请求"
+            ),
+        ));
+    }
+    // stratum 0 表示 Kiss-o'-Death，参考 ID 为 ASCII 代码。
+    if stratum == Some(0) {
+        let code = packet
+            .get(REFERENCE_ID_OFFSET..REFERENCE_ID_OFFSET + 4)
+            .map(|code| {
+                String::from_utf8_lossy(code)
+                    .trim_end_matches(['\0', ' '])
+                    .to_owned()
+            })
+            .unwrap_or_default();
+        return Err(io::Error::new(
+            ErrorKind::ConnectionRefused,
+            KissOfDeath { code },
+        ));
+    }
+    if let Some(stratum) = stratum
+        && stratum > config.max_acceptable_stratum()
+    {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "服务器 stratum {stratum} 超过 max_acceptable_stratum ({})，已拒绝",
+                config.max_acceptable_stratum()
+            ),
+        ));
+    }
+    Ok(stratum)
+}
+/// 响应至少需要的长度：覆盖所有配置的时间戳字段。
+fn required_response_len(config: &NtpConfig) -> usize {
+    config
         .recv_timestamp_offset
         .max(config.transmit_timestamp_offset)
-        .saturating_add(8);
-    if n < min_size {
+        .max(config.origin_timestamp_offset())
+        .saturating_add(NTP_TIMESTAMP_SIZE)
+}
+/// 取出请求包中 `offset` 处的时间戳字段，越界时报错而不是 panic。
+fn timestamp_field(packet: &mut [u8], offset: usize) -> io::Result<&mut [u8]> {
+    let len = packet.len();
+    offset
+        .checked_add(NTP_TIMESTAMP_SIZE)
+        .and_then(|end| packet.get_mut(offset..end))
+        .ok_or_else(|| {
+            io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("时间戳偏移 {offset} 超出 {len} 字节的数据包"),
+            )
+        })
+}
+pub fn utc_to_picos(time: DateTime<Utc>) -> io::Result<i128> {
+    let nanos = time
+        .timestamp_nanos_opt()
+        .ok_or_else(|| io::Error::other("Program time is out of range"))?;
+    Ok(i128::from(nanos) * 1000)
+}
+/// 读取数据包中 `offset` 处的 NTP 时间戳，并换算为自 Unix 纪元起的皮秒数；全零时间戳直接拒绝。
+pub fn read_timestamp_picos(packet: &[u8], offset: usize, config: &NtpConfig) -> io::Result<i128> {
+    let bytes: [u8; 8] = offset
+        .checked_add(NTP_TIMESTAMP_SIZE)
+        .and_then(|end| packet.get(offset..end))
+        .and_then(|slice| slice.try_into().ok())
+        .ok_or_else(|| {
+            io::Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("NTP 时间戳（偏移 {offset}）缺失"),
+            )
+        })?;
+    let timestamp = NtpTimestamp::from_bytes(bytes);
+    if timestamp.is_null() {
         return Err(io::Error::new(
-            ErrorKind::UnexpectedEof,
-            "NTP response is too short",
+            ErrorKind::InvalidData,
+            format!("服务器返回了空时间戳（偏移 {offset}）"),
         ));
     }
-    let round_trip_duration = recv_instant.duration_since(send_instant);
-    let t4 = t1
-        + chrono::Duration::from_std(round_trip_duration)
-            .map_err(|e| io::Error::other(format!("Round trip duration error: {e}")))?;
-    let recv_slice = buf
-        .get(config.recv_timestamp_offset..config.recv_timestamp_offset + 8)
-        .ok_or_else(|| io::Error::new(ErrorKind::UnexpectedEof, "NTP 接收时间戳缺失"))?;
-    let t2_ntp = NtpTimestamp::from_bytes(
-        recv_slice
-            .try_into()
-            .map_err(|_| io::Error::new(ErrorKind::InvalidData, "NTP 接收时间戳长度错误"))?,
-    );
-    let tx_slice = buf
-        .get(config.transmit_timestamp_offset..config.transmit_timestamp_offset + 8)
-        .ok_or_else(|| io::Error::new(ErrorKind::UnexpectedEof, "NTP 发送时间戳缺失"))?;
-    let t3_ntp = NtpTimestamp::from_bytes(
-        tx_slice
-            .try_into()
-            .map_err(|_| io::Error::new(ErrorKind::InvalidData, "NTP 发送时间戳长度错误"))?,
-    );
-    let t2_systime = t2_ntp.to_system_time(config)?;
-    let t3_systime = t3_ntp.to_system_time(config)?;
-    let t2: DateTime<Utc> = t2_systime.into();
-    let t3: DateTime<Utc> = t3_systime.into();
-    let offset = ((t2 - t1) + (t3 - t4)) / 2;
-    let delay = (t4 - t1) - (t3 - t2);
-    Ok((offset, delay))
+    timestamp.to_unix_picos(config)
+}
+pub fn picos_to_duration(picos: i128) -> chrono::Duration {
+    let nanos = (picos + picos.signum() * 500) / 1000;
+    chrono::Duration::nanoseconds(i64::try_from(nanos).unwrap_or(if nanos < 0 {
+        i64::MIN
+    } else {
+        i64::MAX
+    }))
+}
+/// 检测服务器的参考 ID 是否指向本机：stratum >= 2 时参考 ID 为其上游的 IPv4 地址，
+/// 若与本机地址或配置的 `own_refids` 相同，说明该服务器正在与我们同步，可能形成环路。
+fn check_refid_loop(
+    packet: &[u8],
+    socket: &UdpSocket,
+    config: &NtpConfig,
+) -> io::Result<Option<String>> {
+    if config.refid_loop_policy == RefidLoopPolicy::Ignore
+        || packet
+            .get(STRATUM_OFFSET)
+            .is_none_or(|stratum| *stratum < 2)
+    {
+        return Ok(None);
+    }
+    let Some(refid) = packet
+        .get(REFERENCE_ID_OFFSET..REFERENCE_ID_OFFSET + 4)
+        .and_then(|bytes| <[u8; 4]>::try_from(bytes).ok())
+        .map(Ipv4Addr::from)
+    else {
+        return Ok(None);
+    };
+    let local_ip = socket.local_addr().ok().map(|addr| addr.ip());
+    let is_own = local_ip == Some(IpAddr::V4(refid)) || config.own_refid_addrs().contains(&refid);
+    if !is_own {
+        return Ok(None);
+    }
+    let message = format!("服务器的参考 ID {refid} 指向本机，可能形成同步环路");
+    match config.refid_loop_policy {
+        RefidLoopPolicy::Reject => Err(io::Error::new(ErrorKind::InvalidData, message)),
+        RefidLoopPolicy::Warn | RefidLoopPolicy::Ignore => Ok(Some(message)),
+    }
+}
+fn drain_socket(socket: &UdpSocket) -> usize {
+    if socket.set_nonblocking(true).is_err() {
+        return 0;
+    }
+    let mut scratch = [0u8; 512];
+    let mut drained = 0;
+    while socket.recv(&mut scratch).is_ok() {
+        drained += 1;
+    }
+    drained
 }
+/// 对服务器连续查询 `samples_per_poll` 次，按 `sample_combine` 合并成功的样本；全部失败时返回最后一个错误。
 fn perform_sync(
     server: &str,
     program_clock: &Arc<Mutex<ProgramClock>>,
     config: &NtpConfig,
-) -> io::Result<(chrono::Duration, chrono::Duration)> {
-    query_ntp(server, config.sync_timeout(), program_clock, config)
+    limiter: Option<&QueryRateLimiter>,
+) -> io::Result<(NtpSample, Vec<String>)> {
+    let mut samples = Vec::with_capacity(config.samples_per_poll());
+    let mut notices = Vec::new();
+    let mut last_error = None;
+    for _ in 0..config.samples_per_poll() {
+        if let Some(limiter) = limiter {
+            limiter.acquire();
+        }
+        match exchange(
+            server,
+            config.read_timeout(),
+            config.write_timeout(),
+            program_clock,
+            config,
+        ) {
+            Ok((sample, sample_notices)) => {
+                samples.push(sample);
+                notices.extend(sample_notices);
+            }
+            // 服务器已要求降低频率或拒绝访问，本轮不再继续查询它。
+            Err(e) if KissOfDeath::from_error(&e).is_some() => return Err(e),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    let sample = combine_samples(&mut samples, config.sample_combine)
+        .ok_or_else(|| last_error.unwrap_or_else(|| io::Error::other("没有可用的样本")))?;
+    Ok((sample, notices))
 }
-pub fn start_sync_thread(
-    clock: Arc<Mutex<ProgramClock>>,
-    config: NtpConfig,
-) -> mpsc::Receiver<SyncMessage> {
-    let (tx, rx) = mpsc::channel::<SyncMessage>();
-    thread::spawn(move || {
-        let mut rng = rand::rng();
-        loop {
-            let next_sync_interval = Duration::from_secs(
-                rng.random_range(config.sync_interval_min_secs..=config.sync_interval_max_secs),
-            );
-            thread::sleep(next_sync_interval);
-            let server_index = rng.random_range(0..config.servers.len());
-            let server = config.servers[server_index].clone();
-            if tx.send(SyncMessage::Syncing(server.clone())).is_err() {
-                break;
+/// 合并多个样本：`min_delay` 取延迟最小的样本；`median` 取偏移的中位数；
+/// `trimmed_mean` 去掉偏移最大与最小的各四分之一后取平均。后两者的延迟按同样的样本取平均，
+/// 根距离取其中最大者，测量时刻取其中最晚者。
+fn combine_samples(samples: &mut [NtpSample], combine: SampleCombine) -> Option<NtpSample> {
+    if samples.is_empty() {
+        return None;
+    }
+    let kept = match combine {
+        SampleCombine::MinDelay => {
+            return samples.iter().min_by_key(|sample| sample.delay).cloned();
+        }
+        SampleCombine::Median => {
+            samples.sort_by_key(|sample| sample.offset);
+            // 奇数个样本取中间一个，偶数个取中间两个的平均。
+            &samples[(samples.len() - 1) / 2..=samples.len() / 2]
+        }
+        SampleCombine::TrimmedMean => {
+            samples.sort_by_key(|sample| sample.offset);
+            let trim = samples.len() / 4;
+            &samples[trim..samples.len() - trim]
+        }
+    };
+    let count = i32::try_from(kept.len()).ok()?;
+    let (offset_sum, delay_sum) = kept.iter().fold(
+        (chrono::Duration::zero(), chrono::Duration::zero()),
+        |(offset_sum, delay_sum), sample| (offset_sum + sample.offset, delay_sum + sample.delay),
+    );
+    Some(NtpSample {
+        server: kept[0].server.clone(),
+        offset: offset_sum / count,
+        delay: delay_sum / count,
+        stratum: kept.iter().filter_map(|sample| sample.stratum).max(),
+        root_distance_secs: kept
+            .iter()
+            .filter_map(|sample| sample.root_distance_secs)
+            .reduce(f64::max),
+        measured_at: kept.iter().map(|sample| sample.measured_at).max()?,
+    })
+}
+/// 单台服务器一次同步的结果：成功时为通过校验的样本，失败时携带可能收到的 Kiss-o'-Death。
+type ServerOutcome = Result<NtpSample, Option<KissOfDeath>>;
+fn sync_with_server(
+    server: String,
+    clock: &Arc<Mutex<ProgramClock>>,
+    config: &NtpConfig,
+    limiter: Option<&QueryRateLimiter>,
+    sanity: Option<&mut SanityCheck>,
+    tx: &mpsc::Sender<SyncMessage>,
+) -> Result<ServerOutcome, mpsc::SendError<SyncMessage>> {
+    tx.send(SyncMessage::Syncing(server.clone()))?;
+    let result = perform_sync(&server, clock, config, limiter);
+    vet_sync_result(server, result, clock, config, limiter, sanity, tx)
+}
+/// 转发查询附带的提示，配置了锚点服务器时交叉校验样本；查询或校验失败时报告原因。
+fn vet_sync_result(
+    server: String,
+    result: io::Result<(NtpSample, Vec<String>)>,
+    clock: &Arc<Mutex<ProgramClock>>,
+    config: &NtpConfig,
+    limiter: Option<&QueryRateLimiter>,
+    sanity: Option<&mut SanityCheck>,
+    tx: &mpsc::Sender<SyncMessage>,
+) -> Result<ServerOutcome, mpsc::SendError<SyncMessage>> {
+    match result {
+        Ok((sample, notices)) => {
+            for notice in notices {
+                tx.send(SyncMessage::Notice(server.clone(), notice))?;
             }
-            if let Ok(result) = perform_sync(&server, &clock, &config)
-                && tx.send(SyncMessage::Success(result.0, result.1)).is_err()
+            if let Some(sanity) = sanity
+                && let Err(reason) = vet_with_sanity_server(sanity, &sample, clock, config, limiter)
             {
-                break;
+                tx.send(SyncMessage::Failed(server, reason))?;
+                return Ok(Err(None));
             }
+            Ok(Ok(sample))
+        }
+        Err(e) => {
+            tx.send(SyncMessage::Failed(server, e.to_string()))?;
+            Ok(Err(KissOfDeath::from_error(&e).cloned()))
         }
+    }
+}
+/// 在各自的线程中同时查询多台服务器，全部结束后按选择顺序逐一校验结果。
+/// 一个周期的耗时因此只取决于最慢的一台，而不是各台查询时间之和。
+fn sync_concurrently(
+    servers: &[usize],
+    clock: &Arc<Mutex<ProgramClock>>,
+    config: &NtpConfig,
+    limiter: Option<&QueryRateLimiter>,
+    mut sanity: Option<&mut SanityCheck>,
+    tx: &mpsc::Sender<SyncMessage>,
+) -> Result<Vec<(usize, ServerOutcome)>, mpsc::SendError<SyncMessage>> {
+    for &index in servers {
+        tx.send(SyncMessage::Syncing(config.servers[index].clone()))?;
+    }
+    let results: Vec<_> = thread::scope(|scope| {
+        // 先启动全部查询再逐个等待，使查询真正并行。
+        let mut queries = Vec::with_capacity(servers.len());
+        for &index in servers {
+            queries.push(
+                scope.spawn(move || perform_sync(&config.servers[index], clock, config, limiter)),
+            );
+        }
+        queries
+            .into_iter()
+            .map(|query| {
+                query
+                    .join()
+                    .unwrap_or_else(|_| Err(io::Error::other("查询线程异常退出")))
+            })
+            .collect()
     });
-    rx
+    servers
+        .iter()
+        .zip(results)
+        .map(|(&index, result)| {
+            let server = config.servers[index].clone();
+            let outcome = vet_sync_result(
+                server,
+                result,
+                clock,
+                config,
+                limiter,
+                sanity.as_deref_mut(),
+                tx,
+            )?;
+            Ok((index, outcome))
+        })
+        .collect()
+}
+/// 与锚点服务器交叉校验一个样本。样本来自锚点服务器本身时只更新参考；
+/// 参考过旧时先重新查询锚点，锚点不可达且配置要求可达时拒绝样本，不再校正时钟。
+fn vet_with_sanity_server(
+    sanity: &mut SanityCheck,
+    sample: &NtpSample,
+    clock: &Arc<Mutex<ProgramClock>>,
+    config: &NtpConfig,
+    limiter: Option<&QueryRateLimiter>,
+) -> Result<(), String> {
+    if sample.server == sanity.server() {
+        let clock_now = clock.lock().unwrap().disciplined_now();
+        sanity.record(clock_now, sample.offset.as_seconds_f64());
+        return Ok(());
+    }
+    if sanity.needs_refresh() {
+        match perform_sync(sanity.server(), clock, config, limiter) {
+            Ok((anchor, _)) => {
+                let clock_now = clock.lock().unwrap().disciplined_now();
+                sanity.record(clock_now, anchor.offset.as_seconds_f64());
+            }
+            Err(e) if sanity.require_reachable() => {
+                return Err(format!(
+                    "锚点服务器 {} 不可达（{e}），拒绝在无法交叉校验时校正时钟",
+                    sanity.server()
+                ));
+            }
+            Err(_) => {}
+        }
+    }
+    let clock_now = clock.lock().unwrap().disciplined_now();
+    sanity.check(clock_now, sample.offset.as_seconds_f64())
+}
+/// 本周期达到法定数量时把一致样本中延迟最小的一个交给滤波器，否则跳过校正并提示。
+fn discipline_with_quorum(
+    quorum: &Quorum,
+    tx: &mpsc::Sender<SyncMessage>,
+) -> Result<(), mpsc::SendError<SyncMessage>> {
+    if let Some(sample) = quorum.agreed() {
+        return tx.send(SyncMessage::Success(sample.clone()));
+    }
+    tx.send(SyncMessage::Notice(
+        "quorum".to_owned(),
+        format!(
+            "本周期 {} 台服务器响应，其中最多 {} 台的时间区间一致，未达到法定数量 {}，跳过校正",
+            quorum.responded(),
+            quorum.agreeing(),
+            quorum.min_servers()
+        ),
+    ))
+}
+fn sync_with_http_fallback(
+    fallback: &HttpFallbackConfig,
+    clock: &Arc<Mutex<ProgramClock>>,
+    tx: &mpsc::Sender<SyncMessage>,
+) -> Result<(), mpsc::SendError<SyncMessage>> {
+    let source = http_time::source_name(fallback);
+    tx.send(SyncMessage::Syncing(source.clone()))?;
+    match http_time::query_http_date(fallback, clock) {
+        Ok((offset, delay)) => tx.send(SyncMessage::Success(NtpSample::new(source, offset, delay))),
+        Err(e) => tx.send(SyncMessage::Failed(source, e.to_string())),
+    }
+}
+fn next_poll_interval(config: &NtpConfig, rng: &mut impl Rng) -> Duration {
+    let (min, max) = (config.sync_interval_min(), config.sync_interval_max());
+    let base = (min + max) / 2;
+    let jitter = config.poll_jitter_percent() / 100.0;
+    if jitter <= 0.0 || base.is_zero() {
+        return base;
+    }
+    let factor = rng.random_range(-jitter..=jitter);
+    base.mul_f64(1.0 + factor).clamp(min, max)
+}
+/// 依次查询所有服务器一次，按延迟升序排列，未响应的排在最后。
+fn discover_servers(
+    clock: &Arc<Mutex<ProgramClock>>,
+    config: &NtpConfig,
+    timeout: Duration,
+    limiter: Option<&QueryRateLimiter>,
+) -> Vec<ServerRank> {
+    let mut ranking: Vec<ServerRank> = config
+        .servers
+        .iter()
+        .map(|server| {
+            if let Some(limiter) = limiter {
+                limiter.acquire();
+            }
+            ServerRank {
+                server: server.clone(),
+                delay_secs: exchange(server, timeout, timeout, clock, config)
+                    .ok()
+                    .map(|(sample, _)| sample.delay.as_seconds_f64()),
+            }
+        })
+        .collect();
+    ranking.sort_by(|a, b| match (a.delay_secs, b.delay_secs) {
+        (Some(a), Some(b)) => a.total_cmp(&b),
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => std::cmp::Ordering::Equal,
+    });
+    ranking
+}
+/// 选择候选服务器：有发现排名时按名次加权（权重为名次的倒数），延迟越低越常被选中；
+/// 否则均匀随机。
+fn pick_server(candidates: &[usize], ranks: Option<&[usize]>, rng: &mut impl Rng) -> usize {
+    ranks
+        .and_then(|ranks| {
+            #[allow(clippy::cast_precision_loss)]
+            candidates
+                .choose_weighted(rng, |&index| 1.0 / (ranks[index] + 1) as f64)
+                .ok()
+                .copied()
+        })
+        .unwrap_or_else(|| candidates[rng.random_range(0..candidates.len())])
+}
+/// 配置了发现超时时查询全部服务器并返回各服务器的名次（`ranks[i]` 为 `config.servers[i]` 的名次）。
+fn discover_ranks(
+    clock: &Arc<Mutex<ProgramClock>>,
+    config: &NtpConfig,
+    limiter: Option<&QueryRateLimiter>,
+    tx: &mpsc::Sender<SyncMessage>,
+) -> Result<Option<Vec<usize>>, mpsc::SendError<SyncMessage>> {
+    let Some(timeout) = config.discovery_timeout() else {
+        return Ok(None);
+    };
+    let ranking = discover_servers(clock, config, timeout, limiter);
+    let ranks = config
+        .servers
+        .iter()
+        .map(|server| {
+            ranking
+                .iter()
+                .position(|entry| entry.server == *server)
+                .unwrap_or(0)
+        })
+        .collect();
+    tx.send(SyncMessage::Discovered(ranking))?;
+    Ok(Some(ranks))
+}
+/// 同步线程跨周期维护的服务器轮换状态：发现阶段的名次、分级、近期评分与 Kiss-o'-Death 退避。
+struct Rotation {
+    ranks: Option<Vec<usize>>,
+    tiers: Option<ServerTiers>,
+    scores: Option<ServerScores>,
+    backoff: ServerBackoff,
+}
+impl Rotation {
+    fn new(config: &NtpConfig, ranks: Option<Vec<usize>>) -> Self {
+        Self {
+            ranks,
+            tiers: ServerTiers::new(config),
+            scores: config.server_selection.map(|selection| {
+                ServerScores::new(
+                    selection,
+                    config.selection_jitter_weight(),
+                    config.servers.len(),
+                )
+            }),
+            backoff: ServerBackoff::new(config),
+        }
+    }
+
+    /// 从本周期尚未尝试的服务器中选出一台；候选均已尝试过或仍在退避时返回 `None`。
+    fn pick(&self, config: &NtpConfig, tried: &[usize], rng: &mut impl Rng) -> Option<usize> {
+        let candidates: Vec<usize> = (0..config.servers.len())
+            .filter(|index| !tried.contains(index))
+            .collect();
+        let candidates = self.backoff.restrict(&candidates);
+        if candidates.is_empty() {
+            return None;
+        }
+        let candidates = match &self.tiers {
+            Some(tiers) => tiers.restrict(&candidates),
+            None => candidates,
+        };
+        // 配置了选择策略时按近期的延迟与抖动排名，否则沿用发现阶段的名次。
+        let score_ranks = self.scores.as_ref().map(ServerScores::ranks);
+        Some(pick_server(
+            &candidates,
+            score_ranks.as_deref().or(self.ranks.as_deref()),
+            rng,
+        ))
+    }
+
+    /// 一次选出至多 `count` 台互不相同的服务器，供并发查询。
+    fn pick_many(&self, config: &NtpConfig, count: usize, rng: &mut impl Rng) -> Vec<usize> {
+        let mut picked = Vec::with_capacity(count);
+        while picked.len() < count
+            && let Some(index) = self.pick(config, &picked, rng)
+        {
+            picked.push(index);
+        }
+        picked
+    }
+
+    fn record(
+        &mut self,
+        server_index: usize,
+        outcome: Result<&NtpSample, &Option<KissOfDeath>>,
+        config: &NtpConfig,
+        tx: &mpsc::Sender<SyncMessage>,
+    ) -> Result<(), mpsc::SendError<SyncMessage>> {
+        if let Some(tiers) = &mut self.tiers {
+            tiers.record(server_index, outcome.is_ok());
+        }
+        if let (Ok(sample), Some(scores)) = (outcome, &mut self.scores) {
+            scores.record(
+                server_index,
+                sample.offset.as_seconds_f64(),
+                sample.delay.as_seconds_f64(),
+            );
+        }
+        record_backoff(&mut self.backoff, server_index, outcome, config, tx)
+    }
+
+    /// 网络切换后旧网络上积累的声誉不再可靠：只保留各服务器最近的少量样本并恢复全部层级，
+    /// 避免继续偏爱在旧网络上表现好的服务器；调用方随后在启用发现时重新探测全部服务器。
+    fn forget(
+        &mut self,
+        reason: &str,
+        config: &NtpConfig,
+        tx: &mpsc::Sender<SyncMessage>,
+    ) -> Result<(), mpsc::SendError<SyncMessage>> {
+        if let Some(scores) = &mut self.scores {
+            scores.forget(config.network_change.map_or(0, |nc| nc.retained_samples));
+        }
+        if let Some(tiers) = &mut self.tiers {
+            tiers.reset();
+        }
+        tx.send(SyncMessage::Notice(
+            "network".to_owned(),
+            format!("{reason}，疑似网络切换，已部分重置服务器声誉并重新探测"),
+        ))
+    }
+}
+/// 按一次查询的结果更新退避：正常响应清除 RATE 退避；收到 Kiss-o'-Death 时
+/// 让服务器退出轮换或延长其查询间隔，并提示暂停时长。
+fn record_backoff(
+    backoff: &mut ServerBackoff,
+    server_index: usize,
+    outcome: Result<&NtpSample, &Option<KissOfDeath>>,
+    config: &NtpConfig,
+    tx: &mpsc::Sender<SyncMessage>,
+) -> Result<(), mpsc::SendError<SyncMessage>> {
+    let kiss = match outcome {
+        Ok(_) => {
+            backoff.record_success(server_index);
+            return Ok(());
+        }
+        Err(None) => return Ok(()),
+        Err(Some(kiss)) => kiss,
+    };
+    let Some(pause) = backoff.record_kiss(server_index, kiss) else {
+        return Ok(());
+    };
+    let reason = if kiss.is_rate() {
+        "查询过于频繁"
+    } else {
+        "拒绝访问"
+    };
+    tx.send(SyncMessage::Notice(
+        config.servers[server_index].clone(),
+        format!(
+            "服务器{reason}（{}），{} 秒内不再查询",
+            kiss.code,
+            pause.as_secs()
+        ),
+    ))
+}
+fn run_sync_loop(
+    clock: &Arc<Mutex<ProgramClock>>,
+    config: &NtpConfig,
+    http_fallback: Option<&HttpFallbackConfig>,
+    control: &SyncControl,
+    limiter: Option<&QueryRateLimiter>,
+    heartbeat: Option<&Heartbeat>,
+    tx: &mpsc::Sender<SyncMessage>,
+) -> Result<(), mpsc::SendError<SyncMessage>> {
+    let mut rng = rand::rng();
+    let mut rotation = Rotation::new(config, discover_ranks(clock, config, limiter, tx)?);
+    let mut network_change = config.network_change.map(NetworkChange::new);
+    let mut sanity = config.sanity_server.clone().map(SanityCheck::new);
+    loop {
+        if let Some(heartbeat) = heartbeat {
+            heartbeat.beat();
+        }
+        thread::sleep(next_poll_interval(config, &mut rng));
+        // 被看门狗判定为停滞并替换后，旧线程恢复运行时直接退出，避免与新线程重复查询。
+        if heartbeat.is_some_and(Heartbeat::is_retired) {
+            return Ok(());
+        }
+        if control.paused.load(Ordering::SeqCst) {
+            continue;
+        }
+        if let Some(reason) = network_change
+            .as_mut()
+            .and_then(|network_change| network_change.check(&control.network_changed))
+        {
+            rotation.forget(&reason, config, tx)?;
+            rotation.ranks = discover_ranks(clock, config, limiter, tx)?;
+        }
+        let mut quorum = config.discipline_quorum().map(Quorum::new);
+        let synced =
+            if let (Some(count), Some(quorum)) = (config.concurrent_queries, quorum.as_mut()) {
+                let servers = rotation.pick_many(config, count, &mut rng);
+                for (index, outcome) in
+                    sync_concurrently(&servers, clock, config, limiter, sanity.as_mut(), tx)?
+                {
+                    rotation.record(index, outcome.as_ref(), config, tx)?;
+                    if let Ok(sample) = outcome {
+                        quorum.record(sample);
+                    }
+                }
+                if let Some(heartbeat) = heartbeat {
+                    heartbeat.beat();
+                }
+                quorum.agreed().is_some()
+            } else {
+                // 法定数量模式下一个周期可以查询全部服务器，直到达成一致。
+                let attempts = match quorum {
+                    Some(_) => config.servers.len(),
+                    None => config.retry_servers_per_cycle() + 1,
+                };
+                let mut tried = Vec::with_capacity(attempts);
+                let mut synced = false;
+                while tried.len() < attempts
+                    && let Some(server_index) = rotation.pick(config, &tried, &mut rng)
+                {
+                    tried.push(server_index);
+                    let server = config.servers[server_index].clone();
+                    let outcome =
+                        sync_with_server(server, clock, config, limiter, sanity.as_mut(), tx)?;
+                    if let Some(heartbeat) = heartbeat {
+                        heartbeat.beat();
+                    }
+                    rotation.record(server_index, outcome.as_ref(), config, tx)?;
+                    let Ok(sample) = outcome else {
+                        continue;
+                    };
+                    // 启用法定数量时样本先暂存，本周期达成一致后才交给滤波器。
+                    match &mut quorum {
+                        Some(quorum) => quorum.record(sample),
+                        None => tx.send(SyncMessage::Success(sample))?,
+                    }
+                    if quorum
+                        .as_ref()
+                        .is_none_or(|quorum| quorum.agreed().is_some())
+                    {
+                        synced = true;
+                        break;
+                    }
+                }
+                synced
+            };
+        if let Some(network_change) = &mut network_change {
+            network_change.record_cycle(synced);
+        }
+        if let Some(quorum) = &quorum {
+            // 未达成一致时宁可本周期不校正，也不退回无法交叉验证的 HTTP 时间。
+            discipline_with_quorum(quorum, tx)?;
+            continue;
+        }
+        if !synced && let Some(fallback) = http_fallback {
+            sync_with_http_fallback(fallback, clock, tx)?;
+        }
+    }
+}
+pub fn start_sync_thread(
+    clock: Arc<Mutex<ProgramClock>>,
+    config: NtpConfig,
+    http_fallback: Option<HttpFallbackConfig>,
+    control: SyncControl,
+    limiter: Option<Arc<QueryRateLimiter>>,
+    heartbeat: Option<Heartbeat>,
+    tx: mpsc::Sender<SyncMessage>,
+) {
+    thread::spawn(move || {
+        let _ = run_sync_loop(
+            &clock,
+            &config,
+            http_fallback.as_ref(),
+            &control,
+            limiter.as_deref(),
+            heartbeat.as_ref(),
+            &tx,
+        );
+    });
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+
+    fn config() -> NtpConfig {
+        AppConfig::builtin().unwrap().ntp
+    }
+
+    /// 构造一个 48 字节、stratum 0 的 Kiss-o'-Death 服务器响应。
+    fn kiss_packet(code: [u8; 4]) -> Vec<u8> {
+        let mut packet = vec![0u8; 48];
+        packet[0] = 0b00_100_100;
+        packet[STRATUM_OFFSET] = 0;
+        packet[REFERENCE_ID_OFFSET..REFERENCE_ID_OFFSET + 4].copy_from_slice(&code);
+        packet
+    }
+
+    fn kiss_from(packet: &[u8]) -> KissOfDeath {
+        let error = check_response_header(packet, &config()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ConnectionRefused);
+        KissOfDeath::from_error(&error)
+            .expect("stratum 0 响应应解析为 Kiss-o'-Death")
+            .clone()
+    }
+
+    #[test]
+    fn rate_kiss_is_parsed_and_classified() {
+        let kiss = kiss_from(&kiss_packet(*b"RATE"));
+        assert_eq!(kiss.code, "RATE");
+        assert!(kiss.is_rate());
+        assert!(!kiss.denies_access());
+    }
+
+    #[test]
+    fn deny_kiss_is_parsed_and_classified() {
+        let kiss = kiss_from(&kiss_packet(*b"DENY"));
+        assert_eq!(kiss.code, "DENY");
+        assert!(kiss.denies_access());
+        assert!(!kiss.is_rate());
+        assert!(kiss_from(&kiss_packet(*b"RSTR")).denies_access());
+    }
+
+    #[test]
+    fn unknown_kiss_code_is_neither_rate_nor_denial() {
+        let kiss = kiss_from(&kiss_packet(*b"INIT"));
+        assert_eq!(kiss.code, "INIT");
+        assert!(!kiss.is_rate());
+        assert!(!kiss.denies_access());
+    }
+
+    const NONCE: [u8; NTP_TIMESTAMP_SIZE] = [0x5a, 1, 2, 3, 4, 5, 6, 7];
+
+    /// 构造一个发起时间戳为 `origin`、发送时间戳为 `transmit` 的服务器响应。
+    fn reply(origin: [u8; NTP_TIMESTAMP_SIZE], transmit: [u8; NTP_TIMESTAMP_SIZE]) -> Vec<u8> {
+        let config = config();
+        let mut packet = vec![0u8; 48];
+        packet[0] = 0b00_100_100;
+        packet[STRATUM_OFFSET] = 2;
+        let origin_offset = config.origin_timestamp_offset();
+        packet[origin_offset..origin_offset + NTP_TIMESTAMP_SIZE].copy_from_slice(&origin);
+        let transmit_offset = config.transmit_timestamp_offset;
+        packet[transmit_offset..transmit_offset + NTP_TIMESTAMP_SIZE].copy_from_slice(&transmit);
+        packet
+    }
+
+    #[test]
+    fn echoed_nonce_matches() {
+        let packet = reply(NONCE, [9; NTP_TIMESTAMP_SIZE]);
+        assert_eq!(
+            match_response(&packet, &NONCE, &config()),
+            ResponseMatch::Matched
+        );
+    }
+
+    #[test]
+    fn mismatched_origin_is_unmatched() {
+        let mut stale = NONCE;
+        stale[7] ^= 1;
+        let packet = reply(stale, [9; NTP_TIMESTAMP_SIZE]);
+        assert_eq!(
+            match_response(&packet, &NONCE, &config()),
+            ResponseMatch::Unmatched
+        );
+        let packet = reply([0; NTP_TIMESTAMP_SIZE], [9; NTP_TIMESTAMP_SIZE]);
+        assert_eq!(
+            match_response(&packet, &NONCE, &config()),
+            ResponseMatch::Unmatched
+        );
+    }
+
+    #[test]
+    fn reflected_request_is_reported_as_echo() {
+        let packet = reply(NONCE, NONCE);
+        assert_eq!(
+            match_response(&packet, &NONCE, &config()),
+            ResponseMatch::RequestEcho
+        );
+    }
+
+    #[test]
+    fn timeout_after_discards_is_invalid_data() {
+        assert_eq!(unmatched_response_error(0).kind(), ErrorKind::TimedOut);
+        assert_eq!(unmatched_response_error(2).kind(), ErrorKind::InvalidData);
+    }
+
+    /// 在本机回环上模拟服务器：先回一个发起时间戳不匹配的旧响应，再回正确回显 nonce 的响应。
+    #[test]
+    fn exchange_drops_non_matching_echoes() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut config = config();
+        config.port = server.local_addr().unwrap().port();
+        config.dscp = None;
+        config.source_port_range = None;
+        let responder = {
+            let config = config.clone();
+            thread::spawn(move || {
+                let mut request = [0u8; 48];
+                let (_, client) = server.recv_from(&mut request).unwrap();
+                let transmit_offset = config.transmit_timestamp_offset;
+                let mut nonce = [0u8; NTP_TIMESTAMP_SIZE];
+                nonce.copy_from_slice(
+                    &request[transmit_offset..transmit_offset + NTP_TIMESTAMP_SIZE],
+                );
+                let now = seconds_since_1900(Utc::now(), &config)
+                    .unwrap()
+                    .to_be_bytes();
+                let mut now_field = [0u8; NTP_TIMESTAMP_SIZE];
+                now_field[..4].copy_from_slice(&now);
+                let mut stale = nonce;
+                stale[0] ^= 0xff;
+                for origin in [stale, nonce] {
+                    let mut packet = reply(origin, now_field);
+                    let recv_offset = config.recv_timestamp_offset;
+                    packet[recv_offset..recv_offset + NTP_TIMESTAMP_SIZE]
+                        .copy_from_slice(&now_field);
+                    server.send_to(&packet, client).unwrap();
+                }
+            })
+        };
+        let clock = Arc::new(Mutex::new(ProgramClock::new(Utc::now())));
+        let timeout = Duration::from_secs(2);
+        let (sample, notices) = exchange("127.0.0.1", timeout, timeout, &clock, &config).unwrap();
+        responder.join().unwrap();
+        assert_eq!(sample.stratum, Some(2));
+        assert!(
+            notices.iter().any(|notice| notice.contains("丢弃了 1 个")),
+            "{notices:?}"
+        );
+    }
+
+    fn sample(offset_millis: i64) -> NtpSample {
+        NtpSample::new(
+            "test".to_owned(),
+            chrono::Duration::milliseconds(offset_millis),
+            chrono::Duration::milliseconds(2),
+        )
+    }
+
+    #[test]
+    fn too_few_responses_are_not_reported_as_disagreement() {
+        let mut quorum = Quorum::new(3);
+        quorum.record(sample(0));
+        quorum.record(sample(0));
+        let timeout = io::Error::new(ErrorKind::TimedOut, "timeout");
+        let error = quorum_error(4, &quorum, Some(&timeout));
+        assert_ne!(error.kind(), ErrorKind::InvalidData);
+        assert!(error.to_string().contains("只有 2 台响应"), "{error}");
+    }
+
+    #[test]
+    fn disagreeing_responses_are_invalid_data() {
+        let mut quorum = Quorum::new(2);
+        quorum.record(sample(0));
+        quorum.record(sample(500));
+        quorum.record(sample(-500));
+        assert!(quorum.agreed().is_none());
+        let error = quorum_error(3, &quorum, None);
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(error.to_string().contains("最多 1 台"), "{error}");
+    }
+
+    #[test]
+    fn ordinary_errors_carry_no_kiss() {
+        let mut packet = kiss_packet(*b"RATE");
+        packet[STRATUM_OFFSET] = 2;
+        assert_eq!(check_response_header(&packet, &config()).unwrap(), Some(2));
+        let error = io::Error::new(ErrorKind::TimedOut, "timeout");
+        assert!(KissOfDeath::from_error(&error).is_none());
+    }
 }
@@ -0,0 +1,125 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, ErrorKind},
+    time::Duration,
+};
+
+use crate::ntp;
+
+const CONTROL_HEADER_SIZE: usize = 12;
+const MAX_CONTROL_PACKET_SIZE: usize = 512;
+const MODE_CONTROL_VERSION_2: u8 = 0b00_010_110;
+const OPCODE_READ_VARIABLES: u8 = 2;
+const RESPONSE_BIT: u8 = 0b1000_0000;
+const ERROR_BIT: u8 = 0b0100_0000;
+const MORE_BIT: u8 = 0b0010_0000;
+const OPCODE_MASK: u8 = 0b0001_1111;
+struct ControlResponse {
+    more: bool,
+    offset: usize,
+    data: Vec<u8>,
+}
+fn build_read_variables_request(sequence: u16) -> [u8; CONTROL_HEADER_SIZE] {
+    let mut request = [0u8; CONTROL_HEADER_SIZE];
+    request[0] = MODE_CONTROL_VERSION_2;
+    request[1] = OPCODE_READ_VARIABLES;
+    request[2..4].copy_from_slice(&sequence.to_be_bytes());
+    request
+}
+fn parse_control_response(packet: &[u8], sequence: u16) -> io::Result<ControlResponse> {
+    if packet.len() < CONTROL_HEADER_SIZE {
+        return Err(io::Error::new(
+            ErrorKind::UnexpectedEof,
+            "控制消息响应长度不足",
+        ));
+    }
+    if packet[0] & 0b111 != 6 {
+        return Err(io::Error::new(ErrorKind::InvalidData, "响应不是控制消息"));
+    }
+    let flags = packet[1];
+    if flags & RESPONSE_BIT == 0 || flags & OPCODE_MASK != OPCODE_READ_VARIABLES {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "响应操作码与请求不匹配",
+        ));
+    }
+    if u16::from_be_bytes([packet[2], packet[3]]) != sequence {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "响应序号与请求不匹配",
+        ));
+    }
+    if flags & ERROR_BIT != 0 {
+        let code = packet[4];
+        return Err(io::Error::other(format!("服务器返回控制消息错误码 {code}")));
+    }
+    let offset = usize::from(u16::from_be_bytes([packet[8], packet[9]]));
+    let count = usize::from(u16::from_be_bytes([packet[10], packet[11]]));
+    let data = packet
+        .get(CONTROL_HEADER_SIZE..CONTROL_HEADER_SIZE + count)
+        .ok_or_else(|| io::Error::new(ErrorKind::UnexpectedEof, "控制消息数据不完整"))?;
+    Ok(ControlResponse {
+        more: flags & MORE_BIT != 0,
+        offset,
+        data: data.to_vec(),
+    })
+}
+fn parse_variables(text: &str) -> BTreeMap<String, String> {
+    let mut variables = BTreeMap::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut push = |item: &str| {
+        if let Some((name, value)) = item.split_once('=') {
+            variables.insert(
+                name.trim().to_owned(),
+                value.trim().trim_matches('"').to_owned(),
+            );
+        } else if !item.trim().is_empty() {
+            variables.insert(item.trim().to_owned(), String::new());
+        }
+    };
+    for c in text.chars() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                current.push(c);
+            }
+            ',' if !in_quotes => {
+                push(&current);
+                current.clear();
+            }
+            _ => current.push(c),
+        }
+    }
+    push(&current);
+    variables
+}
+pub fn read_variables(
+    server: &str,
+    port: u16,
+    timeout: Duration,
+    source_port_range: Option<(u16, u16)>,
+) -> io::Result<BTreeMap<String, String>> {
+    let (socket, _) = ntp::connect_client_socket(server, port, source_port_range)?;
+    socket.set_read_timeout(Some(timeout))?;
+    socket.set_write_timeout(Some(timeout))?;
+    let sequence = rand::random::<u16>();
+    socket.send(&build_read_variables_request(sequence))?;
+    let mut fragments = Vec::new();
+    let mut buf = [0u8; MAX_CONTROL_PACKET_SIZE];
+    loop {
+        let n = socket.recv(&mut buf)?;
+        let response = parse_control_response(&buf[..n], sequence)?;
+        let more = response.more;
+        fragments.push(response);
+        if !more {
+            break;
+        }
+    }
+    fragments.sort_by_key(|fragment| fragment.offset);
+    let data: Vec<u8> = fragments
+        .into_iter()
+        .flat_map(|fragment| fragment.data)
+        .collect();
+    Ok(parse_variables(&String::from_utf8_lossy(&data)))
+}
@@ -0,0 +1,70 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+};
+
+use chrono::{DateTime, Timelike, Utc};
+const MJD_UNIX_EPOCH: i64 = 40_587;
+/// 以 ntpd 的 loopstats / peerstats 列格式追加写入统计文件，便于沿用 ntpviz 等现有工具分析。
+pub struct NtpStatsWriter {
+    loopstats: File,
+    peerstats: File,
+}
+impl NtpStatsWriter {
+    pub fn open(dir: &str) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let open = |name: &str| {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(Path::new(dir).join(name))
+        };
+        Ok(Self {
+            loopstats: open("loopstats")?,
+            peerstats: open("peerstats")?,
+        })
+    }
+
+    /// loopstats 列：MJD、当日秒数、偏移(s)、频率(ppm)、RMS 抖动(s)、频率抖动(ppm)、时间常数。
+    /// Khronos 没有 ntpd 的锁相环时间常数，该列固定为 0。
+    pub fn write_loop(
+        &mut self,
+        time: DateTime<Utc>,
+        offset_secs: f64,
+        drift_ppm: f64,
+        jitter_secs: f64,
+        drift_jitter_ppm: f64,
+    ) -> io::Result<()> {
+        writeln!(
+            self.loopstats,
+            "{} {offset_secs:.9} {drift_ppm:.6} {jitter_secs:.9} {drift_jitter_ppm:.6} 0",
+            mjd_and_seconds(time)
+        )?;
+        self.loopstats.flush()
+    }
+
+    /// peerstats 列：MJD、当日秒数、服务器、状态字、偏移(s)、延迟(s)、离散度(s)、抖动(s)。
+    pub fn write_peer(
+        &mut self,
+        time: DateTime<Utc>,
+        server: &str,
+        offset_secs: f64,
+        delay_secs: f64,
+        dispersion_secs: f64,
+        jitter_secs: f64,
+    ) -> io::Result<()> {
+        writeln!(
+            self.peerstats,
+            "{} {server} 0000 {offset_secs:.9} {delay_secs:.9} {dispersion_secs:.9} {jitter_secs:.9}",
+            mjd_and_seconds(time)
+        )?;
+        self.peerstats.flush()
+    }
+}
+fn mjd_and_seconds(time: DateTime<Utc>) -> String {
+    let mjd = time.timestamp().div_euclid(86_400) + MJD_UNIX_EPOCH;
+    let seconds = f64::from(time.num_seconds_from_midnight())
+        + f64::from(time.nanosecond() % 1_000_000_000) / 1_000_000_000.0;
+    format!("{mjd} {seconds:.3}")
+}
@@ -0,0 +1,57 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::config::PathChangeConfig;
+/// 单个服务器的延迟历史：较早的基线窗口与最近的确认窗口。
+#[derive(Default)]
+struct DelayHistory {
+    baseline: VecDeque<f64>,
+    recent: VecDeque<f64>,
+}
+/// 通过最小延迟基线的持续跳变检测网络路径切换（如 CGNAT 出口或基站变化）。
+/// 最近 `confirm_samples` 个样本的最小延迟与更早基线窗口的最小延迟相差超过阈值时判定为路径变化。
+pub struct PathChangeDetector {
+    config: PathChangeConfig,
+    servers: BTreeMap<String, DelayHistory>,
+}
+impl PathChangeDetector {
+    pub const fn new(config: PathChangeConfig) -> Self {
+        Self {
+            config,
+            servers: BTreeMap::new(),
+        }
+    }
+
+    /// 记录一个延迟样本，检测到路径变化时返回新旧最小延迟（秒）。
+    pub fn record(&mut self, server: &str, delay_secs: f64) -> Option<(f64, f64)> {
+        let history = self.servers.entry(server.to_owned()).or_default();
+        history.recent.push_back(delay_secs);
+        if history.recent.len() > self.config.confirm_samples {
+            if let Some(oldest) = history.recent.pop_front() {
+                history.baseline.push_back(oldest);
+            }
+            if history.baseline.len() > self.config.baseline_samples {
+                history.baseline.pop_front();
+            }
+        }
+        if history.baseline.len() < self.config.baseline_samples
+            || history.recent.len() < self.config.confirm_samples
+        {
+            return None;
+        }
+        let baseline_min = history
+            .baseline
+            .iter()
+            .copied()
+            .fold(f64::INFINITY, f64::min);
+        let recent_min = history.recent.iter().copied().fold(f64::INFINITY, f64::min);
+        if (recent_min - baseline_min).abs() < self.config.min_delay_jump_millis / 1000.0 {
+            return None;
+        }
+        history.baseline.clear();
+        Some((baseline_min, recent_min))
+    }
+
+    pub const fn covariance_inflation(&self) -> f64 {
+        self.config.covariance_inflation
+    }
+}
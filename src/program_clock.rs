@@ -1,25 +1,394 @@
+use std::{cell::Cell, io, time::Duration};
+
 use chrono::{DateTime, Utc};
 use quanta::Instant;
+#[cfg(feature = "skew-injection")]
+struct InjectedSkew {
+    offset_secs: f64,
+    drift_ppm: f64,
+    started_at: Instant,
+}
+/// 正在进行的渐进校正：从 `started_at` 起以 `rate`（秒/秒）的速率累计施加 `offset_secs`。
+struct Slew {
+    offset_secs: f64,
+    rate: f64,
+    started_at: Instant,
+}
+impl Slew {
+    fn progress_secs(&self, at: Instant) -> f64 {
+        let elapsed = at.saturating_duration_since(self.started_at).as_secs_f64();
+        (elapsed * self.rate).min(self.offset_secs.abs()) * self.offset_secs.signum()
+    }
+}
 pub struct ProgramClock {
     current_utc: DateTime<Utc>,
     last_updated_at: Instant,
+    synchronized: bool,
+    max_slew_rate: f64,
+    max_slew_duration: Duration,
+    slew: Option<Slew>,
+    applied_corrections: chrono::Duration,
+    monotonic: bool,
+    last_reading: Cell<Option<DateTime<Utc>>>,
+    #[cfg(feature = "skew-injection")]
+    injected_skew: Option<InjectedSkew>,
 }
 impl ProgramClock {
     pub fn new(initial_utc: DateTime<Utc>) -> Self {
         Self {
             current_utc: initial_utc,
             last_updated_at: Instant::now(),
+            synchronized: false,
+            max_slew_rate: 0.0,
+            max_slew_duration: Duration::ZERO,
+            slew: None,
+            applied_corrections: chrono::Duration::zero(),
+            monotonic: false,
+            last_reading: Cell::new(None),
+            #[cfg(feature = "skew-injection")]
+            injected_skew: None,
         }
     }
 
+    /// 启用后 `now()` 不会返回早于此前任何一次读数的时间：负的跳变校正使读数停在原处，
+    /// 直到校正后的时间追上为止。未启用时返回原始的校正后时间。
+    pub const fn set_monotonic(&mut self, monotonic: bool) {
+        self.monotonic = monotonic;
+    }
+
+    /// 设置渐进校正的速率上限（ppm）与最长持续时间。速率为 0，或按上限速率完成校正
+    /// 需要的时间超过 `max_duration` 时，校正量直接一步施加。
+    pub fn set_slew_limits(&mut self, max_slew_ppm: f64, max_duration: Duration) {
+        self.max_slew_rate = max_slew_ppm / 1_000_000.0;
+        self.max_slew_duration = max_duration;
+    }
+
+    #[cfg(feature = "skew-injection")]
+    pub fn inject_skew(&mut self, offset_secs: f64, drift_ppm: f64) {
+        self.injected_skew = Some(InjectedSkew {
+            offset_secs,
+            drift_ppm,
+            started_at: Instant::now(),
+        });
+    }
+
     pub fn now(&self) -> DateTime<Utc> {
-        let elapsed = self.last_updated_at.elapsed();
-        self.current_utc + chrono::Duration::from_std(elapsed).unwrap()
+        self.try_now().unwrap()
+    }
+
+    /// 与 `now` 相同，但读数换算溢出等内部异常时返回错误而不是 panic，供界面退回显示系统时间。
+    pub fn try_now(&self) -> io::Result<DateTime<Utc>> {
+        let reading = self.try_skewed_now()?;
+        if !self.monotonic {
+            return Ok(reading);
+        }
+        let reading = self
+            .last_reading
+            .get()
+            .map_or(reading, |last| reading.max(last));
+        self.last_reading.set(Some(reading));
+        Ok(reading)
     }
 
+    fn try_skewed_now(&self) -> io::Result<DateTime<Utc>> {
+        let disciplined = self.try_reading_at(Instant::now())?;
+        #[cfg(feature = "skew-injection")]
+        if let Some(skew) = &self.injected_skew {
+            let skew_secs = (skew.started_at.elapsed().as_secs_f64() * skew.drift_ppm)
+                .mul_add(1e-6, skew.offset_secs);
+            return disciplined
+                .checked_add_signed(try_secs_to_chrono(skew_secs).map_err(|_| out_of_range())?)
+                .ok_or_else(out_of_range);
+        }
+        Ok(disciplined)
+    }
+
+    pub fn disciplined_now(&self) -> DateTime<Utc> {
+        self.reading_at(Instant::now())
+    }
+
+    /// 扣除所有已施加校正（包括渐进校正已完成的部分）后的读数，即起点加上时钟自身时间基准的经过时间，
+    /// 不含偏移注入与单调钳制。两次读数之差不受期间施加的校正影响，供滤波器计算预测步长。
+    pub fn uncorrected_now(&self) -> DateTime<Utc> {
+        let at = Instant::now();
+        let slewed = self
+            .slew
+            .as_ref()
+            .map_or(0.0, |slew| slew.progress_secs(at));
+        self.reading_at(at) - self.applied_corrections - secs_to_chrono(slewed)
+    }
+
+    fn reading_at(&self, at: Instant) -> DateTime<Utc> {
+        self.try_reading_at(at).unwrap()
+    }
+
+    fn try_reading_at(&self, at: Instant) -> io::Result<DateTime<Utc>> {
+        let elapsed =
+            chrono::Duration::from_std(at.saturating_duration_since(self.last_updated_at))
+                .map_err(|_| out_of_range())?;
+        let slewed = self
+            .slew
+            .as_ref()
+            .map_or(0.0, |slew| slew.progress_secs(at));
+        self.current_utc
+            .checked_add_signed(elapsed)
+            .and_then(|time| time.checked_add_signed(try_secs_to_chrono(slewed).ok()?))
+            .ok_or_else(out_of_range)
+    }
+
+    /// 按校正后的时间，距下一个 `granularity` 整数倍时刻还有多久，供需要在整秒等边界触发的调度使用。
+    /// 恰好位于边界上时返回零；`granularity` 为零时同样返回零。
+    pub fn duration_to_next_boundary(&self, granularity: Duration) -> Duration {
+        duration_to_boundary(self.disciplined_now(), granularity)
+    }
+
+    /// 以当前时刻为基准应用校正。只读取一次 `Instant::now()`，同时用于计算当前读数和作为新的基准，
+    /// 两次读取之间经过的时间不会丢失。
     pub fn apply_offset(&mut self, offset: chrono::Duration) {
-        let current_time = self.now();
-        self.current_utc = current_time + offset;
-        self.last_updated_at = Instant::now();
+        self.apply_offset_at(offset, Instant::now());
+    }
+
+    /// 无论渐进校正的设置如何都一步施加校正，用于首次同步等需要立即对准的场合。
+    /// 负的校正会让读数回退，之后的周期校正应使用 [`Self::apply_offset`]。
+    pub fn apply_offset_stepped(&mut self, offset: chrono::Duration) {
+        self.correct_at(offset, Instant::now(), false);
+    }
+
+    /// 以 `at` 时刻的时钟读数为基准应用校正，此后时钟从 `at` 起推进。
+    /// `at` 早于上次校正时无法回溯，退化为以上次校正时刻为基准。
+    /// 设置了渐进校正且能在最长时长内完成时，读数以不超过速率上限的速度连续、单调地追上目标；
+    /// 否则一步施加。
+    pub fn apply_offset_at(&mut self, offset: chrono::Duration, at: Instant) {
+        self.correct_at(offset, at, true);
+    }
+
+    fn correct_at(&mut self, offset: chrono::Duration, at: Instant, allow_slew: bool) {
+        let at = at.max(self.last_updated_at);
+        // 新的偏移测量已包含尚未施加完的渐进校正余量，因此先固定当前读数，再替换掉旧的校正。
+        self.current_utc = self.reading_at(at);
+        self.last_updated_at = at;
+        if let Some(slew) = self.slew.take() {
+            self.applied_corrections += secs_to_chrono(slew.progress_secs(at));
+        }
+        let offset_secs = offset.as_seconds_f64();
+        if allow_slew
+            && self.max_slew_rate > 0.0
+            && offset_secs.abs() / self.max_slew_rate <= self.max_slew_duration.as_secs_f64()
+        {
+            self.slew = Some(Slew {
+                offset_secs,
+                rate: self.max_slew_rate,
+                started_at: at,
+            });
+        } else {
+            self.current_utc += offset;
+            self.applied_corrections += offset;
+        }
+        self.synchronized = true;
+    }
+
+    pub const fn is_synchronized(&self) -> bool {
+        self.synchronized
+    }
+}
+/// 从 `now` 到下一个 `granularity` 整数倍时刻的时长；恰好位于边界上或 `granularity` 为零时为零。
+fn duration_to_boundary(now: DateTime<Utc>, granularity: Duration) -> Duration {
+    let granularity_nanos = granularity.as_nanos();
+    if granularity_nanos == 0 {
+        return Duration::ZERO;
+    }
+    let since_epoch_nanos =
+        i128::from(now.timestamp()) * 1_000_000_000 + i128::from(now.timestamp_subsec_nanos());
+    let into_period = since_epoch_nanos.rem_euclid(granularity_nanos.cast_signed());
+    if into_period == 0 {
+        return Duration::ZERO;
+    }
+    let remaining = granularity_nanos - into_period.cast_unsigned();
+    Duration::new(
+        u64::try_from(remaining / 1_000_000_000).unwrap_or(u64::MAX),
+        u32::try_from(remaining % 1_000_000_000).unwrap_or(0),
+    )
+}
+pub fn secs_to_chrono(secs: f64) -> chrono::Duration {
+    try_secs_to_chrono(secs).unwrap()
+}
+/// 把带符号的秒数转换为 `chrono::Duration`：按绝对值转换后再恢复符号，负零与零都得到零。
+/// NaN、无穷或超出 `chrono::Duration` 范围的值返回错误；亚纳秒部分按 `std::time::Duration` 的规则舍入。
+pub fn try_secs_to_chrono(secs: f64) -> io::Result<chrono::Duration> {
+    let magnitude = Duration::try_from_secs_f64(secs.abs())
+        .ok()
+        .and_then(|magnitude| chrono::Duration::from_std(magnitude).ok())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{secs} 秒无法转换为 chrono::Duration"),
+            )
+        })?;
+    Ok(if secs < 0.0 { -magnitude } else { magnitude })
+}
+fn out_of_range() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "程序时钟读数超出可表示范围")
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(rfc3339: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(rfc3339)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn rapid_apply_offset_loses_no_elapsed_time() {
+        let initial = at("2024-01-01T00:00:00Z");
+        let before_create = Instant::now();
+        let mut clock = ProgramClock::new(initial);
+        let after_create = Instant::now();
+        for _ in 0..10_000 {
+            clock.apply_offset(chrono::Duration::zero());
+        }
+        let before_read = Instant::now();
+        let reading = clock.now();
+        let after_read = Instant::now();
+        // 时钟的起点与读数时刻分别夹在两次 Instant 读取之间，累计丢失的时间会使读数跌出下界。
+        let elapsed = (reading - initial).to_std().unwrap();
+        let tolerance = Duration::from_micros(1);
+        assert!(elapsed + tolerance >= before_read.duration_since(after_create));
+        assert!(elapsed <= after_read.duration_since(before_create) + tolerance);
+    }
+
+    #[test]
+    fn uncorrected_reading_excludes_steps_and_slews() {
+        let initial = at("2024-01-01T00:00:00Z");
+        let before_create = Instant::now();
+        let mut clock = ProgramClock::new(initial);
+        let after_create = Instant::now();
+        clock.set_slew_limits(500.0, Duration::from_mins(10));
+        clock.apply_offset_stepped(chrono::Duration::hours(1));
+        clock.apply_offset(-chrono::Duration::seconds(5));
+        clock.apply_offset(chrono::Duration::milliseconds(10));
+        std::thread::sleep(Duration::from_millis(5));
+        // 替换进行中的渐进校正，把已完成的部分固定进读数。
+        clock.apply_offset(chrono::Duration::milliseconds(-10));
+        std::thread::sleep(Duration::from_millis(5));
+        let before_read = Instant::now();
+        let reading = clock.uncorrected_now();
+        let after_read = Instant::now();
+        assert!(clock.disciplined_now() - reading > chrono::Duration::minutes(59));
+        let elapsed = (reading - initial).to_std().unwrap();
+        let tolerance = Duration::from_micros(1);
+        assert!(elapsed + tolerance >= before_read.duration_since(after_create));
+        assert!(elapsed <= after_read.duration_since(before_create) + tolerance);
+    }
+
+    #[test]
+    fn secs_to_chrono_keeps_sign_and_magnitude() {
+        assert_eq!(
+            try_secs_to_chrono(1.25).unwrap(),
+            chrono::Duration::milliseconds(1250)
+        );
+        assert_eq!(
+            try_secs_to_chrono(-0.000_001_5).unwrap(),
+            -chrono::Duration::nanoseconds(1500)
+        );
+        assert_eq!(
+            try_secs_to_chrono(-2.75).unwrap(),
+            -chrono::Duration::milliseconds(2750)
+        );
+        assert_eq!(try_secs_to_chrono(0.0).unwrap(), chrono::Duration::zero());
+        assert_eq!(try_secs_to_chrono(-0.0).unwrap(), chrono::Duration::zero());
+    }
+
+    #[test]
+    fn secs_to_chrono_rejects_values_past_the_range() {
+        #[allow(clippy::cast_precision_loss)]
+        let max_secs = chrono::Duration::MAX.num_seconds() as f64;
+        for secs in [max_secs * 0.999, -max_secs * 0.999] {
+            let converted = try_secs_to_chrono(secs).unwrap();
+            assert_eq!(converted < chrono::Duration::zero(), secs < 0.0);
+            assert!((converted.as_seconds_f64() - secs).abs() <= secs.abs() * f64::EPSILON);
+        }
+        for secs in [
+            max_secs * 1.001,
+            -max_secs * 1.001,
+            f64::NAN,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+        ] {
+            let error = try_secs_to_chrono(secs).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn applied_offset_moves_reading_by_its_sign() {
+        for offset_secs in [0.5, -0.5, 0.0] {
+            let mut clock = ProgramClock::new(at("2024-01-01T00:00:00Z"));
+            let before = clock.now();
+            clock.apply_offset(try_secs_to_chrono(offset_secs).unwrap());
+            let moved_secs = (clock.now() - before).as_seconds_f64();
+            assert!(
+                (moved_secs - offset_secs).abs() < 0.01,
+                "{offset_secs}: {moved_secs}"
+            );
+        }
+    }
+
+    #[test]
+    fn monotonic_clock_never_goes_back_after_negative_step() {
+        let mut clock = ProgramClock::new(at("2024-01-01T00:00:00Z"));
+        clock.set_monotonic(true);
+        let mut previous = clock.now();
+        clock.apply_offset_stepped(chrono::Duration::seconds(-3600));
+        for _ in 0..1000 {
+            let reading = clock.now();
+            assert!(reading >= previous);
+            previous = reading;
+        }
+        // 未启用时负的跳变会让读数回退。
+        clock.set_monotonic(false);
+        assert!(clock.now() < previous);
+    }
+
+    #[test]
+    fn boundary_exactly_on_boundary_is_zero() {
+        let second = Duration::from_secs(1);
+        assert_eq!(
+            duration_to_boundary(at("2024-01-01T00:00:00Z"), second),
+            Duration::ZERO
+        );
+        assert_eq!(
+            duration_to_boundary(at("2024-01-01T00:00:10Z"), Duration::from_secs(5)),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn boundary_sub_millisecond_remaining() {
+        assert_eq!(
+            duration_to_boundary(at("2024-01-01T00:00:00.999600Z"), Duration::from_secs(1)),
+            Duration::from_micros(400)
+        );
+        assert_eq!(
+            duration_to_boundary(at("2024-01-01T00:00:00.999999999Z"), Duration::from_secs(1)),
+            Duration::from_nanos(1)
+        );
+        assert_eq!(
+            duration_to_boundary(at("2024-01-01T00:00:00.000000001Z"), Duration::from_secs(1)),
+            Duration::from_nanos(999_999_999)
+        );
+    }
+
+    #[test]
+    fn boundary_zero_granularity_and_pre_epoch() {
+        assert_eq!(
+            duration_to_boundary(at("2024-01-01T00:00:00.5Z"), Duration::ZERO),
+            Duration::ZERO
+        );
+        assert_eq!(
+            duration_to_boundary(at("1969-12-31T23:59:59.250Z"), Duration::from_secs(1)),
+            Duration::from_millis(750)
+        );
     }
 }
@@ -0,0 +1,68 @@
+use std::{collections::VecDeque, sync::Mutex, thread, time::Duration};
+
+use quanta::Instant;
+const RATE_WINDOW: Duration = Duration::from_mins(1);
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+    sent: VecDeque<Instant>,
+}
+/// 进程内所有对外 NTP 查询共享的令牌桶，按每分钟查询数持续补充令牌。
+/// 桶容量为一次周期同步的连续查询数，使单次同步的突发不被拆开，但长期速率不超过上限。
+pub struct QueryRateLimiter {
+    capacity: f64,
+    refill_per_sec: f64,
+    bucket: Mutex<Bucket>,
+}
+impl QueryRateLimiter {
+    pub fn new(max_queries_per_minute: u32, burst: usize) -> Self {
+        #[allow(clippy::cast_precision_loss)]
+        let capacity = burst.max(1) as f64;
+        Self {
+            capacity,
+            refill_per_sec: f64::from(max_queries_per_minute) / 60.0,
+            bucket: Mutex::new(Bucket {
+                tokens: capacity,
+                last_refill: Instant::now(),
+                sent: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// 取得一个令牌后返回，令牌不足时阻塞等待补充。
+    pub fn acquire(&self) {
+        loop {
+            let wait = {
+                let mut bucket = self.bucket.lock().unwrap();
+                let now = Instant::now();
+                let elapsed = now.saturating_duration_since(bucket.last_refill);
+                bucket.tokens = self
+                    .refill_per_sec
+                    .mul_add(elapsed.as_secs_f64(), bucket.tokens)
+                    .min(self.capacity);
+                bucket.last_refill = now;
+                if bucket.tokens >= 1.0 {
+                    bucket.tokens -= 1.0;
+                    bucket.sent.push_back(now);
+                    return;
+                }
+                Duration::from_secs_f64((1.0 - bucket.tokens) / self.refill_per_sec)
+            };
+            thread::sleep(wait);
+        }
+    }
+
+    /// 最近一分钟内实际发出的查询数。
+    pub fn queries_last_minute(&self) -> usize {
+        let mut bucket = self.bucket.lock().unwrap();
+        let now = Instant::now();
+        while bucket
+            .sent
+            .front()
+            .is_some_and(|&at| now.saturating_duration_since(at) > RATE_WINDOW)
+        {
+            bucket.sent.pop_front();
+        }
+        bucket.sent.len()
+    }
+}
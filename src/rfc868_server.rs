@@ -0,0 +1,58 @@
+use std::{
+    io::{self, Write},
+    net::{TcpListener, TcpStream, UdpSocket},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::{config::NtpConfig, ntp, program_clock::ProgramClock, server_handle::ServerHandle};
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(2);
+/// RFC 868 TIME 协议服务：在同一端口的 TCP 与 UDP 上以 32 位大端整数返回自 1900 年起的秒数，
+/// 供只支持该协议的旧设备使用。协议无法表达未同步状态，因此尚未完成首次同步时不作响应。
+pub fn start_rfc868_server(
+    clock: Arc<Mutex<ProgramClock>>,
+    config: NtpConfig,
+    port: u16,
+) -> io::Result<ServerHandle> {
+    let listener = TcpListener::bind(("0.0.0.0", port))?;
+    let socket = UdpSocket::bind(("0.0.0.0", port))?;
+    let config = Arc::new(config);
+    let mut handle = ServerHandle::default();
+    {
+        let clock = Arc::clone(&clock);
+        let config = Arc::clone(&config);
+        handle.spawn_tcp(listener, move |stream| {
+            let _ = serve_tcp(stream, &clock, &config);
+        })?;
+    }
+    handle.spawn_udp(socket, move |socket, _, peer| {
+        if let Ok(Some(seconds)) = current_seconds(&clock, &config) {
+            let _ = socket.send_to(&seconds, peer);
+        }
+    })?;
+    Ok(handle)
+}
+fn serve_tcp(
+    mut stream: TcpStream,
+    clock: &Arc<Mutex<ProgramClock>>,
+    config: &NtpConfig,
+) -> io::Result<()> {
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+    if let Some(seconds) = current_seconds(clock, config)? {
+        stream.write_all(&seconds)?;
+    }
+    stream.flush()
+}
+fn current_seconds(
+    clock: &Arc<Mutex<ProgramClock>>,
+    config: &NtpConfig,
+) -> io::Result<Option<[u8; 4]>> {
+    let (now, synchronized) = {
+        let clock = clock.lock().unwrap();
+        (clock.now(), clock.is_synchronized())
+    };
+    if !synchronized {
+        return Ok(None);
+    }
+    ntp::seconds_since_1900(now, config).map(|seconds| Some(seconds.to_be_bytes()))
+}
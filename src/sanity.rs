@@ -0,0 +1,59 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use quanta::Instant;
+
+use crate::{config::SanityServerConfig, program_clock::secs_to_chrono};
+/// 可信锚点服务器：其他服务器的偏移与它的最近参考相差超过阈值时拒绝。
+/// 参考保存为锚点服务器给出的绝对时间及对应的单调时刻，此后按单调时钟外推，
+/// 因此不受期间程序时钟校正的影响。
+pub struct SanityCheck {
+    config: SanityServerConfig,
+    reference: Option<(DateTime<Utc>, Instant)>,
+}
+impl SanityCheck {
+    pub const fn new(config: SanityServerConfig) -> Self {
+        Self {
+            config,
+            reference: None,
+        }
+    }
+
+    pub fn server(&self) -> &str {
+        &self.config.server
+    }
+
+    pub const fn require_reachable(&self) -> bool {
+        self.config.require_reachable
+    }
+
+    /// 记录锚点服务器的一个样本：`clock_now` 为程序时钟读数，`offset_secs` 为锚点相对它的偏移。
+    pub fn record(&mut self, clock_now: DateTime<Utc>, offset_secs: f64) {
+        self.reference = Some((clock_now + secs_to_chrono(offset_secs), Instant::now()));
+    }
+
+    /// 参考不存在或已超过 `max_reference_age_secs` 时需要重新查询锚点服务器。
+    pub fn needs_refresh(&self) -> bool {
+        self.reference.is_none_or(|(_, at)| {
+            at.elapsed() > Duration::from_secs(self.config.max_reference_age_secs)
+        })
+    }
+
+    /// 检查其他服务器的样本，与锚点外推到当前的时间相差超过阈值时返回拒绝原因。没有参考时放行。
+    pub fn check(&self, clock_now: DateTime<Utc>, offset_secs: f64) -> Result<(), String> {
+        let Some((reference_utc, at)) = self.reference else {
+            return Ok(());
+        };
+        let anchor_now =
+            reference_utc + chrono::Duration::from_std(at.elapsed()).unwrap_or_default();
+        let candidate_now = clock_now + secs_to_chrono(offset_secs);
+        let disagreement_millis = (candidate_now - anchor_now).as_seconds_f64() * 1000.0;
+        if disagreement_millis.abs() > self.config.max_disagreement_millis {
+            return Err(format!(
+                "与锚点服务器 {} 相差 {disagreement_millis:.3}ms，超过 {}ms，已拒绝",
+                self.config.server, self.config.max_disagreement_millis
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,399 @@
+use std::{
+    collections::VecDeque,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use quanta::Instant;
+
+use crate::{
+    config::{NetworkChangeConfig, NtpConfig, ServerSelection},
+    ntp::{KissOfDeath, NtpSample},
+};
+/// RATE 退避后单个服务器查询间隔的上限，与 NTP 最大轮询指数 17 对应。
+const MAX_RATE_INTERVAL: Duration = Duration::from_secs(1 << 17);
+/// 每个服务器保留的最近样本数，用于估计最小延迟与抖动。
+const HISTORY_LEN: usize = 8;
+#[derive(Default)]
+struct ServerHistory {
+    delays: VecDeque<f64>,
+    offsets: VecDeque<f64>,
+}
+impl ServerHistory {
+    fn min_delay(&self) -> Option<f64> {
+        self.delays.iter().copied().reduce(f64::min)
+    }
+
+    /// 相邻偏移之差的均方根（秒），与 ntpd 的 jitter 定义相同；少于两个样本时无法估计。
+    fn jitter(&self) -> Option<f64> {
+        let diffs: Vec<f64> = self
+            .offsets
+            .iter()
+            .zip(self.offsets.iter().skip(1))
+            .map(|(a, b)| b - a)
+            .collect();
+        if diffs.is_empty() {
+            return None;
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let mean_square = diffs.iter().map(|d| d * d).sum::<f64>() / diffs.len() as f64;
+        Some(mean_square.sqrt())
+    }
+}
+/// 按配置的策略，以各服务器最近的延迟与抖动为服务器排名，供同步线程选择服务器。
+pub struct ServerScores {
+    selection: ServerSelection,
+    jitter_weight: f64,
+    servers: Vec<ServerHistory>,
+}
+impl ServerScores {
+    pub fn new(selection: ServerSelection, jitter_weight: f64, server_count: usize) -> Self {
+        Self {
+            selection,
+            jitter_weight,
+            servers: (0..server_count)
+                .map(|_| ServerHistory::default())
+                .collect(),
+        }
+    }
+
+    pub fn record(&mut self, server_index: usize, offset_secs: f64, delay_secs: f64) {
+        let Some(history) = self.servers.get_mut(server_index) else {
+            return;
+        };
+        for (values, value) in [
+            (&mut history.delays, delay_secs),
+            (&mut history.offsets, offset_secs),
+        ] {
+            if values.len() == HISTORY_LEN {
+                values.pop_front();
+            }
+            values.push_back(value);
+        }
+    }
+
+    /// 只保留每个服务器最近 `retained` 个样本，其余历史丢弃。
+    pub fn forget(&mut self, retained: usize) {
+        for history in &mut self.servers {
+            for values in [&mut history.delays, &mut history.offsets] {
+                let excess = values.len().saturating_sub(retained);
+                values.drain(..excess);
+            }
+        }
+    }
+
+    fn score(&self, server_index: usize) -> Option<f64> {
+        let history = &self.servers[server_index];
+        match self.selection {
+            ServerSelection::MinDelay => history.min_delay(),
+            ServerSelection::MinJitter => history.jitter(),
+            ServerSelection::Composite => Some(
+                self.jitter_weight
+                    .mul_add(history.jitter()?, history.min_delay()?),
+            ),
+        }
+    }
+
+    /// 各服务器的名次（0 为最好）。尚无足够样本的服务器排在最前，以便先收集到它们的数据。
+    pub fn ranks(&self) -> Vec<usize> {
+        let mut order: Vec<(usize, Option<f64>)> = (0..self.servers.len())
+            .map(|index| (index, self.score(index)))
+            .collect();
+        order.sort_by(|(_, a), (_, b)| match (a, b) {
+            (Some(a), Some(b)) => a.total_cmp(b),
+            (None, Some(_)) => std::cmp::Ordering::Less,
+            (Some(_), None) => std::cmp::Ordering::Greater,
+            (None, None) => std::cmp::Ordering::Equal,
+        });
+        let mut ranks = vec![0; self.servers.len()];
+        for (rank, (index, _)) in order.into_iter().enumerate() {
+            ranks[index] = rank;
+        }
+        ranks
+    }
+}
+/// 分级查询：优先使用高优先级层级的服务器，只有其中有响应的服务器少于 `min_responsive` 个时，
+/// 才逐级把后面的层级加入候选。尚未查询过的服务器视为有响应，以便先尝试高优先级层级。
+pub struct ServerTiers {
+    tiers: Vec<usize>,
+    responsive: Vec<bool>,
+    min_responsive: usize,
+}
+impl ServerTiers {
+    pub fn new(config: &NtpConfig) -> Option<Self> {
+        let server_tiers = config.server_tiers.as_ref()?;
+        Some(Self {
+            tiers: config
+                .servers
+                .iter()
+                .map(|server| config.server_tier(server))
+                .collect(),
+            responsive: vec![true; config.servers.len()],
+            min_responsive: server_tiers.min_responsive,
+        })
+    }
+
+    /// 把所有服务器重新视为有响应，从最高优先级层级开始重新尝试。
+    pub fn reset(&mut self) {
+        self.responsive.fill(true);
+    }
+
+    pub fn record(&mut self, server_index: usize, responded: bool) {
+        if let Some(responsive) = self.responsive.get_mut(server_index) {
+            *responsive = responded;
+        }
+    }
+
+    /// 当前启用的最深层级：从第 0 层起累计有响应的服务器，达到 `min_responsive` 即停止。
+    fn active_depth(&self) -> usize {
+        let deepest = self.tiers.iter().copied().max().unwrap_or(0);
+        let mut responsive = 0;
+        for depth in 0..=deepest {
+            responsive += self
+                .tiers
+                .iter()
+                .zip(&self.responsive)
+                .filter(|&(&tier, &ok)| tier == depth && ok)
+                .count();
+            if responsive >= self.min_responsive {
+                return depth;
+            }
+        }
+        deepest
+    }
+
+    /// 从本轮尚未尝试的候选中筛出启用层级内的服务器；启用层级已全部尝试过时，
+    /// 退到仍有候选的下一个层级，保证高层级耗尽后才查询低层级。
+    pub fn restrict(&self, candidates: &[usize]) -> Vec<usize> {
+        let depth = self.active_depth();
+        let active: Vec<usize> = candidates
+            .iter()
+            .copied()
+            .filter(|&index| self.tiers[index] <= depth)
+            .collect();
+        if !active.is_empty() {
+            return active;
+        }
+        let next_tier = candidates.iter().map(|&index| self.tiers[index]).min();
+        candidates
+            .iter()
+            .copied()
+            .filter(|&index| Some(self.tiers[index]) == next_tier)
+            .collect()
+    }
+}
+/// Kiss-o'-Death 退避：DENY 与 RSTR 使服务器在冷却期内退出轮换；RATE 使该服务器的查询间隔
+/// 从两倍平均同步间隔起逐次加倍，直到它再次正常响应。
+pub struct ServerBackoff {
+    until: Vec<Option<Instant>>,
+    rate_intervals: Vec<Duration>,
+    base_interval: Duration,
+    cooloff: Duration,
+}
+impl ServerBackoff {
+    pub fn new(config: &NtpConfig) -> Self {
+        Self {
+            until: vec![None; config.servers.len()],
+            rate_intervals: vec![Duration::ZERO; config.servers.len()],
+            base_interval: (config.sync_interval_min() + config.sync_interval_max()) / 2,
+            cooloff: config.kod_cooloff(),
+        }
+    }
+
+    /// 记录一个 Kiss-o'-Death，返回该服务器暂停查询的时长；未知代码不退避，返回 `None`。
+    pub fn record_kiss(&mut self, server_index: usize, kiss: &KissOfDeath) -> Option<Duration> {
+        let pause = if kiss.is_rate() {
+            let interval = (self.rate_intervals[server_index] * 2)
+                .max(self.base_interval * 2)
+                .min(MAX_RATE_INTERVAL);
+            self.rate_intervals[server_index] = interval;
+            interval
+        } else if kiss.denies_access() {
+            self.cooloff
+        } else {
+            return None;
+        };
+        self.until[server_index] = Some(Instant::now() + pause);
+        Some(pause)
+    }
+
+    /// 服务器正常响应后清除其 RATE 退避。
+    pub fn record_success(&mut self, server_index: usize) {
+        self.rate_intervals[server_index] = Duration::ZERO;
+    }
+
+    /// 从候选中去掉仍在退避期内的服务器。
+    pub fn restrict(&self, candidates: &[usize]) -> Vec<usize> {
+        let now = Instant::now();
+        candidates
+            .iter()
+            .copied()
+            .filter(|&index| self.until[index].is_none_or(|until| until <= now))
+            .collect()
+    }
+}
+/// 网络切换检测：同步器检测到最小延迟基线跳变（路径切换）时置位共享标志，
+/// 或同步线程连续 `failure_cycles` 个周期没有任何服务器成功时，判定网络已变化。
+pub struct NetworkChange {
+    config: NetworkChangeConfig,
+    failed_cycles: usize,
+}
+impl NetworkChange {
+    pub const fn new(config: NetworkChangeConfig) -> Self {
+        Self {
+            config,
+            failed_cycles: 0,
+        }
+    }
+
+    pub const fn record_cycle(&mut self, synced: bool) {
+        self.failed_cycles = if synced { 0 } else { self.failed_cycles + 1 };
+    }
+
+    /// 判定网络已变化时返回原因，并清除已消费的信号。
+    pub fn check(&mut self, path_changed: &AtomicBool) -> Option<String> {
+        if path_changed.swap(false, Ordering::SeqCst) {
+            self.failed_cycles = 0;
+            return Some("最小延迟基线跳变".to_owned());
+        }
+        if self.failed_cycles >= self.config.failure_cycles {
+            let reason = format!("连续 {} 个周期所有服务器均失败", self.failed_cycles);
+            self.failed_cycles = 0;
+            return Some(reason);
+        }
+        None
+    }
+}
+/// 法定数量校验：一个周期内收集多台服务器的样本，只有至少 `min_servers` 台的正确性区间
+/// （偏移 ± 根距离）存在公共交集时才接受时间，防止单台被篡改或配置错误的服务器带偏时钟。
+pub struct Quorum {
+    min_servers: usize,
+    samples: Vec<NtpSample>,
+}
+impl Quorum {
+    pub const fn new(min_servers: usize) -> Self {
+        Self {
+            min_servers,
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, sample: NtpSample) {
+        self.samples.push(sample);
+    }
+
+    pub const fn min_servers(&self) -> usize {
+        self.min_servers
+    }
+
+    pub const fn responded(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// 区间未知根距离的样本以单程延迟作为区间半宽。
+    fn interval(sample: &NtpSample) -> (f64, f64) {
+        let offset = sample.offset.as_seconds_f64();
+        let half_width = sample
+            .root_distance_secs
+            .unwrap_or_else(|| sample.delay.as_seconds_f64().max(0.0) / 2.0);
+        (offset - half_width, offset + half_width)
+    }
+
+    /// Marzullo 算法：返回被最多区间同时覆盖的区间及覆盖它的区间数。
+    fn intersection(&self) -> Option<((f64, f64), usize)> {
+        let mut edges: Vec<(f64, i32)> = self
+            .samples
+            .iter()
+            .map(Self::interval)
+            .flat_map(|(low, high)| [(low, -1), (high, 1)])
+            .collect();
+        // 同一位置先计入区间起点，使首尾相接的区间视为相交。
+        edges.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+        let mut best: Option<((f64, f64), usize)> = None;
+        let mut count = 0usize;
+        for (index, &(position, kind)) in edges.iter().enumerate() {
+            if kind < 0 {
+                count += 1;
+                if best.is_none_or(|(_, best_count)| count > best_count) {
+                    best = Some(((position, edges[index + 1].0), count));
+                }
+            } else {
+                count -= 1;
+            }
+        }
+        best
+    }
+
+    /// 达到法定数量时返回交集内的样本中延迟最小的一个，否则返回 `None`。
+    pub fn agreed(&self) -> Option<&NtpSample> {
+        let ((low, high), count) = self.intersection()?;
+        if count < self.min_servers {
+            return None;
+        }
+        self.samples
+            .iter()
+            .filter(|sample| {
+                let (sample_low, sample_high) = Self::interval(sample);
+                sample_low <= low && sample_high >= high
+            })
+            .min_by_key(|sample| sample.delay)
+    }
+
+    /// 交集内的最多样本数，供未达到法定数量时的提示使用。
+    pub fn agreeing(&self) -> usize {
+        self.intersection().map_or(0, |(_, count)| count)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+
+    fn kiss(code: &str) -> KissOfDeath {
+        KissOfDeath {
+            code: code.to_owned(),
+        }
+    }
+
+    fn backoff() -> (ServerBackoff, Duration) {
+        let config = AppConfig::builtin().unwrap().ntp;
+        let base = (config.sync_interval_min() + config.sync_interval_max()) / 2;
+        (ServerBackoff::new(&config), base)
+    }
+
+    #[test]
+    fn rate_kiss_doubles_up_to_the_cap() {
+        let (mut backoff, base) = backoff();
+        let mut expected = base * 2;
+        for _ in 0..32 {
+            assert_eq!(backoff.record_kiss(0, &kiss("RATE")), Some(expected));
+            expected = (expected * 2).min(MAX_RATE_INTERVAL);
+        }
+        assert_eq!(
+            backoff.record_kiss(0, &kiss("RATE")),
+            Some(MAX_RATE_INTERVAL)
+        );
+        assert!(backoff.restrict(&[0]).is_empty());
+    }
+
+    #[test]
+    fn success_resets_rate_backoff() {
+        let (mut backoff, base) = backoff();
+        backoff.record_kiss(0, &kiss("RATE"));
+        assert_eq!(backoff.record_kiss(0, &kiss("RATE")), Some(base * 4));
+        backoff.record_success(0);
+        assert_eq!(backoff.record_kiss(0, &kiss("RATE")), Some(base * 2));
+    }
+
+    #[test]
+    fn denial_uses_cooloff_and_unknown_codes_do_not_back_off() {
+        let config = AppConfig::builtin().unwrap().ntp;
+        let mut backoff = ServerBackoff::new(&config);
+        assert_eq!(
+            backoff.record_kiss(0, &kiss("DENY")),
+            Some(config.kod_cooloff())
+        );
+        assert_eq!(backoff.record_kiss(1, &kiss("INIT")), None);
+        assert_eq!(backoff.restrict(&[0, 1]), vec![1]);
+    }
+}
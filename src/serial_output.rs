@@ -0,0 +1,112 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    sync::{Arc, Mutex, mpsc},
+    thread,
+    time::Duration,
+};
+
+use chrono::{DateTime, DurationRound, TimeDelta, Utc};
+use nix::sys::termios::{self, BaudRate, SetArg};
+
+use crate::{
+    config::{SerialOutputConfig, SerialTimeFormat},
+    ntp::SyncMessage,
+    program_clock::ProgramClock,
+};
+const SOURCE: &str = "serial";
+/// 串口时间输出：每到校正后时间的整秒边界写出一行该秒的时间，行首字节的发送时刻即为准时标记，
+/// 把联网主机变成只接受串口时间的下游设备的时间源。尚未完成首次同步时不输出。
+pub fn start_serial_output_thread(
+    clock: Arc<Mutex<ProgramClock>>,
+    config: SerialOutputConfig,
+    tx: mpsc::Sender<SyncMessage>,
+) -> io::Result<()> {
+    let mut port = open_port(&config)?;
+    thread::spawn(move || {
+        let mut last_second = None;
+        let mut failing = false;
+        loop {
+            let wait = clock
+                .lock()
+                .unwrap()
+                .duration_to_next_boundary(Duration::from_secs(1));
+            thread::sleep(wait);
+            let (now, synchronized) = {
+                let clock = clock.lock().unwrap();
+                (clock.now(), clock.is_synchronized())
+            };
+            // 睡眠可能略早或略晚于边界返回，取最近的整秒；同一秒只输出一次。
+            let Ok(second) = now.duration_round(TimeDelta::seconds(1)) else {
+                continue;
+            };
+            if !synchronized || last_second == Some(second) {
+                continue;
+            }
+            last_second = Some(second);
+            let result = port
+                .write_all(format_line(second, config.format).as_bytes())
+                .and_then(|()| port.flush());
+            // 写入失败只在连续失败开始时提示一次，恢复后重新计数。
+            match result {
+                Ok(()) => failing = false,
+                Err(e) if !failing => {
+                    failing = true;
+                    let message = format!("写入串口 {} 失败: {e}", config.device.display());
+                    if tx
+                        .send(SyncMessage::Notice(SOURCE.to_owned(), message))
+                        .is_err()
+                    {
+                        return;
+                    }
+                }
+                Err(_) => {}
+            }
+        }
+    });
+    Ok(())
+}
+/// 以原始模式打开串口并设置波特率。
+fn open_port(config: &SerialOutputConfig) -> io::Result<File> {
+    let port = OpenOptions::new()
+        .write(true)
+        .open(&config.device)
+        .map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("无法打开串口 {}: {e}", config.device.display()),
+            )
+        })?;
+    let mut attributes = termios::tcgetattr(&port)?;
+    termios::cfmakeraw(&mut attributes);
+    termios::cfsetspeed(&mut attributes, baud_rate(config.baud_rate)?)?;
+    termios::tcsetattr(&port, SetArg::TCSANOW, &attributes)?;
+    Ok(port)
+}
+fn baud_rate(baud_rate: u32) -> io::Result<BaudRate> {
+    Ok(match baud_rate {
+        4800 => BaudRate::B4800,
+        9600 => BaudRate::B9600,
+        19200 => BaudRate::B19200,
+        38400 => BaudRate::B38400,
+        57600 => BaudRate::B57600,
+        115_200 => BaudRate::B115200,
+        230_400 => BaudRate::B230400,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("不支持的波特率 {baud_rate}"),
+            ));
+        }
+    })
+}
+fn format_line(second: DateTime<Utc>, format: SerialTimeFormat) -> String {
+    match format {
+        SerialTimeFormat::Iso => format!("{}\r\n", second.format("%Y-%m-%dT%H:%M:%SZ")),
+        SerialTimeFormat::Zda => {
+            let body = format!("GPZDA,{},00,00", second.format("%H%M%S.00,%d,%m,%Y"));
+            let checksum = body.bytes().fold(0u8, |checksum, byte| checksum ^ byte);
+            format!("${body}*{checksum:02X}\r\n")
+        }
+    }
+}
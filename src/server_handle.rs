@@ -0,0 +1,106 @@
+use std::{
+    io::{self, ErrorKind},
+    net::{SocketAddr, TcpListener, TcpStream, UdpSocket},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+/// 监听线程检查停止标志的间隔，也是停止后释放端口的最长等待时间。
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// 对外时间服务的监听线程。套接字以短超时轮询停止标志，[`Self::shutdown`] 或丢弃时等待线程退出，
+/// 返回时端口已释放，嵌入式服务停止后可立即重新绑定同一端口。
+#[derive(Default)]
+pub struct ServerHandle {
+    stop: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+}
+impl ServerHandle {
+    /// 在后台逐个接受 TCP 连接并交给 `serve` 处理，直到停止。
+    pub fn spawn_tcp(
+        &mut self,
+        listener: TcpListener,
+        mut serve: impl FnMut(TcpStream) + Send + 'static,
+    ) -> io::Result<()> {
+        listener.set_nonblocking(true)?;
+        let stop = Arc::clone(&self.stop);
+        self.threads.push(thread::spawn(move || {
+            while !stop.load(Ordering::SeqCst) {
+                match listener.accept() {
+                    // 部分平台上接受的连接继承非阻塞模式，处理函数依赖阻塞读写与超时。
+                    Ok((stream, _)) => {
+                        if stream.set_nonblocking(false).is_ok() {
+                            serve(stream);
+                        }
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+                    Err(_) => {}
+                }
+            }
+        }));
+        Ok(())
+    }
+
+    /// 在后台逐个接收 UDP 数据报并交给 `serve` 处理，直到停止。
+    pub fn spawn_udp(
+        &mut self,
+        socket: UdpSocket,
+        mut serve: impl FnMut(&UdpSocket, &[u8], SocketAddr) + Send + 'static,
+    ) -> io::Result<()> {
+        socket.set_read_timeout(Some(POLL_INTERVAL))?;
+        let stop = Arc::clone(&self.stop);
+        self.threads.push(thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            while !stop.load(Ordering::SeqCst) {
+                if let Ok((n, peer)) = socket.recv_from(&mut buf) {
+                    serve(&socket, &buf[..n], peer);
+                }
+            }
+        }));
+        Ok(())
+    }
+
+    /// 通知所有监听线程停止并等待其退出。
+    pub fn shutdown(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+impl Drop for ServerHandle {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shutdown_releases_the_ports() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let socket = UdpSocket::bind(addr).unwrap();
+        let mut handle = ServerHandle::default();
+        handle.spawn_tcp(listener, drop).unwrap();
+        handle
+            .spawn_udp(socket, |socket, request, peer| {
+                let _ = socket.send_to(request, peer);
+            })
+            .unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        client.send_to(b"ping", addr).unwrap();
+        let mut reply = [0u8; 4];
+        assert_eq!(client.recv(&mut reply).unwrap(), 4);
+        assert!(TcpStream::connect(addr).is_ok());
+        handle.shutdown();
+        TcpListener::bind(addr).unwrap();
+        UdpSocket::bind(addr).unwrap();
+    }
+}
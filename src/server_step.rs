@@ -0,0 +1,68 @@
+use std::collections::BTreeMap;
+
+use quanta::Instant;
+
+use crate::config::ServerStepConfig;
+/// 一次被排除的样本：偏移（秒），及它是否是该服务器这轮跳变中的第一个样本。
+pub struct ServerStep {
+    pub offset_secs: f64,
+    pub stable_servers: usize,
+    pub first: bool,
+}
+/// 区分上游服务器自身的时钟跳变与本地时钟问题。某个服务器的偏移突然超过阈值、
+/// 而窗口内其他服务器的最近偏移都保持在阈值以内时，认为是该服务器被步进，排除其样本。
+/// 若另一个已被标记的服务器以相近的幅度跳变，则说明是本地时钟的变化，恢复接受。
+pub struct ServerStepDetector {
+    config: ServerStepConfig,
+    recent: BTreeMap<String, (f64, Instant)>,
+    stepped: BTreeMap<String, f64>,
+}
+impl ServerStepDetector {
+    pub const fn new(config: ServerStepConfig) -> Self {
+        Self {
+            config,
+            recent: BTreeMap::new(),
+            stepped: BTreeMap::new(),
+        }
+    }
+
+    /// 检查一个样本，需要排除时返回跳变信息；接受的样本计入该服务器的最近偏移。
+    pub fn check(&mut self, server: &str, offset_secs: f64) -> Option<ServerStep> {
+        let now = Instant::now();
+        let window = self.config.window();
+        self.recent
+            .retain(|_, (_, at)| now.saturating_duration_since(*at) <= window);
+        let threshold = self.config.threshold_millis / 1000.0;
+        let shared_jump = self.stepped.iter().any(|(other, stepped_offset)| {
+            other != server && (stepped_offset - offset_secs).abs() <= threshold
+        });
+        if shared_jump {
+            self.stepped.clear();
+        }
+        let stable_servers = self
+            .recent
+            .iter()
+            .filter(|(other, (offset, _))| *other != server && offset.abs() <= threshold)
+            .count();
+        let others = self.recent.keys().filter(|other| *other != server).count();
+        if !shared_jump
+            && offset_secs.abs() > threshold
+            && stable_servers >= self.config.min_stable_servers
+            && stable_servers == others
+        {
+            self.recent.remove(server);
+            let first = self
+                .stepped
+                .insert(server.to_owned(), offset_secs)
+                .is_none();
+            return Some(ServerStep {
+                offset_secs,
+                stable_servers,
+                first,
+            });
+        }
+        self.stepped.remove(server);
+        self.recent.insert(server.to_owned(), (offset_secs, now));
+        None
+    }
+}
@@ -0,0 +1,142 @@
+use std::{
+    io,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::{config::AppConfig, ntp, program_clock::ProgramClock, synchronizer::Synchronizer};
+/// 后台线程处理同步消息的间隔。
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// 嵌入式同步服务：在后台线程中完成初始同步、周期同步与滤波，调用方只需读取时间。
+/// 不向标准输出打印，也不安装 Ctrl+C 处理器；配置中的日志、统计与对外时间服务照常生效。
+pub struct SyncService {
+    clock: Arc<Mutex<ProgramClock>>,
+    running: Arc<AtomicBool>,
+    worker: Option<JoinHandle<io::Result<()>>>,
+}
+impl SyncService {
+    /// 按配置创建同步器并在后台启动。初始同步在后台重试，与程序一样遵守法定数量并可退回 HTTP 时间；
+    /// 完成前 [`Self::current_time`] 返回以启动时系统时间为起点的时间，
+    /// 可用 [`Self::is_synchronized`] 判断是否已同步。
+    ///
+    /// # Errors
+    ///
+    /// 启动时间校验失败，或日志、统计等输出文件无法打开时返回错误。
+    pub fn start(config: &AppConfig) -> io::Result<Self> {
+        // 启动时间与持久化记录冲突时的提示只对交互程序有意义，这里直接采用校准后的起点。
+        let (initial_utc, _) = config.clock.reconcile_startup_utc(Utc::now(), false)?;
+        let mut synchronizer = Synchronizer::new(config, ProgramClock::new(initial_utc))?;
+        let clock = Arc::clone(synchronizer.clock());
+        let running = Arc::new(AtomicBool::new(true));
+        let worker = {
+            let running = Arc::clone(&running);
+            let ntp_config = config.ntp.clone();
+            let http_fallback = config.http_fallback.clone();
+            let query_servers = config.broadcast.is_none();
+            thread::spawn(move || {
+                // 与程序的初始同步相同：遵守法定数量，未配置时可退回 HTTP 时间。
+                // 广播模式下不主动查询服务器，直接等待第一个广播包完成首次校正。
+                while query_servers && running.load(Ordering::SeqCst) {
+                    let synced = ntp::initial_sync_round(
+                        synchronizer.clock(),
+                        &ntp_config,
+                        http_fallback.as_ref(),
+                        &mut |_| Ok(()),
+                    )?;
+                    if synced {
+                        break;
+                    }
+                    thread::sleep(ntp_config.initial_sync_retry_interval());
+                }
+                if !running.load(Ordering::SeqCst) {
+                    return Ok(());
+                }
+                synchronizer.start()?;
+                while running.load(Ordering::SeqCst) {
+                    // 事件已由同步器写入日志，这里只需取尽，让滤波与校正持续进行。
+                    while synchronizer.poll()?.is_some() {}
+                    thread::sleep(POLL_INTERVAL);
+                }
+                synchronizer.stop_servers();
+                Ok(())
+            })
+        };
+        Ok(Self {
+            clock,
+            running,
+            worker: Some(worker),
+        })
+    }
+
+    /// 校正后的当前时间。
+    pub fn current_time(&self) -> DateTime<Utc> {
+        self.clock.lock().unwrap().now()
+    }
+
+    pub fn is_synchronized(&self) -> bool {
+        self.clock.lock().unwrap().is_synchronized()
+    }
+
+    /// 共享的程序时钟，可交给需要自行读取时间的组件。
+    pub fn clock(&self) -> Arc<Mutex<ProgramClock>> {
+        Arc::clone(&self.clock)
+    }
+
+    /// 停止后台处理并返回其中发生的错误。初始同步尚未完成时不再启动同步线程；
+    /// HTTP 时间与 RFC 868 服务在返回前关闭，端口随之释放。同步线程在下一次上报结果时
+    /// 发现接收端已关闭而退出；停止后时钟仍按最后一次校正继续走时。
+    ///
+    /// # Errors
+    ///
+    /// 后台启动对外服务失败、处理同步消息出错或线程异常退出时返回该错误。
+    pub fn stop(mut self) -> io::Result<()> {
+        self.shutdown()
+    }
+
+    fn shutdown(&mut self) -> io::Result<()> {
+        self.running.store(false, Ordering::SeqCst);
+        let Some(worker) = self.worker.take() else {
+            return Ok(());
+        };
+        worker
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("同步服务线程异常退出")))
+    }
+}
+impl Drop for SyncService {
+    fn drop(&mut self) {
+        let _ = self.shutdown();
+    }
+}
+#[cfg(test)]
+mod tests {
+    use std::net::UdpSocket;
+
+    use super::*;
+
+    #[test]
+    fn stop_during_initial_sync_does_not_start_sync_threads() {
+        // 指向本机未监听的端口，初始同步的每一轮都会失败并持续重试。
+        let unused_port = UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut config = AppConfig::builtin().unwrap();
+        config.ntp.servers = vec!["127.0.0.1".to_owned()];
+        config.ntp.server_tiers = None;
+        config.ntp.port = unused_port;
+        config.http_fallback = None;
+        config.broadcast = None;
+        let service = SyncService::start(&config).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        assert!(!service.is_synchronized());
+        service.stop().unwrap();
+    }
+}
@@ -0,0 +1,122 @@
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{config::StartupStateConfig, kalman_filter::FilterState};
+/// 状态文件的格式版本，字段含义改变时递增；版本不符的文件整体忽略。
+const STATE_VERSION: u32 = 1;
+/// 持久化的时钟状态：保存时系统时钟的读数、程序时钟相对系统时钟的偏差，以及滤波器状态。
+#[derive(Serialize, Deserialize)]
+struct PersistedState {
+    #[serde(default)]
+    version: u32,
+    saved_at_os: String,
+    clock_minus_os_secs: f64,
+    #[serde(default)]
+    filter: Option<FilterState>,
+}
+/// 每次成功同步后保存时钟状态，供下次启动时跳过阻塞的初始同步。
+pub struct StartupStateStore {
+    config: StartupStateConfig,
+}
+impl StartupStateStore {
+    pub const fn new(config: StartupStateConfig) -> Self {
+        Self { config }
+    }
+
+    /// 先写临时文件再重命名，避免进程在写入中途退出留下不完整的状态。
+    pub fn save(
+        &self,
+        os_now: DateTime<Utc>,
+        clock_now: DateTime<Utc>,
+        filter: FilterState,
+    ) -> io::Result<()> {
+        let state = PersistedState {
+            version: STATE_VERSION,
+            saved_at_os: os_now.to_rfc3339_opts(SecondsFormat::Micros, true),
+            clock_minus_os_secs: (clock_now - os_now).as_seconds_f64(),
+            filter: Some(filter),
+        };
+        let bytes = serde_json::to_vec(&state).map_err(io::Error::other)?;
+        let path = Path::new(&self.config.path);
+        let temp_path = path.with_extension("tmp");
+        fs::write(&temp_path, bytes)?;
+        fs::rename(temp_path, path)
+    }
+
+    /// 上次保存的滤波器状态。漂移率是本机振荡器的长期特性，不受新鲜度窗口限制；
+    /// 文件不存在或没有滤波器状态时返回 `Ok(None)`，损坏或版本不符时返回原因。
+    pub fn saved_filter(&self) -> Result<Option<FilterState>, String> {
+        match read_state(&self.config) {
+            Ok(state) => Ok(state.filter),
+            Err(StateError::Missing(_)) => Ok(None),
+            Err(StateError::Invalid(reason)) => Err(reason),
+        }
+    }
+}
+enum StateError {
+    Missing(String),
+    Invalid(String),
+}
+impl From<StateError> for String {
+    fn from(error: StateError) -> Self {
+        match error {
+            StateError::Missing(reason) | StateError::Invalid(reason) => reason,
+        }
+    }
+}
+fn read_state(config: &StartupStateConfig) -> Result<PersistedState, StateError> {
+    let content = fs::read_to_string(&config.path).map_err(|e| match e.kind() {
+        ErrorKind::NotFound => StateError::Missing(format!("{} 不存在", config.path)),
+        _ => StateError::Invalid(format!("读取 {} 失败: {e}", config.path)),
+    })?;
+    let state: PersistedState = serde_json::from_str(&content)
+        .map_err(|e| StateError::Invalid(format!("{} 解析失败: {e}", config.path)))?;
+    if state.version != STATE_VERSION {
+        return Err(StateError::Invalid(format!(
+            "{} 的格式版本为 {}，当前版本为 {STATE_VERSION}",
+            config.path, state.version
+        )));
+    }
+    Ok(state)
+}
+/// 判断能否以保存的状态临时启动：状态需在新鲜度窗口内，且保存时程序时钟与系统时钟足够一致，
+/// 此时系统时钟可作为起点，加上保存的偏差得到初始时间。不满足时返回原因。
+pub fn provisional_start(
+    config: &StartupStateConfig,
+    os_now: DateTime<Utc>,
+) -> Result<DateTime<Utc>, String> {
+    let state = read_state(config)?;
+    let saved_at_os = DateTime::parse_from_rfc3339(&state.saved_at_os)
+        .map_err(|e| format!("{} 中的保存时间无效: {e}", config.path))?;
+    let age_secs = (os_now - saved_at_os.with_timezone(&Utc)).num_seconds();
+    if !(0..=i64::try_from(config.freshness_secs).unwrap_or(i64::MAX)).contains(&age_secs) {
+        return Err(format!(
+            "保存的状态距今 {age_secs} 秒，不在 {} 秒的新鲜度窗口内",
+            config.freshness_secs
+        ));
+    }
+    if !state.clock_minus_os_secs.is_finite()
+        || state.clock_minus_os_secs.abs() * 1000.0 > config.max_os_disagreement_millis
+    {
+        return Err(format!(
+            "保存时程序时钟与系统时钟相差 {:.3}ms，超过 {}ms",
+            state.clock_minus_os_secs * 1000.0,
+            config.max_os_disagreement_millis
+        ));
+    }
+    let offset = chrono::Duration::from_std(std::time::Duration::from_secs_f64(
+        state.clock_minus_os_secs.abs(),
+    ))
+    .map_err(|e| e.to_string())?;
+    Ok(if state.clock_minus_os_secs < 0.0 {
+        os_now - offset
+    } else {
+        os_now + offset
+    })
+}
@@ -0,0 +1,105 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    time::Duration,
+};
+
+use quanta::Instant;
+
+use crate::ntp::ServerRank;
+#[derive(Copy, Clone, Debug)]
+pub struct SamplePoint {
+    pub offset_secs: f64,
+    pub delay_secs: f64,
+}
+#[derive(Clone, Debug, Default)]
+pub struct ServerStats {
+    pub successes: u64,
+    pub failures: u64,
+    pub last_offset_secs: Option<f64>,
+    pub last_delay_secs: Option<f64>,
+    pub discovery_rank: Option<usize>,
+    pub steps: u64,
+}
+pub struct SyncStats {
+    history: VecDeque<SamplePoint>,
+    history_capacity: usize,
+    servers: BTreeMap<String, ServerStats>,
+    current_server: Option<String>,
+    success_instants: VecDeque<Instant>,
+}
+impl SyncStats {
+    pub fn new(history_capacity: usize) -> Self {
+        Self {
+            history: VecDeque::with_capacity(history_capacity),
+            history_capacity,
+            servers: BTreeMap::new(),
+            current_server: None,
+            success_instants: VecDeque::with_capacity(history_capacity),
+        }
+    }
+
+    pub fn record_attempt(&mut self, server: &str) {
+        self.servers.entry(server.to_owned()).or_default();
+        self.current_server = Some(server.to_owned());
+    }
+
+    pub fn record_failure(&mut self, server: &str) {
+        self.servers.entry(server.to_owned()).or_default().failures += 1;
+    }
+
+    /// 记录一次检测到的服务器时钟跳变，被排除的样本另按失败计数。
+    pub fn record_server_step(&mut self, server: &str) {
+        self.servers.entry(server.to_owned()).or_default().steps += 1;
+    }
+
+    pub fn record_sample(&mut self, server: &str, sample: SamplePoint) {
+        let entry = self.servers.entry(server.to_owned()).or_default();
+        entry.successes += 1;
+        entry.last_offset_secs = Some(sample.offset_secs);
+        entry.last_delay_secs = Some(sample.delay_secs);
+        if self.history.len() == self.history_capacity {
+            self.history.pop_front();
+        }
+        self.history.push_back(sample);
+    }
+
+    /// 记录一次成功同步的时刻，用于计算实际达到的采样间隔。
+    pub fn record_success_time(&mut self, at: Instant) {
+        if self.success_instants.len() == self.history_capacity {
+            self.success_instants.pop_front();
+        }
+        self.success_instants.push_back(at);
+    }
+
+    /// 最近若干次成功同步之间的平均间隔；轮询间隔的随机抖动与失败的周期都会反映在其中。
+    pub fn effective_interval(&self) -> Option<Duration> {
+        let (first, last) = (
+            self.success_instants.front()?,
+            self.success_instants.back()?,
+        );
+        let intervals = u32::try_from(self.success_instants.len() - 1).ok()?;
+        (intervals > 0).then(|| last.saturating_duration_since(*first) / intervals)
+    }
+
+    /// 记录发现阶段的名次，名次从 1 开始。
+    pub fn record_ranking(&mut self, ranking: &[ServerRank]) {
+        for (index, entry) in ranking.iter().enumerate() {
+            self.servers
+                .entry(entry.server.clone())
+                .or_default()
+                .discovery_rank = Some(index + 1);
+        }
+    }
+
+    pub const fn history(&self) -> &VecDeque<SamplePoint> {
+        &self.history
+    }
+
+    pub const fn servers(&self) -> &BTreeMap<String, ServerStats> {
+        &self.servers
+    }
+
+    pub fn current_server(&self) -> Option<&str> {
+        self.current_server.as_deref()
+    }
+}
@@ -0,0 +1,132 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use chrono::{SecondsFormat, Utc};
+use flate2::{Compression, write::GzEncoder};
+use serde::Serialize;
+
+use crate::config::LogConfig;
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum LogRecord<'a> {
+    Sample {
+        server: &'a str,
+        measured_offset_secs: f64,
+        delay_secs: f64,
+        smoothed_offset_secs: f64,
+        drift_ppm: f64,
+        process_noise_q: f64,
+        offset_sigma_secs: f64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        temperature_celsius: Option<f64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        effective_interval_secs: Option<f64>,
+    },
+    Failure {
+        server: &'a str,
+        error: &'a str,
+    },
+    Notice {
+        server: &'a str,
+        message: &'a str,
+    },
+}
+#[derive(Serialize)]
+struct LogLine<'a> {
+    time: String,
+    #[serde(flatten)]
+    record: &'a LogRecord<'a>,
+}
+struct LogWriter {
+    file: BufWriter<File>,
+    size: u64,
+}
+pub struct StructuredLog {
+    config: LogConfig,
+    writer: Mutex<LogWriter>,
+}
+impl StructuredLog {
+    pub fn open(config: &LogConfig) -> io::Result<Self> {
+        let writer = open_writer(&config.path)?;
+        Ok(Self {
+            config: config.clone(),
+            writer: Mutex::new(writer),
+        })
+    }
+
+    pub fn write(&self, record: &LogRecord<'_>) -> io::Result<()> {
+        let line = LogLine {
+            time: Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true),
+            record,
+        };
+        let mut bytes = serde_json::to_vec(&line).map_err(io::Error::other)?;
+        bytes.push(b'\n');
+        let mut writer = self.writer.lock().unwrap();
+        if writer.size > 0 && writer.size + bytes.len() as u64 > self.config.max_size_bytes {
+            writer.file.flush()?;
+            self.rotate()?;
+            *writer = open_writer(&self.config.path)?;
+        }
+        writer.file.write_all(&bytes)?;
+        writer.file.flush()?;
+        writer.size += bytes.len() as u64;
+        drop(writer);
+        Ok(())
+    }
+
+    fn rotate(&self) -> io::Result<()> {
+        let path = Path::new(&self.config.path);
+        for index in (1..=self.config.max_files).rev() {
+            for candidate in [
+                segment_path(path, index, false),
+                segment_path(path, index, true),
+            ] {
+                if !candidate.exists() {
+                    continue;
+                }
+                if index == self.config.max_files {
+                    fs::remove_file(&candidate)?;
+                } else {
+                    let compressed = candidate.extension().is_some_and(|ext| ext == "gz");
+                    fs::rename(&candidate, segment_path(path, index + 1, compressed))?;
+                }
+            }
+        }
+        if self.config.max_files == 0 {
+            return fs::remove_file(path);
+        }
+        let first = segment_path(path, 1, false);
+        fs::rename(path, &first)?;
+        if self.config.compress {
+            compress_file(&first, &segment_path(path, 1, true))?;
+        }
+        Ok(())
+    }
+}
+fn open_writer(path: &str) -> io::Result<LogWriter> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let size = file.metadata()?.len();
+    Ok(LogWriter {
+        file: BufWriter::new(file),
+        size,
+    })
+}
+fn segment_path(path: &Path, index: usize, compressed: bool) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{index}"));
+    if compressed {
+        name.push(".gz");
+    }
+    PathBuf::from(name)
+}
+fn compress_file(source: &Path, target: &Path) -> io::Result<()> {
+    let mut input = File::open(source)?;
+    let mut encoder = GzEncoder::new(File::create(target)?, Compression::default());
+    io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?;
+    fs::remove_file(source)
+}
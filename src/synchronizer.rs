@@ -0,0 +1,1205 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io,
+    sync::{Arc, Mutex, atomic::Ordering, mpsc},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use quanta::Instant;
+
+use crate::{
+    accuracy::{AccuracySummary, AccuracyTracker},
+    broadcast,
+    clock_step::ClockStepGuard,
+    config::{
+        AppConfig, BroadcastConfig, CorrectionReference, FilterDtSource, HttpFallbackConfig,
+        KalmanConfig, LocalSourceConfig, NtpConfig, TemperatureSourceConfig,
+    },
+    cross_check::{FilterCrossCheck, FilterDivergence},
+    demo,
+    drift_latch::DriftSafetyLatch,
+    exec_hook::{ExecHooks, HookContext},
+    http_time_server,
+    kalman_filter::{KalmanFilter, MAX_PREDICT_DT},
+    local_source, ntp,
+    ntpstats::NtpStatsWriter,
+    path_change::PathChangeDetector,
+    program_clock::{ProgramClock, secs_to_chrono, try_secs_to_chrono},
+    rate_limit::QueryRateLimiter,
+    rfc868_server,
+    server_handle::ServerHandle,
+    server_step::ServerStepDetector,
+    startup_state::StartupStateStore,
+    stats::{SamplePoint, ServerStats, SyncStats},
+    structured_log::{LogRecord, StructuredLog},
+    temperature,
+    time_file::{TimeFileSnapshot, TimeFileWriter},
+    update_quota::{PendingUpdate, UpdateQuota},
+    watchdog::SyncWatchdog,
+};
+#[cfg(feature = "serial-output")]
+use crate::{config::SerialOutputConfig, serial_output};
+/// 经 [`Synchronizer::inject_measurement`] 注入的外部测量在统计与日志中的来源名称。
+const EXTERNAL_SOURCE: &str = "external";
+#[derive(Copy, Clone, Debug)]
+pub struct SyncReport {
+    pub measured_offset_secs: f64,
+    pub measured_delay_secs: f64,
+    pub smoothed_offset_secs: f64,
+    pub drift_ppm: f64,
+    pub process_noise_q: f64,
+    pub offset_sigma_secs: f64,
+    pub effective_interval_secs: Option<f64>,
+}
+/// 最近一次送入滤波器之前的原始测量（未经校正基准换算与卡尔曼平滑），供调试或自行滤波的使用方读取。
+#[derive(Clone, Debug)]
+pub struct RawMeasurement {
+    pub server: String,
+    pub offset_secs: f64,
+    pub delay_secs: f64,
+    pub measured_at: Instant,
+}
+#[derive(Copy, Clone, Debug)]
+pub struct ClockQuality {
+    pub precision: i8,
+    pub root_delay_secs: f64,
+    pub root_dispersion_secs: f64,
+}
+impl ClockQuality {
+    /// 由滤波器的偏移方差与漂移方差推导对外报告的时钟质量。
+    /// 离散度在偏移标准差之上，按漂移标准差随距上次更新的时间线性增长。
+    fn from_filter(kalman_filter: &KalmanFilter, root_delay_secs: f64) -> Self {
+        let offset_sigma = kalman_filter.get_offset_uncertainty();
+        let drift_sigma = kalman_filter.get_drift_uncertainty();
+        #[allow(clippy::cast_possible_truncation)]
+        let precision = offset_sigma.log2().floor().clamp(-32.0, 0.0) as i8;
+        Self {
+            precision,
+            root_delay_secs,
+            root_dispersion_secs: drift_sigma
+                .mul_add(kalman_filter.since_last_update(), offset_sigma),
+        }
+    }
+
+    pub fn ntp_root_delay(&self) -> u32 {
+        ntp::to_short_format(self.root_delay_secs)
+    }
+
+    pub fn ntp_root_dispersion(&self) -> u32 {
+        ntp::to_short_format(self.root_dispersion_secs)
+    }
+}
+#[derive(Clone, Debug)]
+pub struct SyncState {
+    pub time: DateTime<Utc>,
+    pub synchronized: bool,
+    pub offset_secs: f64,
+    pub drift_ppm: f64,
+    pub offset_variance: f64,
+    pub last_sync_age: Option<Duration>,
+    /// 最近一次被滤波器接受的 NTP 样本中服务器报告的 stratum；外部注入的测量没有层级。
+    pub stratum: Option<u8>,
+    pub current_server: Option<String>,
+    pub servers: BTreeMap<String, ServerStats>,
+    pub quality: ClockQuality,
+    pub paused: bool,
+    pub filter_divergence: Option<FilterDivergence>,
+    pub provisional: bool,
+    pub accuracy: Option<AccuracySummary>,
+    pub queries_last_minute: Option<usize>,
+    pub last_raw: Option<RawMeasurement>,
+    pub safed_drift_ppm: Option<f64>,
+    pub clock_error: Option<String>,
+}
+pub enum SyncEvent {
+    Syncing(String),
+    Failed(String),
+    Notice(String),
+    Updated(SyncReport),
+}
+pub struct Synchronizer {
+    clock: Arc<Mutex<ProgramClock>>,
+    kalman_filter: KalmanFilter,
+    stats: SyncStats,
+    kalman_config: KalmanConfig,
+    ntp_config: NtpConfig,
+    local_source_config: Option<LocalSourceConfig>,
+    http_fallback_config: Option<HttpFallbackConfig>,
+    broadcast_config: Option<BroadcastConfig>,
+    http_time_port: Option<u16>,
+    rfc868_port: Option<u16>,
+    servers: Vec<ServerHandle>,
+    #[cfg(feature = "serial-output")]
+    serial_output_config: Option<SerialOutputConfig>,
+    correction_reference: CorrectionReference,
+    log: Option<StructuredLog>,
+    ntp_stats: Option<NtpStatsWriter>,
+    path_change: Option<PathChangeDetector>,
+    server_step: Option<ServerStepDetector>,
+    drift_latch: Option<DriftSafetyLatch>,
+    update_quota: Option<UpdateQuota>,
+    time_file: Option<TimeFileWriter>,
+    cross_check: Option<FilterCrossCheck>,
+    clock_step_guard: Option<ClockStepGuard>,
+    temperature_source: Option<TemperatureSourceConfig>,
+    temperature_failing: bool,
+    startup_state: Option<StartupStateStore>,
+    provisional: bool,
+    accuracy: Option<AccuracyTracker>,
+    exec_hooks: Option<ExecHooks>,
+    last_sync_at: Option<Instant>,
+    last_stratum: Option<u8>,
+    last_delay_secs: f64,
+    last_raw: Option<RawMeasurement>,
+    control: ntp::SyncControl,
+    query_limiter: Option<Arc<QueryRateLimiter>>,
+    drift_exceed_count: usize,
+    nis_runaway_resets: usize,
+    rejected_outliers: usize,
+    last_filter_clock: Option<DateTime<Utc>>,
+    pending_events: VecDeque<SyncEvent>,
+    watchdog: Option<SyncWatchdog>,
+    sync_tx: Option<mpsc::Sender<ntp::SyncMessage>>,
+    rx: Option<mpsc::Receiver<ntp::SyncMessage>>,
+}
+impl Synchronizer {
+    pub fn new(config: &AppConfig, mut clock: ProgramClock) -> io::Result<Self> {
+        let mut kalman_filter = new_filter(&config.kalman);
+        clock.set_slew_limits(
+            config.clock.max_slew_ppm(),
+            config.clock.max_slew_duration(),
+        );
+        clock.set_monotonic(config.clock.monotonic);
+        #[cfg(feature = "skew-injection")]
+        if let Some(debug) = &config.debug {
+            clock.inject_skew(debug.inject_offset_millis / 1000.0, debug.inject_drift_ppm);
+        }
+        let startup_state = config.startup_state.clone().map(StartupStateStore::new);
+        let restore_notice = startup_state
+            .as_ref()
+            .and_then(|store| restore_filter(store, &mut kalman_filter));
+        let log = config.log.as_ref().map(StructuredLog::open).transpose()?;
+        let cross_check = config
+            .cross_check
+            .clone()
+            .map(|cross_check| FilterCrossCheck::new(cross_check, &kalman_filter));
+        let ntp_stats = config
+            .stats_dir
+            .as_deref()
+            .map(NtpStatsWriter::open)
+            .transpose()?;
+        let mut synchronizer = Self {
+            clock: Arc::new(Mutex::new(clock)),
+            kalman_filter,
+            stats: SyncStats::new(config.ui.history_size()),
+            kalman_config: config.kalman.clone(),
+            ntp_config: config.ntp.clone(),
+            local_source_config: config.local_source.clone(),
+            http_fallback_config: config.http_fallback.clone(),
+            broadcast_config: config.broadcast.clone(),
+            http_time_port: config.http_time_port,
+            rfc868_port: config.rfc868_port,
+            servers: Vec::new(),
+            #[cfg(feature = "serial-output")]
+            serial_output_config: config.serial_output.clone(),
+            correction_reference: config.clock.correction_reference(),
+            log,
+            ntp_stats,
+            path_change: config.path_change.clone().map(PathChangeDetector::new),
+            server_step: config.server_step.clone().map(ServerStepDetector::new),
+            drift_latch: config.drift_latch.clone().map(DriftSafetyLatch::new),
+            update_quota: config.kalman.max_updates_per_minute.map(UpdateQuota::new),
+            time_file: config
+                .mmap_path
+                .as_deref()
+                .map(TimeFileWriter::create)
+                .transpose()?,
+            cross_check,
+            clock_step_guard: config
+                .clock_step_guard
+                .as_ref()
+                .map(|guard| ClockStepGuard::new(guard.max_discrepancy_millis)),
+            temperature_source: config.temperature_source.clone(),
+            temperature_failing: false,
+            startup_state,
+            provisional: false,
+            accuracy: config
+                .accuracy
+                .clone()
+                .map(AccuracyTracker::open)
+                .transpose()?,
+            exec_hooks: config.exec.clone().map(ExecHooks::new),
+            last_sync_at: None,
+            last_stratum: None,
+            last_delay_secs: 0.0,
+            last_raw: None,
+            control: ntp::SyncControl::default(),
+            query_limiter: config
+                .ntp
+                .max_queries_per_minute
+                .map(|max_queries_per_minute| {
+                    Arc::new(QueryRateLimiter::new(
+                        max_queries_per_minute,
+                        config.ntp.samples_per_poll(),
+                    ))
+                }),
+            drift_exceed_count: 0,
+            nis_runaway_resets: 0,
+            rejected_outliers: 0,
+            last_filter_clock: None,
+            pending_events: VecDeque::new(),
+            watchdog: config.ntp.heartbeat_timeout().map(SyncWatchdog::new),
+            sync_tx: None,
+            rx: None,
+        };
+        if let Some(message) = restore_notice {
+            synchronizer.log(&LogRecord::Notice {
+                server: "startup_state",
+                message: &message,
+            })?;
+            synchronizer
+                .pending_events
+                .push_back(SyncEvent::Notice(message));
+        }
+        Ok(synchronizer)
+    }
+
+    pub fn start(&mut self) -> io::Result<()> {
+        if self.rx.is_some() {
+            return Ok(());
+        }
+        if let Some(port) = self.http_time_port {
+            self.servers.push(http_time_server::start_http_time_server(
+                Arc::clone(&self.clock),
+                port,
+            )?);
+        }
+        if let Some(port) = self.rfc868_port {
+            self.servers.push(rfc868_server::start_rfc868_server(
+                Arc::clone(&self.clock),
+                self.ntp_config.clone(),
+                port,
+            )?);
+        }
+        let (tx, rx) = mpsc::channel::<ntp::SyncMessage>();
+        #[cfg(feature = "serial-output")]
+        if let Some(serial_output_config) = &self.serial_output_config {
+            serial_output::start_serial_output_thread(
+                Arc::clone(&self.clock),
+                serial_output_config.clone(),
+                tx.clone(),
+            )?;
+        }
+        if let Some(local_source_config) = &self.local_source_config {
+            local_source::start_local_source_thread(
+                Arc::clone(&self.clock),
+                local_source_config.clone(),
+                tx.clone(),
+            );
+        }
+        if let Some(broadcast_config) = &self.broadcast_config {
+            broadcast::start_broadcast_thread(
+                Arc::clone(&self.clock),
+                self.ntp_config.clone(),
+                broadcast_config.clone(),
+                tx,
+            )?;
+        } else {
+            if self.watchdog.is_some() {
+                self.sync_tx = Some(tx.clone());
+            }
+            ntp::start_sync_thread(
+                Arc::clone(&self.clock),
+                self.ntp_config.clone(),
+                self.http_fallback_config.clone(),
+                self.control.clone(),
+                self.query_limiter.clone(),
+                self.watchdog.as_ref().map(SyncWatchdog::heartbeat),
+                tx,
+            );
+        }
+        self.rx = Some(rx);
+        Ok(())
+    }
+
+    /// 停止 HTTP 时间与 RFC 868 服务并等待其线程退出，返回时端口已释放。
+    pub fn stop_servers(&mut self) {
+        for mut server in self.servers.drain(..) {
+            server.shutdown();
+        }
+    }
+
+    /// 启动演示数据源代替所有真实时间源。
+    pub fn start_demo(&mut self) {
+        if self.rx.is_some() {
+            return;
+        }
+        let (tx, rx) = mpsc::channel::<ntp::SyncMessage>();
+        demo::start_demo_thread(Arc::clone(&self.clock), tx);
+        self.rx = Some(rx);
+    }
+
+    pub const fn clock(&self) -> &Arc<Mutex<ProgramClock>> {
+        &self.clock
+    }
+
+    pub fn try_now(&self) -> io::Result<DateTime<Utc>> {
+        self.clock.lock().unwrap().try_now()
+    }
+
+    pub fn is_synchronized(&self) -> bool {
+        self.clock.lock().unwrap().is_synchronized()
+    }
+
+    pub const fn stats(&self) -> &SyncStats {
+        &self.stats
+    }
+
+    /// 在一次时钟加锁内读取时间，并与同一时刻的滤波器状态和统计一并返回。
+    pub fn snapshot(&self) -> SyncState {
+        let clock = self.clock.lock().unwrap();
+        let (time, clock_error) = match clock.try_now() {
+            Ok(time) => (time, None),
+            Err(e) => (Utc::now(), Some(e.to_string())),
+        };
+        let synchronized = clock.is_synchronized();
+        let state = SyncState {
+            time,
+            synchronized,
+            offset_secs: self.kalman_filter.get_offset(),
+            drift_ppm: self.kalman_filter.get_drift_ppm(),
+            offset_variance: self.kalman_filter.get_offset_variance(),
+            last_sync_age: self.last_sync_at.map(|at| at.elapsed()),
+            stratum: self.last_stratum,
+            current_server: self.stats.current_server().map(str::to_owned),
+            servers: self.stats.servers().clone(),
+            quality: self.quality(),
+            paused: self.is_paused(),
+            filter_divergence: self
+                .cross_check
+                .as_ref()
+                .and_then(FilterCrossCheck::divergence),
+            provisional: self.provisional,
+            accuracy: self.accuracy.as_ref().and_then(AccuracyTracker::summary),
+            queries_last_minute: self
+                .query_limiter
+                .as_ref()
+                .map(|limiter| limiter.queries_last_minute()),
+            last_raw: self.last_raw_measurement().cloned(),
+            safed_drift_ppm: self
+                .drift_latch
+                .as_ref()
+                .and_then(DriftSafetyLatch::safed_drift_ppm),
+            clock_error,
+        };
+        drop(clock);
+        state
+    }
+
+    /// 最近一次通过有效性检查的原始测量，与滤波后的偏移并列，便于只需要测量值的使用方自行处理。
+    pub const fn last_raw_measurement(&self) -> Option<&RawMeasurement> {
+        self.last_raw.as_ref()
+    }
+
+    /// 以保存的状态启动、尚未完成首次网络同步时标记为临时同步状态，首个测量到达后自动清除。
+    pub const fn mark_provisional(&mut self) {
+        self.provisional = true;
+    }
+
+    /// 暂停网络同步，同步线程保持运行但不再发出查询，时钟按当前状态自由运行。
+    pub fn pause(&self) {
+        self.control.paused.store(true, Ordering::SeqCst);
+    }
+
+    pub fn resume(&self) {
+        self.control.paused.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.control.paused.load(Ordering::SeqCst)
+    }
+
+    /// 手动解除漂移安全锁，滤波器的漂移率恢复为锁定前最后一次可信的估计。未锁定时不做任何事。
+    pub fn reset_drift_latch(&mut self) -> io::Result<()> {
+        let Some(drift_ppm) = self
+            .drift_latch
+            .as_mut()
+            .and_then(DriftSafetyLatch::release)
+        else {
+            return Ok(());
+        };
+        self.restore_filter_drift("manual", drift_ppm, "已手动解除")
+    }
+
+    pub fn quality(&self) -> ClockQuality {
+        ClockQuality::from_filter(&self.kalman_filter, self.last_delay_secs)
+    }
+
+    pub fn poll(&mut self) -> io::Result<Option<SyncEvent>> {
+        self.check_holdover()?;
+        self.check_sync_watchdog()?;
+        self.publish_time_file()?;
+        if let Some(event) = self.pending_events.pop_front() {
+            return Ok(Some(event));
+        }
+        let Some(message) = self.rx.as_ref().and_then(|rx| rx.try_recv().ok()) else {
+            return Ok(None);
+        };
+        self.handle_sync_message(message).map(Some)
+    }
+
+    /// 记录一条来自界面等非同步来源的提示，写入日志并在下次 `poll` 时作为事件返回。
+    pub fn notify(&mut self, message: &str) -> io::Result<()> {
+        self.log(&LogRecord::Notice {
+            server: "ui",
+            message,
+        })?;
+        self.pending_events
+            .push_back(SyncEvent::Notice(message.to_owned()));
+        Ok(())
+    }
+
+    /// 把当前时间与不确定度写入共享时间文件；调用方每次界面刷新都会经过 `poll`。
+    fn publish_time_file(&mut self) -> io::Result<()> {
+        let Some(time_file) = &mut self.time_file else {
+            return Ok(());
+        };
+        let (time, synchronized) = {
+            let clock = self.clock.lock().unwrap();
+            (clock.now(), clock.is_synchronized())
+        };
+        time_file.publish(&TimeFileSnapshot {
+            time,
+            synchronized,
+            uncertainty_secs: self.kalman_filter.get_offset_uncertainty(),
+            drift_ppm: self.kalman_filter.get_drift_ppm(),
+        })
+    }
+
+    /// 同步线程的心跳停滞超过阈值时记录错误并重建线程，覆盖线程存活却卡住、不会崩溃的情形。
+    fn check_sync_watchdog(&mut self) -> io::Result<()> {
+        let (Some(watchdog), Some(tx)) = (&mut self.watchdog, &self.sync_tx) else {
+            return Ok(());
+        };
+        let Some(stalled) = watchdog.check() else {
+            return Ok(());
+        };
+        let heartbeat = watchdog.renew();
+        let restarts = watchdog.restarts();
+        ntp::start_sync_thread(
+            Arc::clone(&self.clock),
+            self.ntp_config.clone(),
+            self.http_fallback_config.clone(),
+            self.control.clone(),
+            self.query_limiter.clone(),
+            Some(heartbeat),
+            tx.clone(),
+        );
+        let error = format!(
+            "同步线程已停滞 {:.0} 秒（心跳未更新），已重建同步线程（累计 {restarts} 次）",
+            stalled.as_secs_f64()
+        );
+        self.log(&LogRecord::Failure {
+            server: "watchdog",
+            error: &error,
+        })?;
+        self.pending_events.push_back(SyncEvent::Failed(error));
+        Ok(())
+    }
+
+    /// 长时间没有成功同步时通知钩子转入保持状态。
+    fn check_holdover(&mut self) -> io::Result<()> {
+        let Some(exec_hooks) = &mut self.exec_hooks else {
+            return Ok(());
+        };
+        let failures = exec_hooks.check_holdover(
+            self.last_sync_at.map(|at| at.elapsed()),
+            HookContext {
+                offset_secs: self.kalman_filter.get_offset(),
+                drift_ppm: self.kalman_filter.get_drift_ppm(),
+            },
+        );
+        self.report_hook_failures(failures)
+    }
+
+    /// 钩子命令启动失败不影响同步，只作为提示记录。
+    fn report_hook_failures(&mut self, failures: Vec<String>) -> io::Result<()> {
+        for failure in failures {
+            self.log(&LogRecord::Notice {
+                server: "exec",
+                message: &failure,
+            })?;
+            self.pending_events.push_back(SyncEvent::Notice(failure));
+        }
+        Ok(())
+    }
+
+    fn log(&self, record: &LogRecord<'_>) -> io::Result<()> {
+        self.log.as_ref().map_or(Ok(()), |log| log.write(record))
+    }
+
+    fn write_ntp_stats(&mut self, server: &str, report: &SyncReport) -> io::Result<()> {
+        let Some(ntp_stats) = &mut self.ntp_stats else {
+            return Ok(());
+        };
+        let now = Utc::now();
+        let quality = ClockQuality::from_filter(&self.kalman_filter, report.measured_delay_secs);
+        ntp_stats.write_loop(
+            now,
+            report.smoothed_offset_secs,
+            report.drift_ppm,
+            report.offset_sigma_secs,
+            self.kalman_filter.get_drift_uncertainty() * 1_000_000.0,
+        )?;
+        ntp_stats.write_peer(
+            now,
+            server,
+            report.measured_offset_secs,
+            report.measured_delay_secs,
+            quality.root_dispersion_secs,
+            (report.measured_offset_secs - report.smoothed_offset_secs).abs(),
+        )
+    }
+
+    /// 注入一个外部偏移测量（秒）及其测量噪声方差 `r`，与 NTP 样本走相同的滤波与校正流程。
+    /// 供 GPS、手动校正等非 NTP 时间源使用，统计与日志中的来源记为 `external`。
+    pub fn inject_measurement(&mut self, offset_seconds: f64, r: f64) -> io::Result<SyncReport> {
+        if !offset_seconds.is_finite() || !r.is_finite() || r < 0.0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "外部测量的偏移与噪声方差必须为有限值，且方差不能为负",
+            ));
+        }
+        let report =
+            self.fuse_measurement(EXTERNAL_SOURCE, offset_seconds, 0.0, r, Instant::now())?;
+        self.last_stratum = None;
+        Ok(report)
+    }
+
+    /// 用一个测量更新滤波器并校正程序时钟，记录统计、日志后返回本次结果。
+    fn fuse_measurement(
+        &mut self,
+        server: &str,
+        measured_offset_secs: f64,
+        measured_delay_secs: f64,
+        measurement_noise_r: f64,
+        measured_at: Instant,
+    ) -> io::Result<SyncReport> {
+        if self.drift_latch_holds(server, measured_offset_secs)? {
+            return Ok(self.hold_over(server, measured_offset_secs, measured_delay_secs));
+        }
+        if let Some(accuracy) = &mut self.accuracy {
+            let clock_before = self.clock.lock().unwrap().now();
+            accuracy.record_reference(server, clock_before, measured_offset_secs);
+        }
+        let dt = self.filter_dt(server)?;
+        self.kalman_filter.reset_timestamp();
+        let smoothed_offset_secs =
+            self.kalman_filter
+                .update_with_dt(measured_offset_secs, measurement_noise_r, dt);
+        self.check_filter_divergence(server, measured_offset_secs, measurement_noise_r, dt)?;
+        if self.trip_drift_latch(server)? {
+            return Ok(self.hold_over(server, measured_offset_secs, measured_delay_secs));
+        }
+        let smoothed_offset = try_secs_to_chrono(smoothed_offset_secs)
+            .map_err(|e| io::Error::new(e.kind(), format!("滤波偏移无法应用到时钟: {e}")))?;
+        match self.correction_reference {
+            CorrectionReference::Measurement => self
+                .clock
+                .lock()
+                .unwrap()
+                .apply_offset_at(smoothed_offset, measured_at),
+            CorrectionReference::Application => {
+                self.clock.lock().unwrap().apply_offset(smoothed_offset);
+            }
+        }
+        self.last_sync_at = Some(Instant::now());
+        self.last_delay_secs = measured_delay_secs;
+        self.provisional = false;
+        self.after_correction(smoothed_offset_secs)?;
+        self.stats.record_sample(
+            server,
+            SamplePoint {
+                offset_secs: measured_offset_secs,
+                delay_secs: measured_delay_secs,
+            },
+        );
+        self.stats.record_success_time(Instant::now());
+        let report = SyncReport {
+            measured_offset_secs,
+            measured_delay_secs,
+            smoothed_offset_secs,
+            drift_ppm: self.kalman_filter.get_drift_ppm(),
+            process_noise_q: self.kalman_filter.get_process_noise_q(),
+            offset_sigma_secs: self.kalman_filter.get_offset_uncertainty(),
+            effective_interval_secs: self
+                .stats
+                .effective_interval()
+                .map(|interval| interval.as_secs_f64()),
+        };
+        let temperature_celsius = self.read_temperature(server)?;
+        self.log(&LogRecord::Sample {
+            server,
+            measured_offset_secs,
+            delay_secs: measured_delay_secs,
+            smoothed_offset_secs,
+            drift_ppm: report.drift_ppm,
+            process_noise_q: report.process_noise_q,
+            offset_sigma_secs: report.offset_sigma_secs,
+            temperature_celsius,
+            effective_interval_secs: report.effective_interval_secs,
+        })?;
+        self.write_ntp_stats(server, &report)?;
+        self.check_drift_health(server)?;
+        self.check_nis_runaway(server)?;
+        self.check_outlier_rejection(server, measured_offset_secs)?;
+        Ok(report)
+    }
+
+    /// 按配置的时间来源计算本次滤波更新的预测步长。程序时钟来源取扣除校正后的读数之差，
+    /// 滤波器自身施加的校正不会计入下一次的步长；首次更新或步长基准被重置后退回单调时钟。
+    /// 程序时钟来源的步长为负、非有限或超过预测步长上限时视为时间来源异常，提示后本次改用单调时钟。
+    fn filter_dt(&mut self, server: &str) -> io::Result<f64> {
+        let FilterDtSource::ProgramClock = self.kalman_config.dt_source else {
+            return Ok(self.kalman_filter.since_last_update());
+        };
+        let now = self.clock.lock().unwrap().uncorrected_now();
+        let Some(last) = self.last_filter_clock.replace(now) else {
+            return Ok(self.kalman_filter.since_last_update());
+        };
+        let dt = (now - last).as_seconds_f64();
+        if dt.is_finite() && (0.0..=MAX_PREDICT_DT.as_secs_f64()).contains(&dt) {
+            return Ok(dt);
+        }
+        let fallback = self.kalman_filter.since_last_update();
+        let notice = format!(
+            "程序时钟给出的预测步长 {dt:.3}s 异常，本次改用单调时钟的经过时间 {fallback:.3}s"
+        );
+        self.log(&LogRecord::Notice {
+            server,
+            message: &notice,
+        })?;
+        self.pending_events.push_back(SyncEvent::Notice(notice));
+        Ok(fallback)
+    }
+
+    /// 校正程序时钟之后更新依赖校正结果的组件：启动状态、实测精度与外部命令钩子。
+    fn after_correction(&mut self, smoothed_offset_secs: f64) -> io::Result<()> {
+        if let Some(startup_state) = &self.startup_state {
+            let clock_now = self.clock.lock().unwrap().now();
+            startup_state.save(Utc::now(), clock_now, self.kalman_filter.state())?;
+        }
+        if let Some(accuracy) = &mut self.accuracy {
+            let clock_now = self.clock.lock().unwrap().now();
+            accuracy.assess(clock_now)?;
+        }
+        if let Some(exec_hooks) = &mut self.exec_hooks {
+            let failures = exec_hooks.on_correction(
+                smoothed_offset_secs,
+                HookContext {
+                    offset_secs: smoothed_offset_secs,
+                    drift_ppm: self.kalman_filter.get_drift_ppm(),
+                },
+            );
+            self.report_hook_failures(failures)?;
+        }
+        Ok(())
+    }
+
+    /// 滤波器更新后检查漂移安全锁，本次触发锁定时发出 FILTER SAFED 提示并返回 `true`。
+    fn trip_drift_latch(&mut self, server: &str) -> io::Result<bool> {
+        let drift_ppm = self.kalman_filter.get_drift_ppm();
+        let Some(latch) = &mut self.drift_latch else {
+            return Ok(false);
+        };
+        let Some(good_drift_ppm) = latch.observe_drift(drift_ppm) else {
+            return Ok(false);
+        };
+        let notice = format!(
+            "FILTER SAFED：漂移率估计 {drift_ppm:.2} ppm 持续超过安全上限 {} ppm，\
+             已停止施加滤波器校正，按最近可信漂移率 {good_drift_ppm:.2} ppm 保持",
+            latch.max_drift_ppm()
+        );
+        self.log(&LogRecord::Notice {
+            server,
+            message: &notice,
+        })?;
+        self.pending_events.push_back(SyncEvent::Notice(notice));
+        Ok(true)
+    }
+
+    /// 安全锁锁定期间记录测量偏移；仍处于锁定时返回 `true`，连续正常后自动解除并恢复可信漂移率。
+    fn drift_latch_holds(&mut self, server: &str, measured_offset_secs: f64) -> io::Result<bool> {
+        let Some(latch) = &mut self.drift_latch else {
+            return Ok(false);
+        };
+        if !latch.is_safed() {
+            return Ok(false);
+        }
+        match latch.observe_offset(measured_offset_secs) {
+            Some(drift_ppm) => {
+                self.restore_filter_drift(server, drift_ppm, "测量已连续恢复正常，自动解除")?;
+                Ok(false)
+            }
+            None => Ok(true),
+        }
+    }
+
+    fn restore_filter_drift(
+        &mut self,
+        server: &str,
+        drift_ppm: f64,
+        reason: &str,
+    ) -> io::Result<()> {
+        self.kalman_filter.restore_drift(drift_ppm);
+        let notice = format!("漂移安全锁{reason}，滤波器漂移率恢复为 {drift_ppm:.2} ppm");
+        self.log(&LogRecord::Notice {
+            server,
+            message: &notice,
+        })?;
+        self.pending_events.push_back(SyncEvent::Notice(notice));
+        Ok(())
+    }
+
+    /// 安全锁锁定期间代替滤波器校正：只按可信漂移率推算的累积偏移校正时钟，测量值仅用于统计。
+    fn hold_over(
+        &mut self,
+        server: &str,
+        measured_offset_secs: f64,
+        measured_delay_secs: f64,
+    ) -> SyncReport {
+        let (correction_secs, drift_ppm) = self
+            .drift_latch
+            .as_mut()
+            .and_then(DriftSafetyLatch::holdover_correction)
+            .unwrap_or((0.0, 0.0));
+        self.clock
+            .lock()
+            .unwrap()
+            .apply_offset(secs_to_chrono(correction_secs));
+        self.stats.record_sample(
+            server,
+            SamplePoint {
+                offset_secs: measured_offset_secs,
+                delay_secs: measured_delay_secs,
+            },
+        );
+        SyncReport {
+            measured_offset_secs,
+            measured_delay_secs,
+            smoothed_offset_secs: correction_secs,
+            drift_ppm,
+            process_noise_q: self.kalman_filter.get_process_noise_q(),
+            offset_sigma_secs: self.kalman_filter.get_offset_uncertainty(),
+            effective_interval_secs: self
+                .stats
+                .effective_interval()
+                .map(|interval| interval.as_secs_f64()),
+        }
+    }
+
+    /// 读取与本次漂移样本配对的温度；读取失败不影响同步，只在连续失败开始时提示一次。
+    fn read_temperature(&mut self, server: &str) -> io::Result<Option<f64>> {
+        let Some(source) = &self.temperature_source else {
+            return Ok(None);
+        };
+        match temperature::read_celsius(source) {
+            Ok(celsius) => {
+                self.temperature_failing = false;
+                Ok(Some(celsius))
+            }
+            Err(e) => {
+                if !self.temperature_failing {
+                    self.temperature_failing = true;
+                    let notice = format!("读取温度失败: {e}");
+                    self.log(&LogRecord::Notice {
+                        server,
+                        message: &notice,
+                    })?;
+                    self.pending_events.push_back(SyncEvent::Notice(notice));
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    /// 系统时钟与单调时钟的经过时间不一致时，丢弃本次样本并重置滤波器的时间基准，返回原因。
+    fn check_clock_step(&mut self) -> Option<String> {
+        let discrepancy = self
+            .clock_step_guard
+            .as_mut()?
+            .check(Instant::now(), Utc::now())?;
+        self.kalman_filter.reset_timestamp();
+        self.last_filter_clock = None;
+        Some(format!(
+            "系统时钟与单调时钟的经过时间相差 {:.3}ms，疑似外部调整时钟或系统挂起，\
+             已丢弃样本并重置滤波步长",
+            discrepancy * 1000.0
+        ))
+    }
+
+    /// 单个服务器的偏移突然跳变而其他服务器保持稳定时，归因于该服务器自身被步进并返回排除原因。
+    fn check_server_step(&mut self, server: &str, offset_secs: f64) -> Option<String> {
+        let step = self.server_step.as_mut()?.check(server, offset_secs)?;
+        if step.first {
+            self.stats.record_server_step(server);
+        }
+        Some(format!(
+            "偏移 {:.2}ms 而其他 {} 个服务器保持稳定，疑似服务器自身时钟跳变，已排除该样本",
+            step.offset_secs * 1000.0,
+            step.stable_servers
+        ))
+    }
+
+    /// 检测到路径切换时先软重置滤波器，再用本次样本更新。
+    fn check_path_change(&mut self, server: &str, delay_secs: f64) -> io::Result<()> {
+        let Some(detector) = &mut self.path_change else {
+            return Ok(());
+        };
+        let Some((old_min, new_min)) = detector.record(server, delay_secs) else {
+            return Ok(());
+        };
+        self.kalman_filter
+            .inflate_covariance(detector.covariance_inflation());
+        self.control.network_changed.store(true, Ordering::SeqCst);
+        let notice = format!(
+            "最小延迟由 {:.2}ms 变为 {:.2}ms，疑似网络路径切换，已软重置滤波器",
+            old_min * 1000.0,
+            new_min * 1000.0
+        );
+        self.log(&LogRecord::Notice {
+            server,
+            message: &notice,
+        })?;
+        self.pending_events
+            .push_back(SyncEvent::Notice(format!("{server}: {notice}")));
+        Ok(())
+    }
+
+    /// 以相同测量更新参考滤波器，分歧刚超过阈值时发出提示，并按配置重新同步。
+    fn check_filter_divergence(
+        &mut self,
+        server: &str,
+        measurement: f64,
+        measurement_noise_r: f64,
+        dt: f64,
+    ) -> io::Result<()> {
+        let Some(cross_check) = &mut self.cross_check else {
+            return Ok(());
+        };
+        let Some(divergence) =
+            cross_check.update(&self.kalman_filter, measurement, measurement_noise_r, dt)
+        else {
+            return Ok(());
+        };
+        let resync = cross_check.resync_on_anomaly();
+        if resync {
+            cross_check.resync(&mut self.kalman_filter);
+        }
+        let notice = format!(
+            "主滤波器与参考滤波器的偏移估计相差 {:.2}ms，疑似阶跃变化或异常数据{}",
+            divergence * 1000.0,
+            if resync { "，已重新同步" } else { "" }
+        );
+        self.log(&LogRecord::Notice {
+            server,
+            message: &notice,
+        })?;
+        self.pending_events
+            .push_back(SyncEvent::Notice(format!("{server}: {notice}")));
+        Ok(())
+    }
+
+    /// 漂移率连续 `hw_drift_warn_samples` 次超过 `hw_drift_warn_ppm` 时发出一次告警，
+    /// 回落到阈值以下后重新计数。
+    fn check_drift_health(&mut self, server: &str) -> io::Result<()> {
+        let drift_ppm = self.kalman_filter.get_drift_ppm();
+        if drift_ppm.abs() <= self.kalman_config.hw_drift_warn_ppm() {
+            self.drift_exceed_count = 0;
+            return Ok(());
+        }
+        self.drift_exceed_count += 1;
+        if self.drift_exceed_count != self.kalman_config.hw_drift_warn_samples() {
+            return Ok(());
+        }
+        let warning = format!(
+            "漂移率 {drift_ppm:.2} ppm 已连续 {} 次超过 {} ppm，请检查晶振或温度",
+            self.drift_exceed_count,
+            self.kalman_config.hw_drift_warn_ppm()
+        );
+        self.log(&LogRecord::Notice {
+            server,
+            message: &warning,
+        })?;
+        self.pending_events.push_back(SyncEvent::Notice(warning));
+        Ok(())
+    }
+
+    /// 滤波器因 NIS 平均达到上限而重置自适应状态时提示，说明测量与预测持续不符（如网络环境恶化）。
+    fn check_nis_runaway(&mut self, server: &str) -> io::Result<()> {
+        let resets = self.kalman_filter.runaway_resets();
+        if resets == self.nis_runaway_resets {
+            return Ok(());
+        }
+        self.nis_runaway_resets = resets;
+        let notice = format!(
+            "NIS 平均达到上限 {}，过程噪声自适应已失控，恢复为初始过程噪声 {:.1e}（累计 {resets} 次）",
+            self.kalman_config.max_nis_ema(),
+            self.kalman_filter.get_process_noise_q()
+        );
+        self.log(&LogRecord::Notice {
+            server,
+            message: &notice,
+        })?;
+        self.pending_events.push_back(SyncEvent::Notice(notice));
+        Ok(())
+    }
+
+    /// 滤波器以离群门限拒绝了本次测量时提示：时钟只按预测推进，测量未参与校正。
+    fn check_outlier_rejection(
+        &mut self,
+        server: &str,
+        measured_offset_secs: f64,
+    ) -> io::Result<()> {
+        let rejected = self.kalman_filter.rejected_outliers();
+        if rejected == self.rejected_outliers {
+            return Ok(());
+        }
+        self.rejected_outliers = rejected;
+        let notice = format!(
+            "测量偏移 {:.3}ms 的 NIS 超过离群门限，已跳过校正（累计 {rejected} 次）",
+            measured_offset_secs * 1000.0
+        );
+        self.log(&LogRecord::Notice {
+            server,
+            message: &notice,
+        })?;
+        self.pending_events.push_back(SyncEvent::Notice(notice));
+        Ok(())
+    }
+
+    fn handle_sync_message(&mut self, message: ntp::SyncMessage) -> io::Result<SyncEvent> {
+        match message {
+            ntp::SyncMessage::Discovered(ranking) => {
+                self.stats.record_ranking(&ranking);
+                let summary = ranking
+                    .iter()
+                    .map(|entry| {
+                        entry.delay_secs.map_or_else(
+                            || format!("{} (无响应)", entry.server),
+                            |delay| format!("{} ({:.1}ms)", entry.server, delay * 1000.0),
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let notice = format!("服务器延迟排名: {summary}");
+                self.log(&LogRecord::Notice {
+                    server: "discovery",
+                    message: &notice,
+                })?;
+                Ok(SyncEvent::Notice(notice))
+            }
+            ntp::SyncMessage::Syncing(server) => {
+                self.stats.record_attempt(&server);
+                Ok(SyncEvent::Syncing(server))
+            }
+            ntp::SyncMessage::Notice(server, notice) => {
+                self.log(&LogRecord::Notice {
+                    server: &server,
+                    message: &notice,
+                })?;
+                Ok(SyncEvent::Notice(format!("{server}: {notice}")))
+            }
+            ntp::SyncMessage::Failed(server, error) => {
+                self.stats.record_failure(&server);
+                self.log(&LogRecord::Failure {
+                    server: &server,
+                    error: &error,
+                })?;
+                Ok(SyncEvent::Failed(error))
+            }
+            ntp::SyncMessage::Success(sample) => self.handle_success(sample),
+        }
+    }
+
+    /// 过滤过期、时钟跳变与服务器跳变的样本，按配额送入滤波器。
+    fn handle_success(&mut self, sample: ntp::NtpSample) -> io::Result<SyncEvent> {
+        let max_sample_age = self.kalman_config.max_sample_age();
+        let ntp::NtpSample {
+            server,
+            offset: measured_offset,
+            delay: measured_delay,
+            measured_at,
+            stratum,
+            ..
+        } = sample;
+        let sample_age = measured_at.elapsed();
+        if sample_age > max_sample_age {
+            let error = format!("样本已过期 ({}ms)，已丢弃", sample_age.as_millis());
+            self.stats.record_failure(&server);
+            self.log(&LogRecord::Failure {
+                server: &server,
+                error: &error,
+            })?;
+            return Ok(SyncEvent::Failed(error));
+        }
+        if let Some(error) = self.check_clock_step() {
+            self.stats.record_failure(&server);
+            self.log(&LogRecord::Failure {
+                server: &server,
+                error: &error,
+            })?;
+            return Ok(SyncEvent::Failed(error));
+        }
+        let raw_offset_secs = duration_to_secs(measured_offset, "measured_offset")?;
+        let measured_offset_secs = match self.correction_reference {
+            CorrectionReference::Measurement => raw_offset_secs,
+            CorrectionReference::Application => (self.kalman_filter.get_drift_ppm() / 1_000_000.0)
+                .mul_add(sample_age.as_secs_f64(), raw_offset_secs),
+        };
+        let measured_delay_secs = duration_to_secs(measured_delay, "measured_delay")?;
+        self.last_raw = Some(RawMeasurement {
+            server: server.clone(),
+            offset_secs: raw_offset_secs,
+            delay_secs: measured_delay_secs,
+            measured_at,
+        });
+        if let Some(error) = self.check_server_step(&server, measured_offset_secs) {
+            self.stats.record_failure(&server);
+            self.log(&LogRecord::Failure {
+                server: &server,
+                error: &error,
+            })?;
+            return Ok(SyncEvent::Failed(error));
+        }
+        self.check_path_change(&server, measured_delay_secs)?;
+        let update = PendingUpdate {
+            offset_secs: measured_offset_secs,
+            delay_secs: measured_delay_secs,
+            measurement_noise_r: measured_delay_secs * self.kalman_config.delay_to_r_factor,
+            measured_at,
+            merged: 1,
+        };
+        let Some(update) = self
+            .update_quota
+            .as_mut()
+            .map_or(Some(update), |quota| quota.admit(update))
+        else {
+            let notice = "已达到滤波器每分钟更新配额，样本将合并到下一次更新";
+            self.log(&LogRecord::Notice {
+                server: &server,
+                message: notice,
+            })?;
+            return Ok(SyncEvent::Notice(format!("{server}: {notice}")));
+        };
+        let report = self.fuse_measurement(
+            &server,
+            update.offset_secs,
+            update.delay_secs,
+            update.measurement_noise_r,
+            update.measured_at,
+        )?;
+        self.last_stratum = stratum;
+        Ok(SyncEvent::Updated(report))
+    }
+}
+/// 以纳秒精度将 `chrono::Duration` 转换为秒，整秒部分超出 f64 可精确表示的整数范围时报错。
+fn duration_to_secs(duration: chrono::Duration, what: &'static str) -> io::Result<f64> {
+    const MAX_SAFE_INTEGER_IN_F64: i64 = 9_007_199_254_740_992; // 2^53
+    let whole_secs = duration.num_seconds();
+    if whole_secs.unsigned_abs() > MAX_SAFE_INTEGER_IN_F64.unsigned_abs() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{what} 超出 f64 可精确表示的整数范围"),
+        ));
+    }
+    #[allow(clippy::cast_precision_loss)]
+    let whole_secs = whole_secs as f64;
+    Ok(whole_secs + f64::from(duration.subsec_nanos()) / 1_000_000_000.0)
+}
+fn new_filter(kalman: &KalmanConfig) -> KalmanFilter {
+    KalmanFilter::new(
+        kalman.model,
+        0.0,
+        kalman.initial_uncertainty,
+        kalman.initial_process_noise_q,
+        kalman.adaptation_rate_eta,
+        kalman.nis_ema_alpha,
+        kalman.min_predict_dt().as_secs_f64(),
+    )
+    .with_adaptive_q(kalman.adaptive_q())
+    .with_process_noise_range(kalman.min_process_noise_q, kalman.max_process_noise_q)
+    .with_initial_nis_ema(kalman.initial_nis_ema())
+    .with_max_nis_ema(kalman.max_nis_ema())
+    .with_max_drift_ppm(kalman.max_applied_drift_ppm)
+    .with_outlier_nis_threshold(kalman.outlier_nis_threshold)
+    .with_warmup_decay(kalman.warmup_decay)
+}
+/// 从启动状态恢复上次学到的滤波器状态，返回需要提示的结果；没有保存的状态时返回 `None`。
+/// 状态文件损坏、版本或模型不符时保留默认初值。
+fn restore_filter(store: &StartupStateStore, filter: &mut KalmanFilter) -> Option<String> {
+    match store.saved_filter() {
+        Ok(None) => None,
+        Ok(Some(state)) if filter.restore_state(&state) => Some(format!(
+            "已恢复保存的滤波器状态，漂移率 {:.3} ppm",
+            filter.get_drift_ppm()
+        )),
+        Ok(Some(_)) => Some("保存的滤波器状态与当前模型不符或数值无效，已使用默认初值".to_owned()),
+        Err(reason) => Some(format!("{reason}，已使用默认滤波器初值")),
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn synchronizer() -> Synchronizer {
+        let config = AppConfig::builtin().unwrap();
+        let clock = ProgramClock::new(config.clock.initial_utc().unwrap());
+        Synchronizer::new(&config, clock).unwrap()
+    }
+
+    #[test]
+    fn injected_measurement_moves_filter_and_clock() {
+        let mut synchronizer = synchronizer();
+        let before = synchronizer.try_now().unwrap();
+        let report = synchronizer.inject_measurement(0.25, 1e-6).unwrap();
+        let after = synchronizer.try_now().unwrap();
+        assert!(synchronizer.is_synchronized());
+        assert!((report.measured_offset_secs - 0.25).abs() < f64::EPSILON);
+        assert!(report.smoothed_offset_secs > 0.2 && report.smoothed_offset_secs <= 0.25);
+        let advanced_secs = (after - before).as_seconds_f64();
+        assert!((advanced_secs - report.smoothed_offset_secs).abs() < 0.05);
+        assert!(synchronizer.stats().servers().contains_key(EXTERNAL_SOURCE));
+    }
+
+    #[test]
+    fn program_clock_dt_excludes_the_filter_corrections() {
+        let mut config = AppConfig::builtin().unwrap();
+        config.kalman.dt_source = FilterDtSource::ProgramClock;
+        let clock = ProgramClock::new(config.clock.initial_utc().unwrap());
+        let mut synchronizer = Synchronizer::new(&config, clock).unwrap();
+        synchronizer.filter_dt(EXTERNAL_SOURCE).unwrap();
+        // 一次数秒的校正不应进入下一次的步长，步长只反映期间的实际经过时间。
+        for offset in [chrono::Duration::seconds(5), -chrono::Duration::seconds(8)] {
+            synchronizer
+                .clock()
+                .lock()
+                .unwrap()
+                .apply_offset_stepped(offset);
+            let dt = synchronizer.filter_dt(EXTERNAL_SOURCE).unwrap();
+            assert!((0.0..0.5).contains(&dt), "dt = {dt}");
+        }
+        assert!(synchronizer.pending_events.is_empty());
+    }
+
+    #[test]
+    fn injected_measurement_rejects_invalid_input() {
+        let mut synchronizer = synchronizer();
+        for (offset, r) in [(f64::NAN, 1e-6), (0.1, -1.0), (f64::INFINITY, 1e-6)] {
+            let error = synchronizer.inject_measurement(offset, r).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        }
+        assert!(!synchronizer.is_synchronized());
+    }
+}
@@ -0,0 +1,43 @@
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    process::Command,
+};
+
+use crate::config::TemperatureSourceConfig;
+/// 读取一次温度（摄氏度）：取文件内容或命令输出中的第一个数值，再乘以配置的系数。
+pub fn read_celsius(config: &TemperatureSourceConfig) -> io::Result<f64> {
+    let text = match (&config.path, config.command.as_deref()) {
+        (Some(path), _) => fs::read_to_string(path)?,
+        (None, Some([program, args @ ..])) => run_command(program, args)?,
+        (None, _) => {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "temperature_source 未配置 path 或 command",
+            ));
+        }
+    };
+    let value = text
+        .split_whitespace()
+        .next()
+        .and_then(|token| token.parse::<f64>().ok())
+        .filter(|value| value.is_finite())
+        .ok_or_else(|| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!("无法从温度读数中解析数值: {}", text.trim()),
+            )
+        })?;
+    Ok(value * config.scale)
+}
+fn run_command(program: &str, args: &[String]) -> io::Result<String> {
+    let output = Command::new(program).args(args).output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "{program} 执行失败: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    String::from_utf8(output.stdout)
+        .map_err(|_| io::Error::new(ErrorKind::InvalidData, format!("{program} 输出不是 UTF-8")))
+}
@@ -0,0 +1,164 @@
+use std::{
+    fs::{File, OpenOptions},
+    io,
+    os::unix::fs::FileExt,
+};
+
+use chrono::{DateTime, Utc};
+pub const TIME_FILE_SIZE: usize = 64;
+const MAGIC: u32 = 0x4E52_484B;
+const LAYOUT_VERSION: u32 = 1;
+const SEQ_OFFSET: u64 = 8;
+const PAYLOAD_OFFSET: u64 = 16;
+const FLAG_SYNCHRONIZED: u32 = 1;
+const MAX_READ_ATTEMPTS: usize = 100;
+/// 共享文件中的一份完整快照。
+#[derive(Copy, Clone, Debug)]
+pub struct TimeFileSnapshot {
+    pub time: DateTime<Utc>,
+    pub synchronized: bool,
+    pub uncertainty_secs: f64,
+    pub drift_ppm: f64,
+}
+/// 写入方：按界面刷新频率更新共享文件，布局见 README 的 “共享时间文件” 一节。
+/// 写入经由页缓存，映射同一文件的读取方无需系统调用即可看到。
+pub struct TimeFileWriter {
+    file: File,
+    seq: u64,
+}
+impl TimeFileWriter {
+    pub fn create(path: &str) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.set_len(TIME_FILE_SIZE as u64)?;
+        let mut header = [0u8; 16];
+        header[..4].copy_from_slice(&MAGIC.to_le_bytes());
+        header[4..8].copy_from_slice(&LAYOUT_VERSION.to_le_bytes());
+        file.write_all_at(&header, 0)?;
+        Ok(Self { file, seq: 0 })
+    }
+
+    pub fn publish(&mut self, snapshot: &TimeFileSnapshot) -> io::Result<()> {
+        let mut payload = [0u8; 32];
+        payload[..8].copy_from_slice(&snapshot.time.timestamp().to_le_bytes());
+        payload[8..12].copy_from_slice(&snapshot.time.timestamp_subsec_nanos().to_le_bytes());
+        let flags = if snapshot.synchronized {
+            FLAG_SYNCHRONIZED
+        } else {
+            0
+        };
+        payload[12..16].copy_from_slice(&flags.to_le_bytes());
+        payload[16..24].copy_from_slice(&snapshot.uncertainty_secs.to_le_bytes());
+        payload[24..32].copy_from_slice(&snapshot.drift_ppm.to_le_bytes());
+        self.seq = self.seq.wrapping_add(1);
+        self.file
+            .write_all_at(&self.seq.to_le_bytes(), SEQ_OFFSET)?;
+        self.file.write_all_at(&payload, PAYLOAD_OFFSET)?;
+        self.seq = self.seq.wrapping_add(1);
+        self.file.write_all_at(&self.seq.to_le_bytes(), SEQ_OFFSET)
+    }
+}
+/// 读取方辅助函数：按顺序锁协议从映射（或读入）的 64 字节中取出一份一致的快照。
+/// `read` 每次调用都应重新读取共享内存的当前内容；多次重试仍不一致时返回错误。
+pub fn read_consistent(
+    mut read: impl FnMut() -> [u8; TIME_FILE_SIZE],
+) -> io::Result<TimeFileSnapshot> {
+    for _ in 0..MAX_READ_ATTEMPTS {
+        let before = read();
+        if u32::from_le_bytes(before[..4].try_into().unwrap()) != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "共享时间文件的魔数不匹配",
+            ));
+        }
+        let seq = u64::from_le_bytes(before[8..16].try_into().unwrap());
+        if !seq.is_multiple_of(2) {
+            continue;
+        }
+        let after = read();
+        if after[8..16] != before[8..16] {
+            continue;
+        }
+        let secs = i64::from_le_bytes(before[16..24].try_into().unwrap());
+        let nanos = u32::from_le_bytes(before[24..28].try_into().unwrap());
+        let flags = u32::from_le_bytes(before[28..32].try_into().unwrap());
+        let time = DateTime::from_timestamp(secs, nanos).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "共享时间文件中的时间无效")
+        })?;
+        return Ok(TimeFileSnapshot {
+            time,
+            synchronized: flags & FLAG_SYNCHRONIZED != 0,
+            uncertainty_secs: f64::from_le_bytes(before[32..40].try_into().unwrap()),
+            drift_ppm: f64::from_le_bytes(before[40..48].try_into().unwrap()),
+        });
+    }
+    Err(io::Error::new(
+        io::ErrorKind::WouldBlock,
+        "共享时间文件持续处于写入状态",
+    ))
+}
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("khronos-{}-{name}", std::process::id()))
+    }
+
+    fn read_file(path: &PathBuf) -> [u8; TIME_FILE_SIZE] {
+        fs::read(path).unwrap().try_into().unwrap()
+    }
+
+    #[test]
+    fn published_snapshot_reads_back() {
+        let path = temp_path("time-file-roundtrip");
+        let mut writer = TimeFileWriter::create(path.to_str().unwrap()).unwrap();
+        let snapshot = TimeFileSnapshot {
+            time: DateTime::from_timestamp(1_700_000_000, 123_456_789).unwrap(),
+            synchronized: true,
+            uncertainty_secs: 0.000_25,
+            drift_ppm: -3.5,
+        };
+        writer.publish(&snapshot).unwrap();
+        let read = read_consistent(|| read_file(&path)).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(read.time, snapshot.time);
+        assert!(read.synchronized);
+        assert!((read.uncertainty_secs - snapshot.uncertainty_secs).abs() < f64::EPSILON);
+        assert!((read.drift_ppm - snapshot.drift_ppm).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn write_in_progress_is_retried_then_reported() {
+        let mut bytes = [0u8; TIME_FILE_SIZE];
+        bytes[..4].copy_from_slice(&MAGIC.to_le_bytes());
+        bytes[8..16].copy_from_slice(&1u64.to_le_bytes());
+        let error = read_consistent(|| bytes).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::WouldBlock);
+        // 第一次读取时写入方正在写（序号为奇数），之后写入完成，应读到完成后的快照。
+        let mut reads = 0;
+        let completed = read_consistent(|| {
+            reads += 1;
+            let mut bytes = bytes;
+            if reads > 1 {
+                bytes[8..16].copy_from_slice(&2u64.to_le_bytes());
+            }
+            bytes
+        })
+        .unwrap();
+        assert_eq!(completed.time, DateTime::UNIX_EPOCH);
+        assert_eq!(reads, 3);
+    }
+
+    #[test]
+    fn wrong_magic_is_rejected() {
+        let error = read_consistent(|| [0u8; TIME_FILE_SIZE]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
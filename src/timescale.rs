@@ -0,0 +1,117 @@
+use std::io;
+
+use chrono::{DateTime, Local, NaiveDate, NaiveTime, Utc};
+
+use crate::config::{ClockConfig, LeapSecondConfig, Timescale};
+/// 内置的 TAI−UTC 表（IERS Bulletin C），每项自对应日期 UTC 零时起生效。
+const BUILTIN_LEAP_SECONDS: [(&str, i64); 28] = [
+    ("1972-01-01", 10),
+    ("1972-07-01", 11),
+    ("1973-01-01", 12),
+    ("1974-01-01", 13),
+    ("1975-01-01", 14),
+    ("1976-01-01", 15),
+    ("1977-01-01", 16),
+    ("1978-01-01", 17),
+    ("1979-01-01", 18),
+    ("1980-01-01", 19),
+    ("1981-07-01", 20),
+    ("1982-07-01", 21),
+    ("1983-07-01", 22),
+    ("1985-07-01", 23),
+    ("1988-01-01", 24),
+    ("1990-01-01", 25),
+    ("1991-01-01", 26),
+    ("1992-07-01", 27),
+    ("1993-07-01", 28),
+    ("1994-07-01", 29),
+    ("1996-01-01", 30),
+    ("1997-07-01", 31),
+    ("1999-01-01", 32),
+    ("2006-01-01", 33),
+    ("2009-01-01", 34),
+    ("2012-07-01", 35),
+    ("2015-07-01", 36),
+    ("2017-01-01", 37),
+];
+/// 按生效时间升序排列的 TAI−UTC 表。
+pub struct LeapSecondTable {
+    entries: Vec<(DateTime<Utc>, i64)>,
+}
+impl LeapSecondTable {
+    /// 由配置构建闰秒表，配置为空时使用内置表。生效日期必须严格递增。
+    pub fn from_config(configured: &[LeapSecondConfig]) -> io::Result<Self> {
+        let entries = if configured.is_empty() {
+            BUILTIN_LEAP_SECONDS
+                .iter()
+                .map(|&(date, tai_minus_utc)| parse_entry(date, tai_minus_utc))
+                .collect::<io::Result<Vec<_>>>()?
+        } else {
+            configured
+                .iter()
+                .map(|entry| parse_entry(&entry.effective_date, entry.tai_minus_utc))
+                .collect::<io::Result<Vec<_>>>()?
+        };
+        if entries.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "leap_seconds 的 effective_date 必须严格递增",
+            ));
+        }
+        Ok(Self { entries })
+    }
+
+    /// `utc` 时刻的 TAI−UTC（秒）；早于表中第一项时返回 0。
+    pub fn tai_minus_utc(&self, utc: DateTime<Utc>) -> i64 {
+        self.entries
+            .iter()
+            .rev()
+            .find(|(effective, _)| *effective <= utc)
+            .map_or(0, |&(_, tai_minus_utc)| tai_minus_utc)
+    }
+}
+fn parse_entry(date: &str, tai_minus_utc: i64) -> io::Result<(DateTime<Utc>, i64)> {
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("leap_seconds 中的生效日期 {date} 无效: {e}"),
+        )
+    })?;
+    Ok((date.and_time(NaiveTime::MIN).and_utc(), tai_minus_utc))
+}
+/// 在显示与输出边界把内部的 UTC 时间换算为配置的时间尺度；时钟内部始终使用 UTC。
+pub struct TimeDisplay {
+    timescale: Timescale,
+    leap_seconds: LeapSecondTable,
+}
+impl TimeDisplay {
+    pub fn new(config: &ClockConfig) -> io::Result<Self> {
+        Ok(Self {
+            timescale: config.timescale,
+            leap_seconds: LeapSecondTable::from_config(&config.leap_seconds)?,
+        })
+    }
+
+    /// TAI 时间以 UTC 时区的 `DateTime` 表示，其读数比同一时刻的 UTC 快当前的闰秒数。
+    pub fn to_tai(&self, utc: DateTime<Utc>) -> DateTime<Utc> {
+        utc + chrono::Duration::seconds(self.leap_seconds.tai_minus_utc(utc))
+    }
+
+    /// 按 strftime 格式输出：UTC 尺度下按 `local` 选择本地或 UTC 时区，TAI 尺度下附加 “TAI” 后缀。
+    pub fn format(&self, utc: DateTime<Utc>, format: &str, local: bool) -> String {
+        match (self.timescale, local) {
+            (Timescale::Utc, true) => utc.with_timezone(&Local).format(format).to_string(),
+            (Timescale::Utc, false) => utc.format(format).to_string(),
+            (Timescale::Tai, _) => format!("{} TAI", self.to_tai(utc).format(format)),
+        }
+    }
+
+    /// 未指定格式时的默认输出：UTC 尺度为 RFC3339，TAI 尺度不带时区偏移以免与 UTC 混淆。
+    pub fn rfc3339(&self, utc: DateTime<Utc>, local: bool) -> String {
+        match (self.timescale, local) {
+            (Timescale::Utc, true) => utc.with_timezone(&Local).to_rfc3339(),
+            (Timescale::Utc, false) => utc.to_rfc3339(),
+            (Timescale::Tai, _) => self.format(utc, "%Y-%m-%dT%H:%M:%S%.f", false),
+        }
+    }
+}
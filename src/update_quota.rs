@@ -0,0 +1,62 @@
+use std::{collections::VecDeque, time::Duration};
+
+use quanta::Instant;
+const QUOTA_WINDOW: Duration = Duration::from_mins(1);
+/// 等待送入滤波器的测量；超出配额时多个测量合并为一个。
+#[derive(Copy, Clone)]
+pub struct PendingUpdate {
+    pub offset_secs: f64,
+    pub delay_secs: f64,
+    pub measurement_noise_r: f64,
+    pub measured_at: Instant,
+    pub merged: usize,
+}
+/// 限制滤波器每分钟整合的样本数，使查询速率与滤波更新速率解耦。
+/// 短时间内的密集样本彼此相关，逐个送入会违背滤波器的独立性假设而使其过度自信；
+/// 超出配额的样本先累积，下一次允许更新时以偏移平均值合并送入。
+/// 合并后的测量噪声取其中最小者而不按样本数缩小，因为相关的样本并不能降低方差。
+pub struct UpdateQuota {
+    max_per_minute: usize,
+    recent: VecDeque<Instant>,
+    pending: Option<PendingUpdate>,
+}
+impl UpdateQuota {
+    pub const fn new(max_per_minute: usize) -> Self {
+        Self {
+            max_per_minute,
+            recent: VecDeque::new(),
+            pending: None,
+        }
+    }
+
+    /// 加入一个测量；配额允许时返回应送入滤波器的（可能已合并的）测量，否则暂存并返回 `None`。
+    pub fn admit(&mut self, update: PendingUpdate) -> Option<PendingUpdate> {
+        let merged = self.pending.take().map_or(update, |pending| {
+            let count = pending.merged + update.merged;
+            #[allow(clippy::cast_precision_loss)]
+            let weight = pending.merged as f64 / count as f64;
+            PendingUpdate {
+                offset_secs: (pending.offset_secs - update.offset_secs)
+                    .mul_add(weight, update.offset_secs),
+                delay_secs: pending.delay_secs.min(update.delay_secs),
+                measurement_noise_r: pending.measurement_noise_r.min(update.measurement_noise_r),
+                measured_at: pending.measured_at.max(update.measured_at),
+                merged: count,
+            }
+        });
+        let now = Instant::now();
+        while self
+            .recent
+            .front()
+            .is_some_and(|&at| now.saturating_duration_since(at) > QUOTA_WINDOW)
+        {
+            self.recent.pop_front();
+        }
+        if self.recent.len() >= self.max_per_minute {
+            self.pending = Some(merged);
+            return None;
+        }
+        self.recent.push_back(now);
+        Some(merged)
+    }
+}
@@ -0,0 +1,32 @@
+use std::fmt;
+/// 构建信息：包版本、构建时的 git 提交与编译启用的特性。
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_hash: &'static str,
+    pub features: Vec<&'static str>,
+}
+impl fmt::Display for BuildInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let features = if self.features.is_empty() {
+            "无".to_owned()
+        } else {
+            self.features.join(", ")
+        };
+        write!(
+            f,
+            "khronos {} (git {})\n已启用特性: {features}",
+            self.version, self.git_hash
+        )
+    }
+}
+/// 当前二进制或库的构建信息，`git_hash` 在构建环境没有 git 时为 `unknown`。
+pub fn build_info() -> BuildInfo {
+    BuildInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        git_hash: env!("KHRONOS_GIT_HASH"),
+        features: env!("KHRONOS_FEATURES")
+            .split(',')
+            .filter(|feature| !feature.is_empty())
+            .collect(),
+    }
+}
@@ -0,0 +1,77 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use quanta::Instant;
+/// 同步线程持有的心跳句柄：每个周期递增一次计数；被看门狗淘汰后线程应在恢复运行时尽快退出。
+#[derive(Clone, Default)]
+pub struct Heartbeat {
+    beats: Arc<AtomicU64>,
+    retired: Arc<AtomicBool>,
+}
+impl Heartbeat {
+    pub fn beat(&self) {
+        self.beats.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn is_retired(&self) -> bool {
+        self.retired.load(Ordering::SeqCst)
+    }
+}
+/// 监视同步线程的心跳。线程崩溃之外，还可能仍然存活却卡住（例如超时未生效的阻塞系统调用），
+/// 此时心跳停止增长，超过阈值即判定为停滞。
+pub struct SyncWatchdog {
+    timeout: Duration,
+    heartbeat: Heartbeat,
+    last_beats: u64,
+    last_change: Instant,
+    restarts: usize,
+}
+impl SyncWatchdog {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            heartbeat: Heartbeat::default(),
+            last_beats: 0,
+            last_change: Instant::now(),
+            restarts: 0,
+        }
+    }
+
+    /// 交给当前同步线程的心跳句柄。
+    pub fn heartbeat(&self) -> Heartbeat {
+        self.heartbeat.clone()
+    }
+
+    /// 心跳停止增长超过阈值时返回已停滞的时长。
+    pub fn check(&mut self) -> Option<Duration> {
+        let beats = self.heartbeat.beats.load(Ordering::Relaxed);
+        if beats != self.last_beats {
+            self.last_beats = beats;
+            self.last_change = Instant::now();
+            return None;
+        }
+        let stalled = self.last_change.elapsed();
+        (stalled >= self.timeout).then_some(stalled)
+    }
+
+    /// 淘汰停滞线程的心跳句柄并换上新句柄，返回交给重建线程的句柄。
+    /// 卡住的线程无法被强行终止，它恢复运行后发现句柄已被淘汰便会自行退出。
+    pub fn renew(&mut self) -> Heartbeat {
+        self.heartbeat.retired.store(true, Ordering::SeqCst);
+        self.heartbeat = Heartbeat::default();
+        self.last_beats = 0;
+        self.last_change = Instant::now();
+        self.restarts += 1;
+        self.heartbeat.clone()
+    }
+
+    /// 因停滞而重建同步线程的累计次数。
+    pub const fn restarts(&self) -> usize {
+        self.restarts
+    }
+}